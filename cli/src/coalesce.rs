@@ -0,0 +1,86 @@
+use std::{mem, time::{Duration, Instant}};
+
+/// Batches streamed chunks so the terminal is written and flushed at most
+/// once per `interval`. The first chunk goes out right away; a zero interval
+/// passes every chunk straight through.
+#[derive(Debug)]
+pub struct Coalescer {
+    interval: Duration,
+    buffer: String,
+    last_flush: Option<Instant>,
+}
+
+impl Coalescer {
+    pub fn new(interval: Duration) -> Self {
+        Coalescer {
+            interval,
+            buffer: String::new(),
+            last_flush: None,
+        }
+    }
+
+    /// Buffers `chunk`, returning everything buffered so far once the interval has passed.
+    pub fn push(&mut self, chunk: &str, now: Instant) -> Option<String> {
+        self.buffer.push_str(chunk);
+
+        let due = self.last_flush.is_none_or(|last_flush| now.duration_since(last_flush) >= self.interval);
+        if due {
+            self.take(now)
+        } else {
+            None
+        }
+    }
+
+    /// Returns whatever is buffered, regardless of the interval.
+    pub fn take(&mut self, now: Instant) -> Option<String> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+
+        self.last_flush = Some(now);
+        Some(mem::take(&mut self.buffer))
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    /// When the buffered text is due to be written.
+    pub fn deadline(&self) -> Instant {
+        self.last_flush.map_or_else(Instant::now, |last_flush| last_flush + self.interval)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zero_interval_passes_through() {
+        let start = Instant::now();
+        let mut coalescer = Coalescer::new(Duration::ZERO);
+
+        assert_eq!(coalescer.push("Hel", start).as_deref(), Some("Hel"));
+        assert_eq!(coalescer.push("lo", start).as_deref(), Some("lo"));
+        assert!(!coalescer.has_pending());
+    }
+
+    #[test]
+    fn test_chunks_coalesce_within_interval() {
+        let start = Instant::now();
+        let mut coalescer = Coalescer::new(Duration::from_millis(50));
+
+        assert_eq!(coalescer.push("He", start).as_deref(), Some("He"));
+        assert_eq!(coalescer.push("l", start + Duration::from_millis(10)), None);
+        assert_eq!(coalescer.push("lo, ", start + Duration::from_millis(30)), None);
+        assert!(coalescer.has_pending());
+        assert_eq!(coalescer.deadline(), start + Duration::from_millis(50));
+
+        assert_eq!(coalescer.push("wor", start + Duration::from_millis(60)).as_deref(), Some("llo, wor"));
+        assert_eq!(coalescer.push("ld", start + Duration::from_millis(70)), None);
+        assert_eq!(coalescer.deadline(), start + Duration::from_millis(110));
+
+        assert_eq!(coalescer.take(start + Duration::from_millis(80)).as_deref(), Some("ld"));
+        assert_eq!(coalescer.take(start + Duration::from_millis(90)), None);
+    }
+}
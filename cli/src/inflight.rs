@@ -0,0 +1,88 @@
+use codr::CancellationToken;
+
+/// What to do with a prompt submitted while a response may be generating.
+#[derive(Debug, PartialEq)]
+pub enum Submission {
+    /// Nothing is generating; answer the prompt.
+    Run(String),
+    /// The response in flight was cancelled; answer this prompt instead.
+    Replace(String),
+    /// The prompt repeats the one being answered, or is empty.
+    Ignore,
+}
+
+/// Tracks the response being generated, so a new prompt cancels and replaces it
+/// instead of interleaving with it, while an accidental resubmission is ignored.
+#[derive(Debug, Default)]
+pub struct InFlight {
+    current: Option<(String, CancellationToken)>,
+}
+
+impl InFlight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks `prompt` as being answered, returning the token cancelling it.
+    pub fn start(&mut self, prompt: &str) -> CancellationToken {
+        let cancel = CancellationToken::new();
+        self.current = Some((prompt.to_string(), cancel.clone()));
+        cancel
+    }
+
+    /// Marks the response as complete.
+    pub fn finish(&mut self) {
+        self.current = None;
+    }
+
+    pub fn submit(&mut self, prompt: &str) -> Submission {
+        let prompt = prompt.trim();
+        match &self.current {
+            _ if prompt.is_empty() => Submission::Ignore,
+            None => Submission::Run(prompt.to_string()),
+            Some((current, _)) if current == prompt => Submission::Ignore,
+            Some((_, cancel)) => {
+                cancel.cancel();
+                self.current = None;
+                Submission::Replace(prompt.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_prompt_runs() {
+        let mut inflight = InFlight::new();
+
+        assert_eq!(inflight.submit("  explain main.rs\n"), Submission::Run("explain main.rs".to_string()));
+        assert_eq!(inflight.submit(""), Submission::Ignore);
+    }
+
+    #[test]
+    fn test_new_prompt_cancels_and_replaces() {
+        let mut inflight = InFlight::new();
+        let first = inflight.start("explain main.rs");
+
+        assert_eq!(inflight.submit("explain lib.rs"), Submission::Replace("explain lib.rs".to_string()));
+        assert!(first.is_cancelled());
+
+        let second = inflight.start("explain lib.rs");
+        inflight.finish();
+        assert_eq!(inflight.submit("explain config.rs"), Submission::Run("explain config.rs".to_string()));
+        assert!(!second.is_cancelled());
+    }
+
+    #[test]
+    fn test_duplicate_prompt_ignored() {
+        let mut inflight = InFlight::new();
+        let cancel = inflight.start("explain main.rs");
+
+        assert_eq!(inflight.submit("explain main.rs"), Submission::Ignore);
+        assert_eq!(inflight.submit("   "), Submission::Ignore);
+        assert!(!cancel.is_cancelled());
+    }
+}
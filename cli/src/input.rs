@@ -0,0 +1,51 @@
+use std::{
+    io::{self, BufRead},
+    sync::{Arc, Mutex, mpsc},
+    thread,
+};
+
+use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+
+/// Reads stdin on its own thread, so a prompt can be typed while a response
+/// streams. A line goes to a pending [`Input::read_answer`] call if there is
+/// one, e.g. a tool asking the user something, and is a new prompt otherwise.
+#[derive(Clone, Default)]
+pub struct Input {
+    waiting: Arc<Mutex<Option<mpsc::Sender<String>>>>,
+}
+
+impl Input {
+    /// Starts reading stdin, returning the input and the prompts typed into it.
+    pub fn spawn() -> (Self, UnboundedReceiver<String>) {
+        let input = Input::default();
+        let (prompts, receiver) = unbounded_channel();
+
+        let router = input.clone();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                match router.waiting.lock().unwrap().take() {
+                    Some(answer) => {
+                        let _ = answer.send(line);
+                    }
+                    None => {
+                        if prompts.send(line).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        (input, receiver)
+    }
+
+    /// Blocks until the next line is entered and returns it, trimmed.
+    pub fn read_answer(&self) -> String {
+        let (sender, receiver) = mpsc::channel();
+        *self.waiting.lock().unwrap() = Some(sender);
+        receiver.recv().unwrap_or_default().trim().to_string()
+    }
+}
@@ -1,7 +1,18 @@
-use std::io::{self, Write};
-use tokio;
-use termimad::MadSkin;
+mod coalesce;
+mod inflight;
+mod input;
+mod progress;
+mod render;
+mod setup;
+
+use std::{io::{self, IsTerminal, Write}, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::{Duration, Instant}};
 use clap::Parser;
+use codr::{Transcript, tool_calls::ToolTraceFn, transcript::pretty_json};
+use coalesce::Coalescer;
+use inflight::{InFlight, Submission};
+use input::Input;
+use progress::TokenProgress;
+use render::{Renderer, Theme};
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -9,163 +20,503 @@ struct Args {
     #[arg(short, long, default_value = "")]
     prompt: String,
 
+    /// Read the prompt to send from this file, for long or templated prompts.
+    #[arg(long, conflicts_with = "prompt")]
+    prompt_file: Option<PathBuf>,
+
     /// Whether to stream the response. Default is true.
     #[arg(short, long, default_value = "true")]
     stream: bool,
+
+    /// Print raw text without terminal formatting. Implied when stdout is not a terminal.
+    #[arg(long, visible_alias = "no-format")]
+    plain: bool,
+
+    /// Color scheme for formatted output.
+    #[arg(long, value_enum, default_value_t = Theme::Dark)]
+    theme: Theme,
+
+    /// Format output without colors, same as `--theme mono`. Also implied by a
+    /// non-empty NO_COLOR environment variable.
+    #[arg(long)]
+    no_color: bool,
+
+    /// Print each prompt above its response and include it in the transcript.
+    #[arg(long)]
+    echo_prompt: bool,
+
+    /// Record the conversation to this file as JSON lines.
+    #[arg(long)]
+    transcript: Option<PathBuf>,
+
+    /// Indent tool call arguments and results in the transcript, for reading.
+    #[arg(long, requires = "transcript")]
+    pretty_transcript: bool,
+
+    /// Collect streamed text and write it at most this often, in milliseconds.
+    /// Reduces flicker on some terminals. 0 writes every chunk as it arrives.
+    #[arg(long, default_value_t = 0)]
+    flush_interval: u64,
+
+    /// Add a file's content to the conversation before the first prompt. Can be repeated.
+    #[arg(long = "context-file")]
+    context_files: Vec<PathBuf>,
+
+    /// Print each tool call with its arguments and result as it runs.
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Save the conversation to this file as JSON when Codr exits.
+    #[arg(long)]
+    session: Option<PathBuf>,
+
+    /// Language to answer in, e.g. "German". Overrides `language` in codr.toml.
+    #[arg(long)]
+    language: Option<String>,
+
+    /// Serve prompts over HTTP on this address, e.g. 127.0.0.1:8080, instead of
+    /// reading them from the terminal. Answers stream back as server-sent events.
+    #[cfg(feature = "server")]
+    #[arg(long)]
+    serve: Option<String>,
+}
+
+/// The prompt given on the command line, either directly or through `--prompt-file`.
+/// Empty when there is none and the session is interactive.
+fn initial_prompt(args: &Args) -> Result<String, String> {
+    match &args.prompt_file {
+        Some(path) => std::fs::read_to_string(path)
+            .map(|prompt| prompt.trim().to_string())
+            .map_err(|e| format!("Unable to read prompt file {}: {}", path.display(), e)),
+        None => Ok(args.prompt.clone()),
+    }
+}
+
+/// Shows the prompt above its answer and records it in the transcript, if one is kept.
+fn echo_prompt(renderer: &Renderer, out: &mut impl Write, transcript: Option<&Transcript>, prompt: &str) -> io::Result<()> {
+    renderer.echo_prompt(out, prompt)?;
+
+    if let Some(transcript) = transcript {
+        transcript.record(&codr::simple_message(prompt.to_string(), codr::Role::User))?;
+    }
+
+    Ok(())
+}
+
+/// Flushes the transcript and saves the conversation, if asked to. Runs however
+/// the session ends: `exit`, end of input or Ctrl-C.
+fn shutdown(transcript: Option<&Transcript>, session: Option<&Path>, messages: &[codr::Message]) -> io::Result<()> {
+    if let Some(transcript) = transcript {
+        transcript.flush()?;
+    }
+
+    if let Some(path) = session {
+        std::fs::write(path, serde_json::to_string_pretty(messages)?)?;
+    }
+
+    Ok(())
+}
+
+/// The error a session ended with, if it is worth reporting. A reader closing
+/// the pipe early, as `codr ... | head` does, is not.
+fn output_error(ended: io::Result<()>) -> Option<io::Error> {
+    ended.err().filter(|e| e.kind() != io::ErrorKind::BrokenPipe)
+}
+
+/// Returns a tracer printing every tool call to `out` when verbose, and none otherwise.
+fn tool_tracer(verbose: bool, out: impl Write + Send + 'static) -> Option<Box<ToolTraceFn>> {
+    if !verbose {
+        return None;
+    }
+
+    let out = Mutex::new(out);
+    Some(Box::new(move |call, result| {
+        let mut out = out.lock().unwrap();
+        let _ = writeln!(out, "\n[tool] {}\n[arguments] {}\n[result] {}", call.name, pretty_json(&call.arguments), pretty_json(result));
+        let _ = out.flush();
+    }))
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    let initial_prompt = initial_prompt(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
 
-    let mut codr = codr::Codr::new();
+    let config = codr::Config::load("codr.toml").unwrap_or_else(|e| {
+        eprintln!("Unable to read codr.toml: {}", e);
+        std::process::exit(1);
+    });
+    let mut codr = match config.resolved_endpoint() {
+        Ok(endpoint) => codr::Codr::with_endpoint(endpoint),
+        // First run: ask for what's missing, but only if someone is there to answer
+        Err(e) if io::stdin().is_terminal() && io::stderr().is_terminal() => {
+            let endpoint = config.partial_endpoint()
+                .and_then(|known| setup::run(&mut io::stdin().lock(), &mut io::stderr(), Path::new("codr.toml"), known));
+            match endpoint {
+                Ok(endpoint) => codr::Codr::with_endpoint(endpoint),
+                Err(setup_error) => {
+                    eprintln!("{}\n{}", e, setup_error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // Create a custom skin for regular text
-    let text_skin = MadSkin::default();
-    
-    // Create an enhanced skin for code blocks with syntax highlighting
-    let mut code_skin = MadSkin::default();
-    // Customize code blocks with vibrant colors
-    code_skin.code_block.set_bg(termimad::rgb(40, 44, 52));
-    code_skin.code_block.set_fg(termimad::rgb(171, 178, 191));
-    
-    // Create a bold yellow skin for welcome messages
-    let mut welcome_skin = MadSkin::default();
-    welcome_skin.bold.set_fg(termimad::crossterm::style::Color::Red);
+    let transcript = args.transcript.as_ref().map(|path| {
+        Arc::new(Transcript::create(path).expect("Unable to create transcript file").with_pretty_tool_json(args.pretty_transcript))
+    });
+    if let Some(transcript) = transcript.clone() {
+        codr.set_observer(Box::new(move |message| {
+            if let Err(e) = transcript.record(message) {
+                eprintln!("Error writing transcript: {}", e);
+            }
+        }));
+    }
+
+    if args.language.is_some() {
+        codr.set_language(args.language.clone());
+    }
+
+    for path in &args.context_files {
+        if let Err(e) = codr.add_context_file(path) {
+            eprintln!("Unable to add context file {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(tracer) = tool_tracer(args.verbose, io::stderr()) {
+        codr.set_tool_tracer(tracer);
+    }
+
+    let (input, mut prompts) = Input::spawn();
+
+    let answers = input.clone();
+    codr.set_user_input_handler(Box::new(move |question| {
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "\nCodr asks: {}\n> ", question);
+        let _ = stderr.flush();
+        answers.read_answer()
+    }));
+
+    codr.set_confirmation_handler(Box::new(move |action| {
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "\n{} [y/N] ", action);
+        let _ = stderr.flush();
+        matches!(input.read_answer().to_lowercase().as_str(), "y" | "yes")
+    }));
+
+    #[cfg(feature = "server")]
+    if let Some(address) = &args.serve {
+        let listener = tokio::net::TcpListener::bind(address).await.expect("Unable to bind server address");
+        eprintln!("Serving Codr on http://{}", listener.local_addr().unwrap());
+        if let Err(e) = codr::server::serve(codr, listener).await {
+            eprintln!("Server stopped: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let plain = args.plain || !io::stdout().is_terminal();
+    let theme = if args.no_color { Theme::Mono } else { Theme::from_env(args.theme) };
+    let mut renderer = Renderer::new(plain, theme);
+    // The live token count goes to the terminal title, which only a terminal has
+    let show_progress = !plain && io::stderr().is_terminal();
+    // Only the answers go to stdout, so `codr --prompt ... > answer.md` captures
+    // just them; the surrounding chrome and all diagnostics go to stderr
+    let mut stdout = io::stdout();
+    let mut stderr = io::stderr();
     
     let mut prompt = String::new();
+    let mut inflight = InFlight::new();
 
-    welcome_skin.print_text("**------------------------------------------------------------------------**");
-    if args.prompt.is_empty() {
-        welcome_skin.print_text("**WELCOME TO CODR!**");
-        welcome_skin.print_text("**Type 'exit' to quit.**");
-    } else {
-        prompt = args.prompt.clone();
+    if !initial_prompt.is_empty() {
+        prompt = initial_prompt.clone();
     }
 
-    loop {
-        if prompt.is_empty() {
-            let mut msg = "**Ask Codr:** ";
-            if !args.prompt.is_empty() {
-                msg = "**Ask Codr (type 'exit' to quit):** ";
+    // Write errors end the session, which still shuts down as usual
+    let session = async {
+        renderer.banner(&mut stderr, "**------------------------------------------------------------------------**")?;
+        if initial_prompt.is_empty() {
+            renderer.banner(&mut stderr, "**WELCOME TO CODR!**")?;
+            renderer.banner(&mut stderr, "**Type 'exit' to quit.**")?;
+        }
+
+        loop {
+            if prompt.is_empty() {
+                let mut msg = "**Ask Codr:** ";
+                if !initial_prompt.is_empty() {
+                    msg = "**Ask Codr (type 'exit' to quit):** ";
+                }
+                renderer.banner(&mut stderr, "\n")?;
+                renderer.banner_inline(&mut stderr, msg)?;
+                stderr.flush()?;
+
+                prompt = match prompts.recv().await {
+                    Some(line) => line.trim().to_string(),
+                    None => "exit".to_string(),
+                };
             }
-            welcome_skin.print_text("\n");
-            welcome_skin.print_inline(msg);
-            io::stdout().flush().unwrap();
 
-            std::io::stdin().read_line(&mut prompt).unwrap();
-            prompt = prompt.trim().to_string();
             if prompt == "exit" {
-                welcome_skin.print_text("**SEE YOU AROUND!**");
-                welcome_skin.print_text("**------------------------------------------------------------------------**");
+                renderer.banner(&mut stderr, "**SEE YOU AROUND!**")?;
+                renderer.banner(&mut stderr, "**------------------------------------------------------------------------**")?;
                 break;
             }
-        }
-        
-        if args.stream {
-            let mut receiver = codr.message_stream(prompt.clone()).await;
-            
-            // Track code block state
-            let mut in_code_block = false;
-            let mut code_block_content = String::new();
-            
-            while let Some(chunk) = receiver.recv().await {
-                if chunk.is_empty() {
-                    continue;
+
+
+            if prompt == "/context" {
+                write!(stderr, "{}", codr.dump_context())?;
+                prompt.clear();
+                continue;
+            }
+
+            if let Some(path) = prompt.strip_prefix("/export ") {
+                match codr.export_markdown(path.trim()) {
+                    Ok(()) => renderer.banner(&mut stderr, &format!("**Exported the session to {}.**", path.trim()))?,
+                    Err(e) => eprintln!("Error exporting the session: {}", e),
+                }
+                prompt.clear();
+                continue;
+            }
+
+            if let Some(model) = prompt.strip_prefix("/compare ") {
+                match codr.compare_with_model(model.trim()).await {
+                    Ok(comparison) => {
+                        let side_by_side = format!(
+                            "**{}**\n\n{}\n\n---\n\n**{}**\n\n{}",
+                            comparison.original_model, comparison.original, comparison.model, comparison.response,
+                        );
+                        renderer.render(&mut stdout, &side_by_side)?;
+                    }
+                    Err(e) => eprintln!("{}", e),
+                }
+                prompt.clear();
+                continue;
+            }
+
+            if let Some(name) = prompt.strip_prefix("/persona ") {
+                match codr.set_persona(name.trim()) {
+                    Ok(()) => renderer.banner(&mut stderr, &format!("**Switched to persona '{}'.**", name.trim()))?,
+                    Err(e) => eprintln!("{}", e),
                 }
-                
-                if chunk.contains("```") {
-                    // Handle chunks that contain code block markers
-                    let parts: Vec<&str> = chunk.split("```").collect();
-                    
-                    for (i, part) in parts.iter().enumerate() {
-                        if i % 2 == 0 {
-                            // Outside code block
-                            if !in_code_block {
-                                // Regular text before code block
-                                if !part.is_empty() {
-                                    text_skin.print_inline(part);
+                prompt.clear();
+                continue;
+            }
+
+
+            if args.echo_prompt {
+                echo_prompt(&renderer, &mut stdout, transcript.as_deref(), &prompt)?;
+            }
+        
+            let mut replacement = None;
+            if args.stream {
+                let cancel = inflight.start(&prompt);
+                let (mut receiver, _history) = codr.message_stream_cancellable(prompt.clone(), cancel).await;
+                let mut coalescer = Coalescer::new(Duration::from_millis(args.flush_interval));
+                let mut progress = show_progress.then(|| TokenProgress::new(progress::UPDATE_INTERVAL));
+                let mut stdin_open = true;
+
+                loop {
+                    tokio::select! {
+                        chunk = receiver.recv() => match chunk {
+                            Some(chunk) => {
+                                if let Some(tokens) = progress.as_mut().and_then(|progress| progress.push(&chunk, Instant::now())) {
+                                    progress::show(&mut stderr, Some(tokens))?;
+                                }
+                                if let Some(batch) = coalescer.push(&chunk, Instant::now()) {
+                                    renderer.push_chunk(&mut stdout, &batch)?;
                                 }
-                            } else {
-                                // End of code block
-                                code_block_content.push_str(part);
-                                
-                                // Print the complete code block
-                                code_skin.print_text(&format!("```{}", code_block_content));
-                                print!("```");
-                                
-                                // Reset code block tracking
-                                code_block_content.clear();
                             }
-                        } else {
-                            // Code block marker or content
-                            if !in_code_block {
-                                // Starting a code block
-                                in_code_block = true;
-                                code_block_content = part.to_string();
-                            } else {
-                                // Ending a code block
-                                in_code_block = false;
-                                
-                                // Print any text after the code block
-                                if !part.is_empty() {
-                                    text_skin.print_inline(part);
+                            None => break,
+                        },
+                        // A new prompt while this one is answered replaces it, rather than interleaving
+                        line = prompts.recv(), if stdin_open => match line {
+                            Some(line) => match inflight.submit(&line) {
+                                Submission::Run(next) | Submission::Replace(next) => {
+                                    replacement = Some(next);
+                                    break;
                                 }
+                                Submission::Ignore => {}
+                            },
+                            None => stdin_open = false,
+                        },
+                        _ = tokio::time::sleep_until(coalescer.deadline().into()), if coalescer.has_pending() => {
+                            if let Some(batch) = coalescer.take(Instant::now()) {
+                                renderer.push_chunk(&mut stdout, &batch)?;
                             }
                         }
                     }
-                } else if in_code_block {
-                    // Inside a code block - accumulate content
-                    code_block_content.push_str(&chunk);
-                } else {
-                    // Regular text - print immediately
-                    text_skin.print_inline(&chunk);
                 }
-                
-                // Ensure output is displayed immediately
-                io::stdout().flush().unwrap();
-            }
+                if let Some(batch) = coalescer.take(Instant::now()) {
+                    renderer.push_chunk(&mut stdout, &batch)?;
+                }
             
-            // Handle any remaining content
-            if in_code_block && !code_block_content.is_empty() {
-                // Print any accumulated code block
-                code_skin.print_text(&format!("```{}", code_block_content));
-                print!("```");
-            }
-        } else {
-            // Non-streaming mode
-            match codr.message(prompt.to_string()).await {
-                Ok(response) => {
-                    let mut full_response = String::new();
-                    
-                    for line in response {
-                        if let Some(content) = line {
-                            full_response.push_str(&content);
+                // Handle any remaining content
+                renderer.finish_stream(&mut stdout)?;
+                if let Some(tokens) = progress.as_mut().and_then(TokenProgress::finish) {
+                    progress::show(&mut stderr, Some(tokens))?;
+                }
+                inflight.finish();
+                if replacement.is_some() {
+                    renderer.banner(&mut stderr, "\n**Cancelled, answering your new prompt.**")?;
+                }
+            } else {
+                // Non-streaming mode
+                match codr.message(prompt.to_string()).await {
+                    Ok(response) => {
+                        // Text written alongside tool calls comes first, each turn its own paragraph
+                        let full_response = response.into_iter().flatten().collect::<Vec<_>>().join("\n\n");
+
+                        renderer.render(&mut stdout, &full_response)?;
+                        if let Some(answer) = codr.messages().last() {
+                            renderer.sources(&mut stdout, &answer.sources())?;
                         }
                     }
-                    
-                    // Process the markdown to identify code blocks
-                    let segments = full_response.split("```").collect::<Vec<&str>>();
-                    
-                    for (i, segment) in segments.iter().enumerate() {
-                        if i % 2 == 0 {
-                            // Regular text
-                            text_skin.print_text(segment);
-                        } else {
-                            // Code block
-                            code_skin.print_text(&format!("```{}", segment));
-                            print!("```");
-                        }
+                    Err(e) => {
+                        eprintln!("Error while processing your input: {}", e);
                     }
                 }
-                Err(e) => {
-                    eprintln!("Error while processing your input: {}", e);
-                }
             }
+
+            renderer.end_response(&mut stdout)?;
+            if let Some(transcript) = &transcript
+                && let Err(e) = transcript.flush() {
+                eprintln!("Error writing transcript: {}", e);
+            }
+            prompt = replacement.unwrap_or_default();
         }
+        Ok(())
+    };
+
+    // Ctrl-C ends the session like `exit` does, so nothing recorded is lost
+    let ended: io::Result<()> = tokio::select! {
+        ended = session => ended,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+    if show_progress {
+        let _ = progress::show(&mut io::stderr(), None);
+    }
 
-        text_skin.print_text("\n");
-        prompt.clear();
+    if let Err(e) = shutdown(transcript.as_deref(), args.session.as_deref(), &codr.messages()) {
+        eprintln!("Error saving the session: {}", e);
+    }
+    if let Some(e) = output_error(ended) {
+        let _ = writeln!(io::stderr(), "Error writing output: {}", e);
+        std::process::exit(1);
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codr::tool_calls::PendingToolCall;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tool_trace_only_when_verbose() {
+        let call = PendingToolCall {
+            id: "call_1".to_string(),
+            name: "read_file".to_string(),
+            arguments: r#"{"file_path":"src/main.rs"}"#.to_string(),
+        };
+
+        let args = Args::parse_from(["codr"]);
+        assert!(tool_tracer(args.verbose, SharedBuffer::default()).is_none());
+
+        let args = Args::parse_from(["codr", "--verbose"]);
+        let out = SharedBuffer::default();
+        let tracer = tool_tracer(args.verbose, out.clone()).unwrap();
+        tracer(&call, r#"{"content":"fn main() {}"}"#);
+
+        let trace = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert!(trace.contains("[tool] read_file"));
+        assert!(trace.contains("\"file_path\": \"src/main.rs\""));
+        assert!(trace.contains("\"content\": \"fn main() {}\""));
+    }
+
+    #[test]
+    fn test_prompt_file_is_first_prompt() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prompt.md");
+        std::fs::write(&path, "Review src/main.rs\n\nFocus on error handling.\n").unwrap();
+
+        let args = Args::parse_from(["codr", "--prompt-file", path.to_str().unwrap(), "--context-file", "src/main.rs"]);
+        assert_eq!(initial_prompt(&args).unwrap(), "Review src/main.rs\n\nFocus on error handling.");
+        assert_eq!(args.context_files, vec![PathBuf::from("src/main.rs")]);
+
+        let args = Args::parse_from(["codr", "--prompt-file", dir.path().join("missing.md").to_str().unwrap()]);
+        assert!(initial_prompt(&args).unwrap_err().starts_with("Unable to read prompt file"));
+
+        assert!(Args::try_parse_from(["codr", "--prompt", "Hi", "--prompt-file", path.to_str().unwrap()]).is_err());
+        assert_eq!(initial_prompt(&Args::parse_from(["codr", "--prompt", "Hi"])).unwrap(), "Hi");
+    }
+
+    #[test]
+    fn test_shutdown_flushes_transcript_and_saves_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let transcript_path = dir.path().join("transcript.jsonl");
+        let session_path = dir.path().join("session.json");
+        let transcript = Transcript::create(&transcript_path).unwrap();
+
+        let messages = vec![
+            codr::simple_message("Explain main.rs".to_string(), codr::Role::User),
+            codr::simple_message("It starts the CLI.".to_string(), codr::Role::Assistant),
+        ];
+        for message in &messages {
+            transcript.record(message).unwrap();
+        }
+
+        shutdown(Some(&transcript), Some(&session_path), &messages).unwrap();
+
+        let recorded = std::fs::read_to_string(&transcript_path).unwrap();
+        assert_eq!(recorded.lines().count(), 2);
+        assert!(recorded.lines().last().unwrap().contains("It starts the CLI."));
+        let saved: Vec<codr::Message> = serde_json::from_str(&std::fs::read_to_string(&session_path).unwrap()).unwrap();
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[1].content.as_deref(), Some("It starts the CLI."));
+    }
+
+    #[test]
+    fn test_closed_pipe_is_not_reported() {
+        assert!(output_error(Ok(())).is_none());
+        assert!(output_error(Err(io::Error::from(io::ErrorKind::BrokenPipe))).is_none());
+        assert!(output_error(Err(io::Error::other("disk full"))).is_some());
+    }
+
+    #[test]
+    fn test_echo_prompt_is_printed_and_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transcript.jsonl");
+        let transcript = Transcript::create(&path).unwrap();
+        let mut out = Vec::new();
+
+        echo_prompt(&Renderer::new(true, Theme::Dark), &mut out, Some(&transcript), "What does main do?").unwrap();
+        transcript.flush().unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "You: What does main do?\n");
+        let recorded: serde_json::Value = serde_json::from_str(
+            std::fs::read_to_string(&path).unwrap().lines().next().unwrap()
+        ).unwrap();
+        assert_eq!(recorded["role"], "user");
+        assert_eq!(recorded["content"], "What does main do?");
+    }
+}
@@ -0,0 +1,91 @@
+use std::{io::{self, Write}, time::{Duration, Instant}};
+
+use codr::cost::estimate_text_tokens;
+
+/// How often the token count of a streaming response is redrawn.
+pub const UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Counts the tokens of a response as it streams in, for a live indicator
+/// that is redrawn at most once per `interval` and only when the count changed.
+#[derive(Debug)]
+pub struct TokenProgress {
+    interval: Duration,
+    content: String,
+    last_update: Option<Instant>,
+    shown: Option<u64>,
+}
+
+impl TokenProgress {
+    pub fn new(interval: Duration) -> Self {
+        TokenProgress {
+            interval,
+            content: String::new(),
+            last_update: None,
+            shown: None,
+        }
+    }
+
+    /// Adds a streamed chunk, returning the count to show once an update is due.
+    pub fn push(&mut self, chunk: &str, now: Instant) -> Option<u64> {
+        self.content.push_str(chunk);
+
+        let due = self.last_update.is_none_or(|last_update| now.duration_since(last_update) >= self.interval);
+        if !due {
+            return None;
+        }
+        self.last_update = Some(now);
+        self.update()
+    }
+
+    /// The final count, if it differs from the one shown last.
+    pub fn finish(&mut self) -> Option<u64> {
+        self.update()
+    }
+
+    fn update(&mut self) -> Option<u64> {
+        let tokens = estimate_text_tokens(&self.content);
+        if self.shown == Some(tokens) {
+            return None;
+        }
+        self.shown = Some(tokens);
+        Some(tokens)
+    }
+}
+
+/// Shows `tokens` in the terminal title, which updates in place without
+/// disturbing the streamed answer. `None` clears it.
+pub fn show(out: &mut impl Write, tokens: Option<u64>) -> io::Result<()> {
+    match tokens {
+        Some(tokens) => write!(out, "\x1b]2;codr: ~{} tokens\x07", tokens)?,
+        None => write!(out, "\x1b]2;\x07")?,
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_updates_throttled_to_interval() {
+        let start = Instant::now();
+        let mut progress = TokenProgress::new(Duration::from_millis(100));
+
+        assert_eq!(progress.push("Hello, ", start), Some(2));
+        assert_eq!(progress.push("world! ", start + Duration::from_millis(40)), None);
+        assert_eq!(progress.push("How are you?", start + Duration::from_millis(90)), None);
+        assert_eq!(progress.push(" Fine.", start + Duration::from_millis(120)), Some(8));
+        assert_eq!(progress.push("!", start + Duration::from_millis(150)), None);
+        assert_eq!(progress.finish(), Some(9));
+    }
+
+    #[test]
+    fn test_unchanged_count_not_redrawn() {
+        let start = Instant::now();
+        let mut progress = TokenProgress::new(Duration::ZERO);
+
+        assert_eq!(progress.push("abcd", start), Some(1));
+        assert_eq!(progress.push("", start + Duration::from_millis(10)), None);
+        assert_eq!(progress.finish(), None);
+    }
+}
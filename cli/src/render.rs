@@ -0,0 +1,350 @@
+use std::io::{self, Write};
+use termimad::{MadSkin, crossterm::style::Color};
+
+/// Renders Codr responses to the terminal, either through termimad skins or,
+/// in plain mode, as the raw text the model produced.
+pub struct Renderer {
+    plain: bool,
+    text_skin: MadSkin,
+    code_skin: MadSkin,
+    banner_skin: MadSkin,
+
+    // Track code block state while streaming
+    in_code_block: bool,
+    pending: String,
+}
+
+const FENCE: &str = "```";
+
+/// Wraps code block content (optionally starting with a language tag) in an
+/// opening and exactly one closing fence.
+fn fenced(content: &str) -> String {
+    if content.ends_with('\n') {
+        format!("{FENCE}{content}{FENCE}")
+    } else {
+        format!("{FENCE}{content}\n{FENCE}")
+    }
+}
+
+/// Built-in color schemes for formatted output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Theme {
+    /// Light text on a dark code block background.
+    #[default]
+    Dark,
+    /// Dark text on a light code block background.
+    Light,
+    /// Formatting without any colors.
+    Mono,
+}
+
+impl Theme {
+    /// The theme to use when colors were asked for, `Mono` when `NO_COLOR` is
+    /// set to anything non-empty.
+    pub fn from_env(requested: Theme) -> Theme {
+        match std::env::var_os("NO_COLOR") {
+            Some(value) if !value.is_empty() => Theme::Mono,
+            _ => requested,
+        }
+    }
+
+    /// Skins for regular text, code blocks and banners, in that order.
+    fn skins(self) -> (MadSkin, MadSkin, MadSkin) {
+        match self {
+            Theme::Dark | Theme::Light => {
+                let text_skin = MadSkin::default();
+
+                let (bg, fg) = if self == Theme::Dark {
+                    (termimad::rgb(40, 44, 52), termimad::rgb(171, 178, 191))
+                } else {
+                    (termimad::rgb(250, 250, 250), termimad::rgb(56, 58, 66))
+                };
+                let mut code_skin = MadSkin::default();
+                code_skin.code_block.set_bg(bg);
+                code_skin.code_block.set_fg(fg);
+
+                let mut banner_skin = MadSkin::default();
+                banner_skin.bold.set_fg(if self == Theme::Dark { Color::Red } else { Color::DarkRed });
+
+                (text_skin, code_skin, banner_skin)
+            }
+            Theme::Mono => (MadSkin::no_style(), MadSkin::no_style(), MadSkin::no_style()),
+        }
+    }
+}
+
+impl Renderer {
+    pub fn new(plain: bool, theme: Theme) -> Self {
+        let (text_skin, code_skin, banner_skin) = theme.skins();
+
+        Renderer {
+            plain,
+            text_skin,
+            code_skin,
+            banner_skin,
+            in_code_block: false,
+            pending: String::new(),
+        }
+    }
+
+    /// Prints a line of CLI chrome (welcome messages, prompts).
+    pub fn banner(&self, out: &mut impl Write, text: &str) -> io::Result<()> {
+        if self.plain {
+            writeln!(out, "{}", text.replace("**", ""))
+        } else {
+            write!(out, "{}", self.banner_skin.term_text(text))
+        }
+    }
+
+    /// Prints CLI chrome without a trailing newline.
+    pub fn banner_inline(&self, out: &mut impl Write, text: &str) -> io::Result<()> {
+        if self.plain {
+            write!(out, "{}", text.replace("**", ""))
+        } else {
+            write!(out, "{}", self.banner_skin.inline(text))
+        }
+    }
+
+    /// Shows the user's prompt above the response.
+    pub fn echo_prompt(&self, out: &mut impl Write, prompt: &str) -> io::Result<()> {
+        if self.plain {
+            writeln!(out, "You: {}", prompt)
+        } else {
+            write!(out, "{}", self.text_skin.term_text(&format!("**You:** {}", prompt)))
+        }
+    }
+
+    /// Renders one streamed chunk of a response.
+    pub fn push_chunk(&mut self, out: &mut impl Write, chunk: &str) -> io::Result<()> {
+        if chunk.is_empty() {
+            return Ok(());
+        }
+
+        if self.plain {
+            write!(out, "{}", chunk)?;
+            return out.flush();
+        }
+
+        self.pending.push_str(chunk);
+
+        loop {
+            match self.pending.find(FENCE) {
+                Some(pos) if self.in_code_block => {
+                    // End of code block - print it complete with both fences
+                    let code = self.pending[..pos].to_string();
+                    self.pending.drain(..pos + FENCE.len());
+                    self.in_code_block = false;
+                    write!(out, "{}", self.code_skin.term_text(&fenced(&code)))?;
+                }
+                Some(pos) => {
+                    // Start of code block - print the text before it
+                    let text = self.pending[..pos].to_string();
+                    self.pending.drain(..pos + FENCE.len());
+                    self.in_code_block = true;
+                    if !text.is_empty() {
+                        write!(out, "{}", self.text_skin.inline(&text))?;
+                    }
+                }
+                None if self.in_code_block => {
+                    // Inside a code block - keep accumulating until it closes
+                    break;
+                }
+                None => {
+                    // Regular text - print immediately, holding back backticks
+                    // that may be the start of a fence split across chunks
+                    let held = self.pending.len() - self.pending.trim_end_matches('`').len();
+                    let printable = self.pending.len() - held;
+                    if printable > 0 {
+                        let text: String = self.pending.drain(..printable).collect();
+                        write!(out, "{}", self.text_skin.inline(&text))?;
+                    }
+                    break;
+                }
+            }
+        }
+
+        // Ensure output is displayed immediately
+        out.flush()
+    }
+
+    /// Flushes whatever a stream left behind once it ends.
+    pub fn finish_stream(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let rest = std::mem::take(&mut self.pending);
+
+        if self.in_code_block {
+            // The response ended inside a code block, so close it ourselves
+            write!(out, "{}", self.code_skin.term_text(&fenced(&rest)))?;
+        } else if !rest.is_empty() {
+            write!(out, "{}", self.text_skin.inline(&rest))?;
+        }
+
+        self.in_code_block = false;
+        out.flush()
+    }
+
+    /// Renders a complete, non-streamed response.
+    pub fn render(&self, out: &mut impl Write, response: &str) -> io::Result<()> {
+        if self.plain {
+            return write!(out, "{}", response);
+        }
+
+        // Process the markdown to identify code blocks
+        let segments = response.split(FENCE).collect::<Vec<&str>>();
+
+        for (i, segment) in segments.iter().enumerate() {
+            if i % 2 == 0 {
+                // Regular text
+                write!(out, "{}", self.text_skin.term_text(segment))?;
+            } else {
+                // Code block
+                write!(out, "{}", self.code_skin.term_text(&fenced(segment)))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists the sources a response cited, if any.
+    pub fn sources(&self, out: &mut impl Write, sources: &[String]) -> io::Result<()> {
+        if sources.is_empty() {
+            return Ok(());
+        }
+
+        let list: String = sources.iter().map(|source| format!("\n- {}", source)).collect();
+        if self.plain {
+            write!(out, "\n\nSources:{}", list)
+        } else {
+            write!(out, "{}", self.text_skin.term_text(&format!("\n**Sources:**{}", list)))
+        }
+    }
+
+    /// Separates one response from the next prompt.
+    pub fn end_response(&self, out: &mut impl Write) -> io::Result<()> {
+        if self.plain {
+            writeln!(out)
+        } else {
+            write!(out, "{}", self.text_skin.term_text("\n"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESPONSE: &str = "Here is **code**:\n```rust\nfn main() {}\n```\nDone.";
+
+    #[test]
+    fn test_plain_stream_emits_raw_content() {
+        let mut renderer = Renderer::new(true, Theme::Dark);
+        let mut out = Vec::new();
+
+        for chunk in ["Here is **code**:\n``", "`rust\nfn main() {}\n", "```\nDone."] {
+            renderer.push_chunk(&mut out, chunk).unwrap();
+        }
+        renderer.finish_stream(&mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), RESPONSE);
+    }
+
+    #[test]
+    fn test_plain_sources() {
+        let renderer = Renderer::new(true, Theme::Dark);
+        let mut out = Vec::new();
+
+        renderer.sources(&mut out, &[]).unwrap();
+        renderer.sources(&mut out, &["https://example.com".to_string(), "notes.md".to_string()]).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\n\nSources:\n- https://example.com\n- notes.md");
+    }
+
+    #[test]
+    fn test_plain_render_emits_raw_content() {
+        let renderer = Renderer::new(true, Theme::Dark);
+        let mut out = Vec::new();
+
+        renderer.render(&mut out, RESPONSE).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), RESPONSE);
+    }
+
+    #[test]
+    fn test_theme_skins() {
+        let code_colors = |theme: Theme| {
+            let style = Renderer::new(false, theme).code_skin.code_block.compound_style.object_style;
+            (style.background_color, style.foreground_color)
+        };
+
+        assert_eq!(code_colors(Theme::Dark), (Some(termimad::rgb(40, 44, 52)), Some(termimad::rgb(171, 178, 191))));
+        assert_eq!(code_colors(Theme::Light), (Some(termimad::rgb(250, 250, 250)), Some(termimad::rgb(56, 58, 66))));
+        assert_eq!(code_colors(Theme::Mono), (None, None));
+        assert_eq!(Renderer::new(false, Theme::Light).banner_skin.bold.get_fg(), Some(Color::DarkRed));
+        assert_eq!(Renderer::new(false, Theme::Mono).banner_skin.bold.get_fg(), None);
+    }
+
+    #[test]
+    fn test_mono_render_has_no_escape_codes() {
+        let mut out = Vec::new();
+        Renderer::new(false, Theme::Mono).render(&mut out, RESPONSE).unwrap();
+
+        let output = String::from_utf8(out).unwrap();
+        assert!(output.contains("fn main() {}"));
+        assert!(!output.contains('\x1b'), "escape codes in {:?}", output);
+    }
+
+    fn render_stream(chunks: &[&str]) -> String {
+        let mut renderer = Renderer::new(false, Theme::Dark);
+        let mut out = Vec::new();
+
+        for chunk in chunks {
+            renderer.push_chunk(&mut out, chunk).unwrap();
+        }
+        renderer.finish_stream(&mut out).unwrap();
+
+        String::from_utf8(out).unwrap()
+    }
+
+    fn render_full(response: &str) -> String {
+        let mut out = Vec::new();
+        Renderer::new(false, Theme::Dark).render(&mut out, response).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_fenced_closes_exactly_once() {
+        assert_eq!(fenced("rust\nfn main() {}"), "```rust\nfn main() {}\n```");
+        assert_eq!(fenced("rust\nfn main() {}\n"), "```rust\nfn main() {}\n```");
+    }
+
+    #[test]
+    fn test_stream_ending_just_after_code_block() {
+        let output = render_stream(&["Here:\n``", "`rust\nfn main() {}\n`", "``"]);
+
+        assert!(output.contains("fn main() {}"));
+        assert!(!output.contains('`'), "stray backticks in {:?}", output);
+    }
+
+    #[test]
+    fn test_stream_ending_inside_code_block() {
+        let output = render_stream(&["Here:\n```rust\n", "fn main() {}"]);
+
+        assert!(output.contains("fn main() {}"));
+        assert!(!output.contains('`'), "stray backticks in {:?}", output);
+    }
+
+    #[test]
+    fn test_render_ending_just_after_code_block() {
+        let output = render_full("Here:\n```rust\nfn main() {}\n```");
+
+        assert!(output.contains("fn main() {}"));
+        assert!(!output.contains('`'), "stray backticks in {:?}", output);
+    }
+
+    #[test]
+    fn test_render_ending_inside_code_block() {
+        let output = render_full("Here:\n```rust\nfn main() {}");
+
+        assert!(output.contains("fn main() {}"));
+        assert!(!output.contains('`'), "stray backticks in {:?}", output);
+    }
+}
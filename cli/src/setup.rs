@@ -0,0 +1,135 @@
+use std::{
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+use codr::{Endpoint, PartialEndpoint};
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Asks for the parts of the provider neither the environment nor `codr.toml`
+/// names, `known` being the rest, and offers to save the answers to `config_path`.
+pub fn run(input: &mut impl BufRead, out: &mut impl Write, config_path: &Path, known: PartialEndpoint) -> Result<Endpoint, Box<dyn std::error::Error>> {
+    writeln!(out, "Codr is not set up yet. Tell it which model to use:")?;
+
+    let mut answers = PartialEndpoint::default();
+    if known.base_url.is_none() {
+        answers.base_url = Some(ask(input, out, &format!("Base URL [{}]: ", DEFAULT_BASE_URL), Some(DEFAULT_BASE_URL))?);
+    }
+    if known.api_key.is_none() {
+        answers.api_key = Some(ask(input, out, "API key: ", None)?);
+    }
+    if known.model.is_none() {
+        answers.model = Some(ask(input, out, "Model: ", None)?);
+    }
+
+    let question = match answers.api_key {
+        Some(_) => format!("Save to {}? The API key is stored in plain text [y/N]: ", config_path.display()),
+        None => format!("Save to {}? [y/N]: ", config_path.display()),
+    };
+    let save = ask(input, out, &question, Some("n"))?;
+    if matches!(save.to_lowercase().as_str(), "y" | "yes") {
+        codr::config::save_endpoint(config_path, &answers)?;
+        writeln!(out, "Saved to {}.", config_path.display())?;
+    }
+
+    PartialEndpoint {
+        base_url: known.base_url.or(answers.base_url),
+        api_key: known.api_key.or(answers.api_key),
+        model: known.model.or(answers.model),
+    }.complete()
+}
+
+/// Asks until a non-empty answer is given, or takes `default` for an empty one.
+fn ask(input: &mut impl BufRead, out: &mut impl Write, question: &str, default: Option<&str>) -> io::Result<String> {
+    loop {
+        write!(out, "{}", question)?;
+        out.flush()?;
+
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "setup aborted"));
+        }
+
+        match (answer.trim(), default) {
+            ("", Some(default)) => return Ok(default.to_string()),
+            ("", None) => continue,
+            (answer, _) => return Ok(answer.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io::Cursor};
+
+    #[test]
+    fn test_setup_saves_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("codr.toml");
+        fs::write(&path, "language = \"German\"\n\n[pricing]\ninput_per_million = 1.0\noutput_per_million = 2.0\n").unwrap();
+
+        // Default URL, a skipped empty key, then the key with a quote in it
+        let mut input = Cursor::new("\n\nsk-\"test\"\nlocal-model\ny\n");
+        let mut out = Vec::new();
+        let endpoint = run(&mut input, &mut out, &path, PartialEndpoint::default()).unwrap();
+
+        assert_eq!(endpoint, Endpoint {
+            base_url: DEFAULT_BASE_URL.to_string(),
+            api_key: "sk-\"test\"".to_string(),
+            model: "local-model".to_string(),
+        });
+
+        let config = codr::Config::load(&path).unwrap();
+        assert_eq!(config.base_url.as_deref(), Some(DEFAULT_BASE_URL));
+        assert_eq!(config.api_key.as_deref(), Some("sk-\"test\""));
+        assert_eq!(config.model.as_deref(), Some("local-model"));
+        assert_eq!(config.language.as_deref(), Some("German"));
+        assert!(config.pricing.is_some());
+    }
+
+    #[test]
+    fn test_setup_without_saving() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("codr.toml");
+
+        let mut input = Cursor::new("http://localhost:11434/v1\nkey\nllama\n\n");
+        let endpoint = run(&mut input, &mut Vec::new(), &path, PartialEndpoint::default()).unwrap();
+
+        assert_eq!(endpoint.base_url, "http://localhost:11434/v1");
+        assert!(!path.exists());
+
+        let mut input = Cursor::new("http://localhost:11434/v1\n");
+        assert!(run(&mut input, &mut Vec::new(), &path, PartialEndpoint::default()).is_err());
+    }
+
+    #[test]
+    fn test_setup_asks_only_for_missing_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("codr.toml");
+        fs::write(&path, "model = \"local-model\"\napi_key = \"${CODR_SETUP_TEST_KEY}\"\n").unwrap();
+        let known = PartialEndpoint {
+            api_key: Some("from-env".to_string()),
+            model: Some("local-model".to_string()),
+            ..Default::default()
+        };
+
+        let mut input = Cursor::new("http://localhost:11434/v1\ny\n");
+        let mut out = Vec::new();
+        let endpoint = run(&mut input, &mut out, &path, known).unwrap();
+
+        assert_eq!(endpoint, Endpoint {
+            base_url: "http://localhost:11434/v1".to_string(),
+            api_key: "from-env".to_string(),
+            model: "local-model".to_string(),
+        });
+        let out = String::from_utf8(out).unwrap();
+        assert!(!out.contains("API key:") && !out.contains("Model:"));
+
+        // Only the answer is saved, the key reference stays as it was
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains("api_key = \"${CODR_SETUP_TEST_KEY}\""));
+        assert_eq!(codr::Config::parse(&content).unwrap().base_url.as_deref(), Some("http://localhost:11434/v1"));
+    }
+}
@@ -0,0 +1,31 @@
+use std::process::Stdio;
+
+use openai::test_util::{MockResponse, MockServer, stream_chunk};
+
+#[tokio::test]
+async fn test_stdout_holds_only_the_answer() {
+    let server = MockServer::start(vec![MockResponse::sse(vec![
+        stream_chunk(serde_json::json!({"role": "assistant", "content": "# Answer\n"}), None),
+        stream_chunk(serde_json::json!({"content": "Just the content."}), None),
+        stream_chunk(serde_json::json!({}), Some("stop")),
+    ])]).await;
+
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("system_prompt.md"), "You are a test").unwrap();
+
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_cli"))
+        .args(["--prompt", "Answer briefly", "--verbose"])
+        .current_dir(dir.path())
+        .env("CODR_BASE_URL", server.url())
+        .env("CODR_API_KEY", "test-key")
+        .env("CODR_MODEL", "test-model")
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "# Answer\nJust the content.\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("SEE YOU AROUND!"));
+}
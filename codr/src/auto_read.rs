@@ -0,0 +1,79 @@
+use std::{
+    collections::HashSet,
+    path::{Component, Path},
+};
+
+use openai::{Message, Role};
+
+use crate::{context_files::context_message, cost::estimate_text_tokens};
+
+/// Context messages with the files the last answer mentions that exist under
+/// `root` but the conversation hasn't seen yet, neither through a tool call nor
+/// as context. Files are added in the order they are mentioned while they fit
+/// in `budget` tokens; files that don't fit are skipped.
+pub fn referenced_file_messages(root: &Path, messages: &[Message], budget: u64) -> Vec<Message> {
+    let Some(answer) = messages.iter().rev()
+        .find(|message| matches!(message.role, Some(Role::Assistant)) && message.content.as_deref().is_some_and(|content| !content.is_empty()))
+        .and_then(|message| message.content.as_deref()) else {
+        return Vec::new();
+    };
+
+    let mut seen = seen_paths(messages);
+    let mut spent = 0;
+    let mut found = Vec::new();
+    for path in mentioned_paths(answer) {
+        if !seen.insert(path.trim_start_matches("./").to_string()) || !root.join(path).is_file() {
+            continue;
+        }
+        let Ok(message) = context_message(root, Path::new(path)) else {
+            continue;
+        };
+        let tokens = estimate_text_tokens(message.content.as_deref().unwrap_or_default());
+        if spent + tokens <= budget {
+            spent += tokens;
+            found.push(message);
+        }
+    }
+    found
+}
+
+/// Words of `text` that could be relative file paths, e.g. `src/main.rs`,
+/// stripped of the punctuation and code quotes around them.
+fn mentioned_paths(text: &str) -> Vec<&str> {
+    text.split(|c: char| c.is_whitespace() || "`'\"()[]<>,;".contains(c))
+        .map(|word| word.trim_end_matches([':', '.', '!', '?']))
+        .filter(|word| word.contains('.') || word.contains('/'))
+        .filter(|word| Path::new(word).components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)))
+        .collect()
+}
+
+/// Paths the conversation already has the content of: arguments of tool calls
+/// naming a file, and files added as context.
+fn seen_paths(messages: &[Message]) -> HashSet<String> {
+    let mut seen = HashSet::new();
+    for message in messages {
+        for call in message.tool_calls.iter().flatten() {
+            if let Ok(arguments) = serde_json::from_str::<serde_json::Value>(&call.function.arguments)
+                && let Some(path) = arguments["file_path"].as_str() {
+                seen.insert(path.trim_start_matches("./").to_string());
+            }
+        }
+        if matches!(message.role, Some(Role::User))
+            && let Some(header) = message.content.as_deref().and_then(|content| content.strip_prefix("Contents of"))
+            && let Some((path, _)) = header.split_once(":\n") {
+            seen.insert(path.trim().to_string());
+        }
+    }
+    seen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mentioned_paths() {
+        let paths = mentioned_paths("Check `src/lib.rs` and (Cargo.toml). Also ../secret.txt, /etc/passwd and the end.");
+        assert_eq!(paths, vec!["src/lib.rs", "Cargo.toml"]);
+    }
+}
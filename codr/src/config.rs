@@ -0,0 +1,380 @@
+use std::{collections::HashMap, env, fs, path::Path};
+
+use serde::Deserialize;
+use tools::CompositeTool;
+
+use crate::{cost::Pricing, persona::Persona};
+
+/// Settings read from `codr.toml`.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Config {
+    /// Provider URL, used when `CODR_BASE_URL` is not set.
+    pub base_url: Option<String>,
+
+    /// API key, used when `CODR_API_KEY` is not set. May reference `${NAME}`.
+    pub api_key: Option<String>,
+
+    /// Model name, used when `CODR_MODEL` is not set.
+    pub model: Option<String>,
+
+    /// Headers attached to every API request. Values may reference
+    /// environment variables as `${NAME}` so secrets stay out of the file.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// Send tool results to the primary provider as user messages, for
+    /// gateways and local models without the `tool` role.
+    #[serde(default)]
+    pub tool_results_as_user: bool,
+
+    /// Offer tools through the legacy `functions` API, for endpoints without tool calling.
+    #[serde(default)]
+    pub legacy_functions: bool,
+
+    /// Append a list of the available tools to the system prompt, for models that ignore them.
+    #[serde(default)]
+    pub tool_list_in_prompt: bool,
+
+    /// Tokens of files mentioned by the model, but not read, added to the next prompt. Off when unset.
+    pub auto_read_budget: Option<u64>,
+
+    /// Most bytes of arguments a streamed tool call may have before it is
+    /// aborted instead of buffered. Defaults to 16 MiB.
+    pub max_tool_argument_bytes: Option<usize>,
+
+    /// Most bytes a single streamed response may have before it is cut off. Unlimited when unset.
+    pub max_stream_bytes: Option<usize>,
+
+    /// Send each prompt's id as an `X-Request-Id` header, to correlate server logs.
+    #[serde(default)]
+    pub request_id_header: bool,
+
+    /// Tags sent in the body of every request, for providers that record them.
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+
+    /// Persona to start with, see [`crate::persona::builtin_personas`].
+    pub persona: Option<String>,
+
+    /// Additional personas, or overrides of the built-in ones, keyed by name.
+    #[serde(default)]
+    pub personas: HashMap<String, Persona>,
+
+    /// Providers tried in order when the primary one fails with a server,
+    /// network or quota error.
+    #[serde(default)]
+    pub fallbacks: Vec<Fallback>,
+
+    /// Replacement descriptions for tools, keyed by tool name, for models that
+    /// pick tools more reliably with different wording.
+    #[serde(default)]
+    pub tool_descriptions: HashMap<String, String>,
+
+    /// Tools that run a sequence of other tools and answer with one combined result.
+    #[serde(default)]
+    pub composite_tools: Vec<CompositeTool>,
+
+    /// Price of the model, used to track what a session costs.
+    pub pricing: Option<Pricing>,
+
+    /// Dollars a session may spend before Codr refuses further requests. Needs `pricing`.
+    pub max_session_cost: Option<f64>,
+
+    /// Most tokens of a single tool result sent to the model. Longer results,
+    /// like a huge file read, are trimmed with a marker saying so.
+    pub max_tool_result_tokens: Option<u64>,
+
+    /// User turns after which tool results are replaced by a placeholder in the history.
+    pub tool_result_turns: Option<usize>,
+
+    /// Answer a tool call identical to the one right before it with the cached
+    /// result and a note to stop repeating, instead of running it again.
+    #[serde(default)]
+    pub dedupe_tool_calls: bool,
+
+    /// Language the model should answer in, e.g. "German".
+    pub language: Option<String>,
+
+    /// Commands `run_file` uses per file extension, added to or replacing the defaults,
+    /// e.g. `rb = "ruby"` or `py = "uv run python"`.
+    #[serde(default)]
+    pub interpreters: HashMap<String, String>,
+}
+
+/// The provider requests go to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoint {
+    pub base_url: String,
+    pub api_key: String,
+    pub model: String,
+}
+
+/// The parts of an [`Endpoint`] known so far, e.g. from a partly filled in config.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PartialEndpoint {
+    pub base_url: Option<String>,
+    pub api_key: Option<String>,
+    pub model: Option<String>,
+}
+
+impl PartialEndpoint {
+    /// The endpoint, if every part is known. Fails naming every missing one.
+    pub fn complete(self) -> Result<Endpoint, Box<dyn std::error::Error>> {
+        match (self.base_url, self.api_key, self.model) {
+            (Some(base_url), Some(api_key), Some(model)) => Ok(Endpoint { base_url, api_key, model }),
+            (base_url, api_key, model) => {
+                let missing: Vec<&str> = [(base_url, "CODR_BASE_URL"), (api_key, "CODR_API_KEY"), (model, "CODR_MODEL")].into_iter()
+                    .filter(|(value, _)| value.is_none())
+                    .map(|(_, name)| name)
+                    .collect();
+                Err(format!("{} must be set, in the environment or in codr.toml", missing.join(", ")).into())
+            }
+        }
+    }
+}
+
+/// One entry of the fallback chain.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fallback {
+    pub base_url: String,
+    pub model: String,
+    /// API key for this provider, may reference `${NAME}`. Defaults to the primary key.
+    pub api_key: Option<String>,
+    /// Send tool results as user messages, for providers without the `tool` role.
+    #[serde(default)]
+    pub tool_results_as_user: bool,
+}
+
+impl Config {
+    /// Loads the config at `path`, falling back to defaults when the file does not exist.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        match fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// Returns the endpoint from `CODR_BASE_URL`, `CODR_API_KEY` and `CODR_MODEL`,
+    /// falling back to `base_url`, `api_key` and `model` in the file. Fails
+    /// naming every setting found in neither place.
+    pub fn resolved_endpoint(&self) -> Result<Endpoint, Box<dyn std::error::Error>> {
+        self.partial_endpoint()?.complete()
+    }
+
+    /// Like [`Config::resolved_endpoint`], but leaves settings found in neither place unset.
+    pub fn partial_endpoint(&self) -> Result<PartialEndpoint, Box<dyn std::error::Error>> {
+        let setting = |var: &str, value: &Option<String>| -> Result<Option<String>, Box<dyn std::error::Error>> {
+            match env::var(var) {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => value.as_deref().map(expand_env).transpose(),
+            }
+        };
+
+        Ok(PartialEndpoint {
+            base_url: setting("CODR_BASE_URL", &self.base_url)?,
+            api_key: setting("CODR_API_KEY", &self.api_key)?,
+            model: setting("CODR_MODEL", &self.model)?,
+        })
+    }
+
+    /// Returns the configured headers with `${NAME}` references expanded.
+    pub fn resolved_headers(&self) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+        self.headers.iter()
+            .map(|(name, value)| Ok((name.clone(), expand_env(value)?)))
+            .collect()
+    }
+
+    /// Returns the fallbacks with `${NAME}` references in their API keys expanded.
+    pub fn resolved_fallbacks(&self) -> Result<Vec<Fallback>, Box<dyn std::error::Error>> {
+        self.fallbacks.iter()
+            .map(|fallback| Ok(Fallback {
+                api_key: fallback.api_key.as_deref().map(expand_env).transpose()?,
+                ..fallback.clone()
+            }))
+            .collect()
+    }
+}
+
+/// Writes the known parts of `endpoint` to the config at `path`, replacing
+/// those already set there and keeping whatever else it contains.
+pub fn save_endpoint(path: impl AsRef<Path>, endpoint: &PartialEndpoint) -> Result<(), Box<dyn std::error::Error>> {
+    let path = path.as_ref();
+    let existing = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut document: toml_edit::DocumentMut = existing.parse()?;
+    for (key, value) in [("base_url", &endpoint.base_url), ("api_key", &endpoint.api_key), ("model", &endpoint.model)] {
+        if let Some(value) = value {
+            document[key] = toml_edit::value(value.as_str());
+        }
+    }
+    let content = document.to_string();
+    Config::parse(&content)?;
+
+    fs::write(path, content)?;
+    Ok(())
+}
+
+fn expand_env(value: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let re = regex::Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap();
+    let mut expanded = String::new();
+    let mut last = 0;
+
+    for caps in re.captures_iter(value) {
+        let whole = caps.get(0).unwrap();
+        let name = &caps[1];
+        let var = env::var(name)
+            .map_err(|_| format!("environment variable {} referenced in codr.toml is not set", name))?;
+
+        expanded.push_str(&value[last..whole.start()]);
+        expanded.push_str(&var);
+        last = whole.end();
+    }
+
+    expanded.push_str(&value[last..]);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_endpoint_into_partial_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("codr.toml");
+        fs::write(&path, "# my settings\nmodel = \"old-model\"\nlanguage = \"German\"\n\n[pricing]\ninput_per_million = 1.0\noutput_per_million = 2.0\n").unwrap();
+
+        save_endpoint(&path, &PartialEndpoint {
+            base_url: Some("http://localhost:11434/v1".to_string()),
+            api_key: Some("sk-\"test\"".to_string()),
+            model: Some("new-model".to_string()),
+        }).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# my settings\n"));
+        assert_eq!(content.matches("model =").count(), 1);
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.base_url.as_deref(), Some("http://localhost:11434/v1"));
+        assert_eq!(config.api_key.as_deref(), Some("sk-\"test\""));
+        assert_eq!(config.model.as_deref(), Some("new-model"));
+        assert_eq!(config.language.as_deref(), Some("German"));
+        assert!(config.pricing.is_some());
+    }
+
+    #[test]
+    fn test_headers_expand_env() {
+        unsafe { env::set_var("CODR_TEST_GATEWAY_TOKEN", "secret") };
+
+        let config = Config::parse(r#"
+            [headers]
+            X-Gateway-Route = "team-a"
+            X-Gateway-Token = "Bearer ${CODR_TEST_GATEWAY_TOKEN}"
+        "#).unwrap();
+
+        let headers = config.resolved_headers().unwrap();
+        assert_eq!(headers["X-Gateway-Route"], "team-a");
+        assert_eq!(headers["X-Gateway-Token"], "Bearer secret");
+    }
+
+    #[test]
+    fn test_personas() {
+        let config = Config::parse(r#"
+            persona = "security"
+
+            [personas.security]
+            system_prompt = "Look for vulnerabilities."
+            tools = ["read_file"]
+        "#).unwrap();
+
+        assert_eq!(config.persona.as_deref(), Some("security"));
+        assert_eq!(config.personas["security"].system_prompt, "Look for vulnerabilities.");
+        assert_eq!(config.personas["security"].tools, Some(vec!["read_file".to_string()]));
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        unsafe { env::set_var("CODR_TEST_FALLBACK_KEY", "backup-key") };
+
+        let config = Config::parse(r#"
+            [[fallbacks]]
+            base_url = "https://backup.example.com/v1"
+            model = "backup-model"
+            api_key = "${CODR_TEST_FALLBACK_KEY}"
+
+            [[fallbacks]]
+            base_url = "http://localhost:11434/v1"
+            model = "local-model"
+        "#).unwrap();
+
+        let fallbacks = config.resolved_fallbacks().unwrap();
+        assert_eq!(fallbacks.len(), 2);
+        assert_eq!(fallbacks[0].api_key.as_deref(), Some("backup-key"));
+        assert_eq!(fallbacks[1].model, "local-model");
+        assert!(fallbacks[1].api_key.is_none());
+    }
+
+    #[test]
+    fn test_tool_descriptions() {
+        let config = Config::parse(r#"
+            [tool_descriptions]
+            read_file = "Read a file. Always do this before editing it."
+        "#).unwrap();
+
+        let mut toolbox = tools::ToolBox::new();
+        toolbox.set_tool_descriptions(config.tool_descriptions).unwrap();
+
+        let read_file = toolbox.get_tools().into_iter()
+            .find(|tool| tool.function.name == "read_file")
+            .unwrap();
+        assert_eq!(read_file.function.description, "Read a file. Always do this before editing it.");
+    }
+
+    #[test]
+    fn test_composite_tools() {
+        let config = Config::parse(r#"
+            [[composite_tools]]
+            name = "copy_file"
+            description = "Copies a file"
+            parameters = { type = "object", properties = { from = { type = "string" }, to = { type = "string" } } }
+            steps = [
+                { tool = "read_file", args = { file_path = "$args.from" } },
+                { tool = "write_file", args = { file_path = "$args.to", content = "$steps.0.content" } },
+            ]
+        "#).unwrap();
+
+        let mut toolbox = tools::ToolBox::new();
+        for composite in config.composite_tools {
+            toolbox.register_composite(composite).unwrap();
+        }
+        assert!(toolbox.get_tools().iter().any(|tool| tool.function.name == "copy_file"));
+    }
+
+    #[test]
+    fn test_unknown_tool_description() {
+        let config = Config::parse(r#"
+            [tool_descriptions]
+            read_fiel = "Typo"
+        "#).unwrap();
+
+        assert!(tools::ToolBox::new().set_tool_descriptions(config.tool_descriptions).is_err());
+    }
+
+    #[test]
+    fn test_missing_env_var() {
+        let config = Config::parse(r#"
+            [headers]
+            X-Token = "${CODR_TEST_SURELY_UNSET_VAR}"
+        "#).unwrap();
+
+        assert!(config.resolved_headers().is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use std::{fs, path::Path};
+
+use tools::tool_box::ignore::IgnoreRules;
+
+/// Largest file accepted as context, so one file can't crowd out the conversation.
+pub const MAX_CONTEXT_FILE_BYTES: u64 = 100 * 1024;
+
+/// Reads `path` into a user message the model can refer to without calling a
+/// tool. Files over [`MAX_CONTEXT_FILE_BYTES`] or covered by the ignore rules
+/// of `root` are refused.
+pub fn context_message(root: &Path, path: &Path) -> Result<openai::Message, Box<dyn std::error::Error>> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    if IgnoreRules::load(root).is_ignored(relative) {
+        return Err(format!("{} is ignored", path.display()).into());
+    }
+
+    let full_path = root.join(relative);
+    let size = fs::metadata(&full_path)?.len();
+    if size > MAX_CONTEXT_FILE_BYTES {
+        return Err(format!(
+            "{} is {} bytes, over the {} byte limit for context files",
+            path.display(), size, MAX_CONTEXT_FILE_BYTES
+        ).into());
+    }
+
+    let content = fs::read_to_string(&full_path)?;
+    Ok(openai::simple_message(
+        format!("Contents of {}:\n```\n{}\n```", relative.display(), content.trim_end()),
+        openai::Role::User,
+    ))
+}
+
+/// Whether `message` is a file added by [`context_message`] rather than a prompt.
+pub fn is_context_message(message: &openai::Message) -> bool {
+    matches!(message.role, Some(openai::Role::User))
+        && message.content.as_deref().is_some_and(|content| content.starts_with("Contents of ") && content.contains(":\n```\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_message_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("target")).unwrap();
+        fs::write(dir.path().join("target/out.rs"), "generated").unwrap();
+        fs::write(dir.path().join("big.txt"), "x".repeat(MAX_CONTEXT_FILE_BYTES as usize + 1)).unwrap();
+        fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+
+        assert!(context_message(dir.path(), Path::new("target/out.rs")).is_err());
+        assert!(context_message(dir.path(), Path::new("big.txt")).is_err());
+
+        let message = context_message(dir.path(), &dir.path().join("main.rs")).unwrap();
+        assert_eq!(message.content.unwrap(), "Contents of main.rs:\n```\nfn main() {}\n```");
+    }
+}
@@ -0,0 +1,57 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+/// Price of a model in dollars per million tokens.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Pricing {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+impl Pricing {
+    pub fn cost(&self, usage: &openai::Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.input_per_million
+            + usage.completion_tokens as f64 * self.output_per_million) / 1_000_000.0
+    }
+
+    /// Rough cost of sending `messages`, before the provider reports actual usage.
+    pub fn estimate_input_cost(&self, messages: &[openai::Message]) -> f64 {
+        estimate_tokens(messages) as f64 * self.input_per_million / 1_000_000.0
+    }
+
+    /// Refuses sending `messages` if that could take a session that has `spent`
+    /// dollars so far past `limit`.
+    pub fn check_limit(&self, messages: &[openai::Message], spent: f64, limit: f64) -> Result<(), CostLimitExceeded> {
+        match spent + self.estimate_input_cost(messages) > limit {
+            true => Err(CostLimitExceeded { spent, limit }),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Approximates the token count of `messages` at about four characters per token.
+pub fn estimate_tokens(messages: &[openai::Message]) -> u64 {
+    serde_json::to_string(messages).map(|json| estimate_text_tokens(&json)).unwrap_or_default()
+}
+
+/// Approximates the token count of `text`, on the same scale as [`estimate_tokens`].
+pub fn estimate_text_tokens(text: &str) -> u64 {
+    text.chars().count().div_ceil(4) as u64
+}
+
+/// Returned instead of making a request that would push the session over its cost limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostLimitExceeded {
+    /// Dollars spent in the session so far.
+    pub spent: f64,
+    pub limit: f64,
+}
+
+impl fmt::Display for CostLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Session cost limit of ${:.4} reached: ${:.4} spent so far", self.limit, self.spent)
+    }
+}
+
+impl std::error::Error for CostLimitExceeded {}
@@ -0,0 +1,55 @@
+use std::fmt;
+
+use openai::OpenAIError;
+
+use crate::cost::CostLimitExceeded;
+
+/// Everything answering a prompt can fail with, so callers can `match` on the
+/// cause instead of downcasting.
+#[derive(Debug)]
+pub enum CodrError {
+    /// The request failed on every provider tried.
+    Api(OpenAIError),
+    /// The next request would have pushed the session over its cost limit.
+    CostLimitExceeded(CostLimitExceeded),
+    /// A tool task panicked.
+    Tool(tokio::task::JoinError),
+}
+
+impl fmt::Display for CodrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodrError::Api(e) => write!(f, "{}", e),
+            CodrError::CostLimitExceeded(e) => write!(f, "{}", e),
+            CodrError::Tool(e) => write!(f, "Tool failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CodrError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CodrError::Api(e) => Some(e),
+            CodrError::CostLimitExceeded(e) => Some(e),
+            CodrError::Tool(e) => Some(e),
+        }
+    }
+}
+
+impl From<OpenAIError> for CodrError {
+    fn from(e: OpenAIError) -> Self {
+        CodrError::Api(e)
+    }
+}
+
+impl From<CostLimitExceeded> for CodrError {
+    fn from(e: CostLimitExceeded) -> Self {
+        CodrError::CostLimitExceeded(e)
+    }
+}
+
+impl From<tokio::task::JoinError> for CodrError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        CodrError::Tool(e)
+    }
+}
@@ -0,0 +1,54 @@
+/// The system instruction asking for answers in `language`.
+pub fn instruction(language: &str) -> String {
+    format!("Always respond in {}, unless the user explicitly asks for another language.", language)
+}
+
+/// Appends the instruction for `language` to a system prompt, unless the
+/// prompt already asks for that language.
+pub fn with_language(system_prompt: &str, language: Option<&str>) -> String {
+    match language {
+        Some(language) if !specifies_language(system_prompt, language) => {
+            format!("{}\n\n{}", system_prompt, instruction(language))
+        }
+        _ => system_prompt.to_string(),
+    }
+}
+
+/// Removes an instruction added by [`with_language`] for `language`.
+pub fn without_language(system_prompt: &str, language: Option<&str>) -> String {
+    language
+        .and_then(|language| system_prompt.strip_suffix(&format!("\n\n{}", instruction(language))))
+        .unwrap_or(system_prompt)
+        .to_string()
+}
+
+fn specifies_language(system_prompt: &str, language: &str) -> bool {
+    let prompt = system_prompt.to_lowercase();
+    let language = language.to_lowercase();
+    ["respond in", "answer in", "reply in"].iter()
+        .any(|verb| prompt.contains(&format!("{} {}", verb, language)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_language() {
+        assert_eq!(with_language("You are Codr.", None), "You are Codr.");
+        assert_eq!(
+            with_language("You are Codr.", Some("German")),
+            "You are Codr.\n\nAlways respond in German, unless the user explicitly asks for another language."
+        );
+        assert_eq!(with_language("You are Codr. Answer in german.", Some("German")), "You are Codr. Answer in german.");
+    }
+
+    #[test]
+    fn test_without_language() {
+        let prompt = with_language("You are Codr.", Some("German"));
+
+        assert_eq!(without_language(&prompt, Some("German")), "You are Codr.");
+        assert_eq!(without_language(&prompt, Some("French")), prompt);
+        assert_eq!(without_language("You are Codr.", None), "You are Codr.");
+    }
+}
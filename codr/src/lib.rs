@@ -1,106 +1,688 @@
-use std::{env, fs, sync::{Arc, Mutex}};
-use tools::tool_box::ToolBox as ToolBox;
+pub mod auto_read;
+pub mod config;
+pub mod context_files;
+pub mod cost;
+pub mod error;
+pub mod language;
+pub mod manager;
+pub mod persona;
+pub mod report;
+pub mod schema;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod tool_calls;
+pub mod tool_prompt;
+pub mod transcript;
+
+pub use config::{Config, Endpoint, PartialEndpoint};
+pub use cost::{CostLimitExceeded, Pricing};
+pub use error::CodrError;
+pub use manager::{CodrManager, Session};
+pub use persona::Persona;
+pub use transcript::Transcript;
+pub use openai::{Annotation, AuthProvider, CompletionOptions, HttpConfig, Message, OpenAIClient, OpenAIError, RetryConfig, Role, StaticToken, Tool, ToolCall, assistant_tool_call_message, simple_message, tool_call_result};
+pub use tools::{CompositeStep, CompositeTool, ToolBox, ToolContext, ToolFuture, ToolHandler};
+pub use tokio_util::sync::CancellationToken;
+
+use std::{borrow::Cow, collections::HashMap, fs, sync::{Arc, Mutex}};
+use tool_calls::{PendingToolCall, ToolTraceFn, ToolTracer, assign_tool_call_ids, compact_stale_tool_results, execute_tool_calls, execute_tool_calls_deduplicated, ordered_tool_results};
+use tools::tool_box::pending_edits::PendingEdits;
+
+/// System prompt shipped with Codr, used when a resumed conversation lacks one.
+pub const DEFAULT_SYSTEM_PROMPT: &str = include_str!("../../system_prompt.md");
+
+/// Events a slow [`Codr::subscribe`] receiver may fall behind before it misses some.
+const SUBSCRIBER_CAPACITY: usize = 1024;
+
+/// The answers of two models to the same prompt, see [`Codr::compare_with_model`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub prompt: String,
+    /// Model of the conversation and the answer it gave.
+    pub original_model: String,
+    pub original: String,
+    /// Model compared against and its answer.
+    pub model: String,
+    pub response: String,
+}
+
+/// Characters of each message and tool call shown by [`Codr::dump_context`].
+const CONTEXT_DUMP_CHARS: usize = 500;
+
+/// Callback invoked with every assistant message as it is added to the conversation.
+pub type Observer = Arc<dyn Fn(&openai::Message) + Send + Sync>;
+
+/// Transformation applied to assistant content before it reaches the caller.
+pub type ResponseFilter = Arc<dyn Fn(String) -> String + Send + Sync>;
+
+/// What [`Codr::message_stream_events`] reports while a response streams in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    /// A chunk of the response text.
+    Text(String),
+    /// A chunk of a reasoning model's thinking, never part of the response text.
+    Reasoning(String),
+    /// The response parsed as JSON so far, sent each time it grows. Useful
+    /// with a JSON `response_format` to update a UI before the response is done.
+    PartialJson(serde_json::Value),
+    /// The model called a tool, which is about to run.
+    ToolRunning { id: String, name: String },
+    /// A tool finished, with the result sent back to the model.
+    ToolDone { id: String, name: String, result: String },
+    /// The finished response doesn't match the schema of a `json_schema`
+    /// `response_format`, for each reason given in `errors`.
+    SchemaViolation { errors: Vec<String> },
+    /// The request failed, or the response stopped before the model finished
+    /// it. Always the last event.
+    Error(String),
+}
 
 pub struct Codr {
     openai_client: openai::OpenAIClient,
     messages: Arc<Mutex<Vec<openai::Message>>>,
     toolbox: ToolBox,
+    observer: Option<Observer>,
+    response_filter: Option<ResponseFilter>,
+    personas: HashMap<String, Persona>,
+    parallel_tools: bool,
+    dedupe_tool_calls: bool,
+    fallbacks: Vec<openai::OpenAIClient>,
+    tool_tracer: Option<ToolTracer>,
+    pricing: Option<Pricing>,
+    max_session_cost: Option<f64>,
+    session_cost: Arc<Mutex<f64>>,
+    language: Option<String>,
+    tool_result_turns: Option<usize>,
+    events: tokio::sync::broadcast::Sender<StreamEvent>,
+    request_id: Arc<Mutex<Option<String>>>,
+    request_id_header: bool,
+    tool_list_in_prompt: bool,
+    auto_read_budget: Option<u64>,
 }
 
 impl Codr {
+    /// Sets Codr up from the environment and `codr.toml`, see [`Config::resolved_endpoint`].
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
-        let base_url = env::var("CODR_BASE_URL").expect("CODR_BASE_URL must be set");
-        let api_key = env::var("CODR_API_KEY").expect("CODR_API_KEY must be set");
-        let model = env::var("CODR_MODEL").expect("CODR_MODEL must be set");
+        let endpoint = Config::load("codr.toml")
+            .and_then(|config| config.resolved_endpoint())
+            .unwrap_or_else(|e| panic!("{}", e));
+        Self::with_endpoint(endpoint)
+    }
+
+    /// Like [`Codr::new`], but talks to `endpoint` whatever the environment says.
+    pub fn with_endpoint(endpoint: Endpoint) -> Self {
+        let Endpoint { base_url, api_key, model } = endpoint;
 
         let system_prompt = fs::read_to_string("system_prompt.md")
             .expect("Unable to read system prompt file");
 
+        let config = Config::load("codr.toml").expect("Unable to read codr.toml");
+        let headers = config.resolved_headers().expect("Invalid headers in codr.toml");
+
+        let fallbacks = config.resolved_fallbacks().expect("Invalid fallbacks in codr.toml");
+
+        // Fallbacks share every setting but the provider's own
+        let build_client = |base_url, api_key, model, tool_results_as_user| {
+            let client = openai::OpenAIClient::new(base_url, api_key, model)
+                .with_headers(headers.clone())
+                .with_tool_results_as_user(tool_results_as_user)
+                .with_legacy_functions(config.legacy_functions)
+                .with_max_tool_argument_bytes(config.max_tool_argument_bytes.unwrap_or(openai::DEFAULT_MAX_TOOL_ARGUMENT_BYTES));
+            match config.max_stream_bytes {
+                Some(max_bytes) => client.with_max_stream_bytes(max_bytes),
+                None => client,
+            }
+        };
+
+        let openai_client = build_client(base_url, api_key.clone(), model, config.tool_results_as_user);
+        let mut codr = Self::with_client(openai_client, system_prompt);
+
+        for fallback in fallbacks {
+            let api_key = fallback.api_key.unwrap_or_else(|| api_key.clone());
+            codr.add_fallback(build_client(fallback.base_url, api_key, fallback.model, fallback.tool_results_as_user));
+        }
+
+        if let Some(pricing) = config.pricing {
+            codr.set_pricing(pricing);
+        }
+        codr.set_max_session_cost(config.max_session_cost);
+        codr.set_metadata(config.metadata);
+        codr.set_language(config.language);
+        codr.set_max_tool_result_tokens(config.max_tool_result_tokens);
+        codr.set_tool_result_turns(config.tool_result_turns);
+        codr.set_dedupe_tool_calls(config.dedupe_tool_calls);
+        codr.set_request_id_header(config.request_id_header);
+        codr.set_tool_list_in_prompt(config.tool_list_in_prompt);
+        codr.set_auto_read_budget(config.auto_read_budget);
+        codr.toolbox.context_mut().interpreters.extend(config.interpreters);
+
+        for composite in config.composite_tools {
+            codr.toolbox.register_composite(composite).expect("Invalid composite_tools in codr.toml");
+        }
+        // After the composites, so their descriptions can be overridden too
+        codr.toolbox.set_tool_descriptions(config.tool_descriptions)
+            .expect("Invalid tool_descriptions in codr.toml");
+
+        for (name, persona) in config.personas {
+            codr.add_persona(name, persona);
+        }
+        if let Some(name) = config.persona {
+            codr.set_persona(&name).expect("Invalid persona in codr.toml");
+        }
+
+        codr
+    }
+
+    pub fn with_client(openai_client: openai::OpenAIClient, system_prompt: String) -> Self {
         let messages = vec![
             openai::simple_message(system_prompt, openai::Role::System),
         ];
 
+        Self::from_messages(openai_client, messages, ToolBox::new())
+    }
+
+    /// Resumes a stored conversation. If the history does not start with a
+    /// system prompt, the bundled default prompt is inserted in front of it.
+    pub fn from_messages(openai_client: openai::OpenAIClient, mut messages: Vec<openai::Message>, toolbox: ToolBox) -> Self {
+        let has_system_prompt = matches!(
+            messages.first().and_then(|message| message.role.as_ref()),
+            Some(openai::Role::System)
+        );
+
+        if !has_system_prompt {
+            messages.insert(0, openai::simple_message(DEFAULT_SYSTEM_PROMPT.to_string(), openai::Role::System));
+        }
+
         Codr {
-            openai_client: openai::OpenAIClient::new(base_url, api_key, model),
+            openai_client,
             messages: Arc::new(Mutex::new(messages)),
-            toolbox: ToolBox::new(),
+            toolbox,
+            observer: None,
+            response_filter: None,
+            personas: persona::builtin_personas(),
+            parallel_tools: false,
+            dedupe_tool_calls: false,
+            fallbacks: Vec::new(),
+            tool_tracer: None,
+            pricing: None,
+            max_session_cost: None,
+            session_cost: Arc::new(Mutex::new(0.0)),
+            language: None,
+            tool_result_turns: None,
+            events: tokio::sync::broadcast::channel(SUBSCRIBER_CAPACITY).0,
+            request_id: Arc::new(Mutex::new(None)),
+            request_id_header: false,
+            tool_list_in_prompt: false,
+            auto_read_budget: None,
+        }
+    }
+
+    /// Returns a receiver of the events of every streamed response from now on,
+    /// alongside the receiver the streaming method returns. Lets e.g. a GUI and a
+    /// logger follow the same stream. A receiver falling more than 1024 events
+    /// behind skips the oldest ones, see [`tokio::sync::broadcast`].
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<StreamEvent> {
+        self.events.subscribe()
+    }
+
+    /// Returns a copy of the conversation so far.
+    pub fn messages(&self) -> Vec<openai::Message> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    /// Describes the conversation as the model sees it, one numbered entry per
+    /// message with its role, tool calls and tool results. Long content is cut
+    /// short, so this is for reading, not for restoring the conversation.
+    pub fn dump_context(&self) -> String {
+        let mut dump = String::new();
+        for (i, message) in self.messages.lock().unwrap().iter().enumerate() {
+            let role = match message.role {
+                Some(openai::Role::System) => "system",
+                Some(openai::Role::User) => "user",
+                Some(openai::Role::Assistant) => "assistant",
+                Some(openai::Role::Tool) => "tool",
+                Some(openai::Role::Function) => "function",
+                None => "unknown",
+            };
+            dump.push_str(&format!("[{}] {}", i, role));
+            if let Some(id) = &message.tool_call_id {
+                dump.push_str(&format!(" (result of {})", id));
+            }
+            dump.push('\n');
+
+            if let Some(content) = message.content.as_deref().filter(|content| !content.is_empty()) {
+                for line in shorten(content, CONTEXT_DUMP_CHARS).lines() {
+                    dump.push_str(&format!("    {}\n", line));
+                }
+            }
+            for call in message.tool_calls.iter().flatten() {
+                dump.push_str(&format!(
+                    "    -> {} ({}): {}\n",
+                    call.function.name.as_deref().unwrap_or("?"),
+                    call.id.as_deref().unwrap_or("no id"),
+                    shorten(&call.function.arguments, CONTEXT_DUMP_CHARS),
+                ));
+            }
+        }
+        dump
+    }
+
+    /// Writes the conversation to `path` as a markdown report meant for sharing,
+    /// e.g. in a PR or issue, see [`report::render_markdown`].
+    pub fn export_markdown(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        let cost = self.pricing.map(|_| self.session_cost());
+        fs::write(path, report::render_markdown(&self.messages(), cost))
+    }
+
+    /// Asks `model` the last prompt again, on a fork of the conversation as it
+    /// was before that prompt, and returns both answers. The conversation itself
+    /// is left as it is. Tools the other model calls do run.
+    pub async fn compare_with_model(&self, model: &str) -> Result<Comparison, Box<dyn std::error::Error>> {
+        let messages = self.messages();
+        let prompt_index = messages.iter()
+            .rposition(|message| matches!(message.role, Some(openai::Role::User)))
+            .ok_or("Nothing to compare yet, send a prompt first")?;
+        let prompt = messages[prompt_index].content.clone().unwrap_or_default();
+
+        let original = messages[prompt_index + 1..].iter()
+            .filter(|message| matches!(message.role, Some(openai::Role::Assistant)))
+            .filter_map(|message| message.content.clone().filter(|content| !content.trim().is_empty()))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        let mut fork = self.fork();
+        fork.messages.lock().unwrap().truncate(prompt_index);
+        fork.openai_client = self.openai_client.clone().with_model(model.to_string());
+        // A provider failing over to another model would defeat the comparison
+        fork.fallbacks.clear();
+
+        let response = fork.message(prompt.clone()).await?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        Ok(Comparison {
+            prompt,
+            original_model: self.openai_client.model().to_string(),
+            original,
+            model: model.to_string(),
+            response,
+        })
+    }
+
+    /// Branches the conversation: the fork starts from a copy of the history and
+    /// settings but continues independently, sharing only the client and toolbox.
+    /// Edits proposed in one aren't pending in the other. Its cost is counted
+    /// from what was spent so far, separately from this session.
+    pub fn fork(&self) -> Codr {
+        let mut toolbox = self.toolbox.clone();
+        toolbox.context_mut().pending_edits = PendingEdits::default();
+        Codr {
+            openai_client: self.openai_client.clone(),
+            messages: Arc::new(Mutex::new(self.messages())),
+            toolbox,
+            observer: self.observer.clone(),
+            response_filter: self.response_filter.clone(),
+            personas: self.personas.clone(),
+            parallel_tools: self.parallel_tools,
+            dedupe_tool_calls: self.dedupe_tool_calls,
+            fallbacks: self.fallbacks.clone(),
+            tool_tracer: self.tool_tracer.clone(),
+            pricing: self.pricing,
+            max_session_cost: self.max_session_cost,
+            session_cost: Arc::new(Mutex::new(self.session_cost())),
+            language: self.language.clone(),
+            tool_result_turns: self.tool_result_turns,
+            events: tokio::sync::broadcast::channel(SUBSCRIBER_CAPACITY).0,
+            request_id: Arc::new(Mutex::new(None)),
+            request_id_header: self.request_id_header,
+            tool_list_in_prompt: self.tool_list_in_prompt,
+            auto_read_budget: self.auto_read_budget,
+        }
+    }
+
+    /// Adds a file's content to the conversation, so the model can discuss it
+    /// without reading it through a tool first. Relative paths are resolved
+    /// against the tools' workspace root.
+    pub fn add_context_file(&mut self, path: impl AsRef<std::path::Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let message = context_files::context_message(&self.toolbox.context().root, path.as_ref())?;
+        self.messages.lock().unwrap().push(message);
+        Ok(())
+    }
+
+    /// Makes a persona available to [`Codr::set_persona`], replacing any with the same name.
+    pub fn add_persona(&mut self, name: String, persona: Persona) {
+        self.personas.insert(name, persona);
+    }
+
+    /// Switches to the named persona: its system prompt replaces the current
+    /// one and its tool allowlist (if any) limits the tools offered.
+    pub fn set_persona(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let persona = match self.personas.get(name) {
+            Some(persona) => persona.clone(),
+            None => {
+                let mut names: Vec<&str> = self.personas.keys().map(String::as_str).collect();
+                names.sort();
+                return Err(format!("Unknown persona '{}', available: {}", name, names.join(", ")).into());
+            }
+        };
+
+        let mut messages = self.messages.lock().unwrap();
+        let system_prompt = language::with_language(&persona.system_prompt, self.language.as_deref());
+        messages[0] = openai::simple_message(system_prompt, openai::Role::System);
+        self.toolbox.set_allowed_tools(persona.tools);
+
+        Ok(())
+    }
+
+    /// Asks the model to answer in `language`, e.g. "German" or "pt-BR", through
+    /// the system prompt. `None` removes the instruction again.
+    pub fn set_language(&mut self, language: Option<String>) {
+        let mut messages = self.messages.lock().unwrap();
+        if let Some(system_prompt) = messages.first_mut()
+            .filter(|message| matches!(message.role, Some(openai::Role::System)))
+            .and_then(|message| message.content.as_mut()) {
+            let base = language::without_language(system_prompt, self.language.as_deref());
+            *system_prompt = language::with_language(&base, language.as_deref());
+        }
+        drop(messages);
+
+        self.language = language;
+    }
+
+    /// Sets the model's price, enabling [`Codr::session_cost`] and the session cost limit.
+    pub fn set_pricing(&mut self, pricing: Pricing) {
+        self.pricing = Some(pricing);
+    }
+
+    /// Refuses requests, with [`CostLimitExceeded`], once the next one would push
+    /// the session's cost past `limit` dollars. Needs [`Codr::set_pricing`].
+    pub fn set_max_session_cost(&mut self, limit: Option<f64>) {
+        self.max_session_cost = limit;
+    }
+
+    /// Dollars spent in this session, from the usage reported by the API.
+    pub fn session_cost(&self) -> f64 {
+        *self.session_cost.lock().unwrap()
+    }
+
+    /// Tags every request, to the primary provider and the fallbacks, with
+    /// `metadata`, e.g. a session id for server-side analytics. An empty map
+    /// stops sending it.
+    pub fn set_metadata(&mut self, metadata: HashMap<String, String>) {
+        let metadata = (!metadata.is_empty()).then_some(metadata);
+        for client in std::iter::once(&mut self.openai_client).chain(&mut self.fallbacks) {
+            let options = CompletionOptions { metadata: metadata.clone(), ..client.options().clone() };
+            *client = client.clone().with_options(options);
+        }
+    }
+
+    /// Appends a provider to try when the ones before it fail with a server,
+    /// network or quota error. The conversation carries over unchanged.
+    pub fn add_fallback(&mut self, client: openai::OpenAIClient) {
+        self.fallbacks.push(client);
+    }
+
+    /// Runs the tool calls of a single model turn concurrently. Results are
+    /// still added to the conversation in the order the model issued them.
+    pub fn set_parallel_tools(&mut self, parallel: bool) {
+        self.parallel_tools = parallel;
+    }
+
+    /// Answers a tool call identical to the one right before it from the earlier
+    /// result, with a note to stop repeating, instead of running it again.
+    pub fn set_dedupe_tool_calls(&mut self, dedupe: bool) {
+        self.dedupe_tool_calls = dedupe;
+    }
+
+    /// Appends a short list of the tools offered, with their purpose, to the
+    /// system prompt of every request, see [`tool_prompt::tool_list`]. Helps
+    /// weaker models that otherwise ignore their tools.
+    pub fn set_tool_list_in_prompt(&mut self, list: bool) {
+        self.tool_list_in_prompt = list;
+    }
+
+    /// With a budget, files the last answer mentions but the conversation hasn't
+    /// read are added as context with the next prompt, as long as they fit in
+    /// `budget` tokens, sparing the model a read. See [`auto_read::referenced_file_messages`].
+    pub fn set_auto_read_budget(&mut self, budget: Option<u64>) {
+        self.auto_read_budget = budget;
+    }
+
+    /// Context messages for the files the last answer referenced, if auto-reading is on.
+    fn auto_read_messages(&self, messages: &[openai::Message]) -> Vec<openai::Message> {
+        match self.auto_read_budget {
+            Some(budget) => auto_read::referenced_file_messages(&self.toolbox.context().root, messages, budget),
+            None => Vec::new(),
+        }
+    }
+
+    /// Sends the id of the current prompt, see [`Codr::request_id`], as an
+    /// `X-Request-Id` header with every request made to answer it.
+    pub fn set_request_id_header(&mut self, send: bool) {
+        self.request_id_header = send;
+    }
+
+    /// Id of the prompt being answered, or the last one. Every prompt gets a
+    /// fresh UUID, shared by all requests and tool calls made to answer it, so
+    /// logs of one exchange can be correlated.
+    pub fn request_id(&self) -> Option<String> {
+        self.request_id.lock().unwrap().clone()
+    }
+
+    fn start_request(&self) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        *self.request_id.lock().unwrap() = Some(id.clone());
+        id
+    }
+
+    /// `client`, sending `request_id` along if [`Codr::set_request_id_header`] is on.
+    fn tagged_client(&self, client: &openai::OpenAIClient, request_id: &str) -> openai::OpenAIClient {
+        match self.request_id_header {
+            true => client.clone().with_headers(HashMap::from([("X-Request-Id".to_string(), request_id.to_string())])),
+            false => client.clone(),
+        }
+    }
+
+    /// Caps how many tokens of each tool result are sent to the model, see
+    /// [`tool_calls::fit_to_tokens`]. `None` sends results whole.
+    pub fn set_max_tool_result_tokens(&mut self, max_tokens: Option<u64>) {
+        self.toolbox.set_max_result_tokens(max_tokens);
+    }
+
+    /// Keeps tool results in full for this many user turns, after which their
+    /// content is replaced by a short placeholder, see [`tool_calls::compact_stale_tool_results`].
+    /// `None` keeps them for the whole session.
+    pub fn set_tool_result_turns(&mut self, turns: Option<usize>) {
+        self.tool_result_turns = turns;
+    }
+
+    /// Makes edit tools return a diff instead of writing. The model then has to
+    /// call `apply_pending_edit`, which asks the confirmation handler, if any.
+    pub fn set_edit_preview(&mut self, preview: bool) {
+        self.toolbox.context_mut().preview_edits = preview;
+    }
+
+    /// Watches the workspace in the background, so the model can learn about
+    /// edits made outside the conversation through `get_file_changes`.
+    pub fn watch_files(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let ctx = self.toolbox.context();
+        ctx.file_changes.watch(&ctx.root)?;
+        Ok(())
+    }
+
+    /// Registers a callback that sees each assistant turn, in both blocking and streaming mode.
+    pub fn set_observer(&mut self, observer: Box<dyn Fn(&openai::Message) + Send + Sync>) {
+        self.observer = Some(Arc::from(observer));
+    }
+
+    /// Registers the handler answering the model's `ask_user` questions. The
+    /// conversation pauses until it returns, and its answer becomes the tool result.
+    pub fn set_user_input_handler(&mut self, handler: Box<dyn Fn(&str) -> String + Send + Sync>) {
+        self.toolbox.context_mut().user_input = Some(Arc::from(handler));
+    }
+
+    /// Registers the handler asked to approve destructive tool actions, such as
+    /// deleting a folder. Returning `false` makes the tool report that the user declined.
+    pub fn set_confirmation_handler(&mut self, handler: Box<dyn Fn(&str) -> bool + Send + Sync>) {
+        self.toolbox.context_mut().confirm = Some(Arc::from(handler));
+    }
+
+    /// Registers a callback that sees every tool call and its result, e.g. to
+    /// show a trace. Tool execution is silent otherwise.
+    pub fn set_tool_tracer(&mut self, tracer: Box<ToolTraceFn>) {
+        self.tool_tracer = Some(Arc::from(tracer));
+    }
+
+    /// Registers a filter run on assistant content before it is returned or streamed,
+    /// e.g. to redact secrets. In streaming mode it sees whole lines, so a match
+    /// must not span a line break.
+    pub fn set_response_filter(&mut self, filter: Box<dyn Fn(String) -> String + Send + Sync>) {
+        self.response_filter = Some(Arc::from(filter));
+    }
+
+    fn apply_filter(filter: &Option<ResponseFilter>, content: String) -> String {
+        match filter {
+            Some(filter) => filter(content),
+            None => content,
+        }
+    }
+
+    fn notify(observer: &Option<Observer>, message: &openai::Message) {
+        if let Some(observer) = observer {
+            observer(message);
+        }
+    }
+
+    /// Sends a prompt and runs tool calls until the model answers. Returns the
+    /// content of every assistant turn in order: text the model wrote next to
+    /// its tool calls, then the final answer.
+    ///
+    /// Takes `&self`, so an `Arc<Codr>` can answer several prompts at once. Each
+    /// prompt is answered from the history as it was when the prompt was sent,
+    /// without the exchanges still in progress, and its own exchange is appended
+    /// to the history once it ends. Concurrent prompts therefore don't see each
+    /// other's answers until they are done; send them one after the other when
+    /// they build on each other.
+    pub async fn message(&self, message: String) -> Result<Vec<Option<String>>, CodrError> {
+        let mut messages = self.messages.lock().unwrap().clone();
+        let history_len = messages.len();
+        let referenced = self.auto_read_messages(&messages);
+        messages.extend(referenced);
+        messages.push(openai::simple_message(message, openai::Role::User));
+        if let Some(turns) = self.tool_result_turns {
+            compact_stale_tool_results(&mut messages, turns);
+        }
+
+        let request_id = self.start_request();
+        let result = self.run_conversation(&mut messages, &request_id).await;
+
+        // Keep whatever the conversation reached, even if it ended in an error
+        Self::commit_exchange(&self.messages, history_len, messages);
+        result
+    }
+
+    /// Adds the exchange of a prompt to `history`: `messages` is the prompt's
+    /// private copy of the history, which had `history_len` messages when the
+    /// prompt was sent.
+    fn commit_exchange(history: &Mutex<Vec<openai::Message>>, history_len: usize, mut messages: Vec<openai::Message>) {
+        let mut history = history.lock().unwrap();
+        if history.len() == history_len {
+            *history = messages;
+        } else {
+            // Another prompt finished meanwhile; keep its exchange and add this one after it
+            history.extend(messages.drain(history_len..));
+        }
+    }
+
+    /// Requests a completion from the primary client, moving down the fallback
+    /// chain while providers fail with errors another provider could avoid.
+    async fn chat_completion(&self, messages: &[openai::Message], request_id: &str) -> Result<openai::ChatCompletion, CodrError> {
+        let tools = self.toolbox.get_tools();
+        let messages = match self.tool_list_in_prompt {
+            true => Cow::Owned(tool_prompt::with_tool_list(messages, &tools)),
+            false => Cow::Borrowed(messages),
+        };
+
+        if let (Some(pricing), Some(limit)) = (self.pricing, self.max_session_cost) {
+            pricing.check_limit(&messages, self.session_cost(), limit)?;
+        }
+
+        let mut attempt = 0;
+
+        loop {
+            let client = if attempt == 0 { &self.openai_client } else { &self.fallbacks[attempt - 1] };
+            match self.tagged_client(client, request_id).chat_completion(&messages, Some(Box::new(tools.clone()))).await {
+                Ok(response) => {
+                    if let (Some(pricing), Some(usage)) = (self.pricing, &response.usage) {
+                        *self.session_cost.lock().unwrap() += pricing.cost(usage);
+                    }
+                    return Ok(response);
+                }
+                Err(e) => {
+                    if !e.should_fail_over() || attempt == self.fallbacks.len() {
+                        return Err(e.into());
+                    }
+                    eprintln!("API Error (request {}): {}, trying the next provider", request_id, e);
+                    attempt += 1;
+                }
+            }
         }
     }
 
-    pub async fn message(&mut self, message: String) -> Result<Vec<Option<String>>, Box<dyn std::error::Error>> {
-        let mut msg_lock = self.messages.lock().unwrap();
-        msg_lock.push(openai::simple_message(message, openai::Role::User));
+    async fn run_conversation(&self, messages: &mut Vec<openai::Message>, request_id: &str) -> Result<Vec<Option<String>>, CodrError> {
         let mut results = Vec::new();
+        let mut last_call = None;
         
         loop {
-            let response = match self.openai_client.chat_completion(
-                &msg_lock, 
-                Some(Box::new(self.toolbox.get_tools()))
-            ).await {
+            let response = match self.chat_completion(messages, request_id).await {
                 Ok(resp) => resp,
                 Err(e) => {
-                    eprintln!("API Error: {}", e);
+                    eprintln!("API Error (request {}): {}", request_id, e);
                     return Err(e);
                 }
             };
             
-            if response.choices.is_empty() {
-                return Err("No choices returned from API".into());
+            let choice = response.choices.first()
+                .ok_or_else(|| OpenAIError::MalformedResponse("no choices returned from API".to_string()))?;
+
+            let message = choice.message.clone()
+                .ok_or_else(|| OpenAIError::MalformedResponse("choice is missing `message`".to_string()))?;
+
+            // Checked before the message is kept, so a malformed call leaves no unanswered tool calls behind
+            let mut calls = Vec::new();
+            for tool_call in message.tool_calls.iter().flatten() {
+                calls.push(PendingToolCall {
+                    id: tool_call.id.clone()
+                        .ok_or_else(|| OpenAIError::MalformedResponse("tool call is missing `id`".to_string()))?,
+                    name: tool_call.function.name.clone()
+                        .ok_or_else(|| OpenAIError::MalformedResponse("tool call is missing function `name`".to_string()))?,
+                    arguments: tool_call.function.arguments.clone(),
+                });
             }
             
-            let choice = &response.choices[0];
-            
-            let has_tool_calls = choice.message.clone().unwrap().tool_calls.as_ref()
-                .map(|tc| !tc.is_empty())
-                .unwrap_or(false);
-                
-            msg_lock.push(choice.message.clone().unwrap());
+            messages.push(message.clone());
+            Self::notify(&self.observer, &message);
             
-            if has_tool_calls {
-                let msg = choice.message.clone().unwrap();
-                let tool_calls = msg.tool_calls.as_ref().unwrap();
-                
-                for tool_call in tool_calls {
-                    println!("Processing tool call: {}", 
-                             tool_call.function.name.clone().unwrap());
-                    println!("Arguments: {}", tool_call.function.arguments.clone());
-                    
-                    let args = match serde_json::from_str::<serde_json::Value>(&tool_call.function.arguments.clone()) {
-                        Ok(args) => args,
-                        Err(e) => {
-                            eprintln!("Error parsing arguments: {}", e);
-
-                            // Add error message as tool result
-                            let error_result = serde_json::json!({"error": format!("Failed to parse arguments: {}", e)});
-                            msg_lock.push(openai::tool_call_result(
-                                tool_call.id.clone().unwrap(), 
-                                error_result.to_string()
-                            ));
-                            continue;
-                        }
-                    };
-                    
-                    let result = match self.toolbox.run_tool(&tool_call.function.name.clone().unwrap(), args) {
-                        Ok(res) => res,
-                        Err(e) => {
-                            eprintln!("Error running tool: {}", e);
-                            serde_json::json!({"error": e.to_string()})
-                        }
-                    };
-                    
-                    msg_lock.push(openai::tool_call_result(
-                        tool_call.id.clone().unwrap(), 
-                        result.to_string()
-                    ));
-                }
-                
-                // Continue the loop to get the final response
-                continue;
-            } else {
+            if calls.is_empty() {
                 // If there are no tool calls, add the content to results
-                results.push(choice.message.clone().unwrap().content.clone());
+                results.push(message.content.map(|content| Self::apply_filter(&self.response_filter, content)));
                 break;
             }
+
+            // Explanations sent along with tool calls are part of the answer too
+            if let Some(content) = message.content.filter(|content| !content.trim().is_empty()) {
+                results.push(Some(Self::apply_filter(&self.response_filter, content)));
+            }
+
+            let tool_results = if self.dedupe_tool_calls {
+                execute_tool_calls_deduplicated(&self.toolbox, &calls, self.parallel_tools, self.tool_tracer.as_ref(), &mut last_call).await?
+            } else {
+                execute_tool_calls(&self.toolbox, &calls, self.parallel_tools, self.tool_tracer.as_ref()).await?
+            };
+            messages.extend(ordered_tool_results(&calls, tool_results));
         }
         
         Ok(results)
@@ -108,83 +690,1604 @@ impl Codr {
 
 
     pub async fn message_stream(&self, message: String) -> tokio::sync::mpsc::Receiver<String> {
-        let mut msg_lock = self.messages.lock().unwrap();
-        msg_lock.push(openai::simple_message(message, openai::Role::User));
+        let (rx, _history) = self.message_stream_with_history(message).await;
+        rx
+    }
+
+    /// Like [`Codr::message_stream`], but additionally parses the response as
+    /// JSON while it streams and reports each more complete partial value.
+    /// Tool calls are reported too, in order with the text around them.
+    pub async fn message_stream_events(&self, message: String) -> tokio::sync::mpsc::Receiver<StreamEvent> {
+        let (mut events, _history) = self.stream_with_history(message, CancellationToken::new()).await;
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+
+        tokio::spawn(async move {
+            let mut json = openai::partial_json::PartialJson::new();
+            while let Some(event) = events.recv().await {
+                let partial = match &event {
+                    StreamEvent::Text(chunk) => json.push(chunk),
+                    _ => None,
+                };
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+                if let Some(value) = partial
+                    && tx.send(StreamEvent::PartialJson(value)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        rx
+    }
 
+    /// Like [`Codr::message_stream`], but also returns a handle resolving to the
+    /// updated conversation once the stream completes, for callers that need both
+    /// the live text and the final record.
+    pub async fn message_stream_with_history(
+        &self,
+        message: String,
+    ) -> (tokio::sync::mpsc::Receiver<String>, tokio::task::JoinHandle<Vec<Message>>) {
+        self.message_stream_cancellable(message, CancellationToken::new()).await
+    }
+
+    /// Like [`Codr::message_stream_with_history`], but stops as soon as `cancel`
+    /// is cancelled, e.g. because the user asked something else. The cancelled
+    /// prompt and everything said in response to it are dropped from the conversation.
+    pub async fn message_stream_cancellable(
+        &self,
+        message: String,
+        cancel: CancellationToken,
+    ) -> (tokio::sync::mpsc::Receiver<String>, tokio::task::JoinHandle<Vec<Message>>) {
+        let (mut events, history) = self.stream_with_history(message, cancel).await;
         let (tx, rx) = tokio::sync::mpsc::channel(100);
 
-        let mut curr_msg = msg_lock.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                // Plain text has no room for it, so it goes where the non-streaming errors go
+                if let StreamEvent::Error(error) = &event {
+                    eprintln!("Error while processing your input: {}", error);
+                }
+                if let StreamEvent::Text(chunk) = event
+                    && tx.send(chunk).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        (rx, history)
+    }
+
+    /// Starts a streamed completion on the first of `clients` that accepts it,
+    /// moving down the fallback chain like [`Codr::chat_completion`]. Once a
+    /// stream has started, failures end it instead, as its text is already out.
+    async fn start_stream(
+        clients: &[openai::OpenAIClient],
+        messages: &[openai::Message],
+        tools: Vec<openai::Tool>,
+        request_id: &str,
+    ) -> Result<tokio::sync::mpsc::Receiver<openai::StreamChannelChunk>, OpenAIError> {
+        let mut attempt = 0;
+
+        loop {
+            match clients[attempt].chat_completion_stream(messages, Some(Box::new(tools.clone()))).await {
+                Ok(receiver) => return Ok(receiver),
+                Err(e) => {
+                    if !e.should_fail_over() || attempt == clients.len() - 1 {
+                        return Err(e);
+                    }
+                    eprintln!("API Error (request {}): {}, trying the next provider", request_id, e);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Streams every event of the exchange, text and tool calls alike, which the public streaming methods filter.
+    /// Like [`Codr::message`], it works on a copy of the history and adds its exchange once it ends.
+    async fn stream_with_history(
+        &self,
+        message: String,
+        cancel: CancellationToken,
+    ) -> (tokio::sync::mpsc::Receiver<StreamEvent>, tokio::task::JoinHandle<Vec<Message>>) {
+        let mut curr_msg = self.messages();
+        let history_len = curr_msg.len();
+        let referenced = self.auto_read_messages(&curr_msg);
+        curr_msg.extend(referenced);
+        curr_msg.push(openai::simple_message(message, openai::Role::User));
+        if let Some(turns) = self.tool_result_turns {
+            compact_stale_tool_results(&mut curr_msg, turns);
+        }
 
-        drop(msg_lock); // Drop the lock to allow other threads to access it
+        let (tx, mut produced) = tokio::sync::mpsc::channel::<StreamEvent>(100);
 
-        let openai_client = self.openai_client.clone();
+        let request_id = self.start_request();
+        let clients: Vec<openai::OpenAIClient> = std::iter::once(&self.openai_client)
+            .chain(&self.fallbacks)
+            // The usage is only needed to count the cost
+            .map(|client| self.tagged_client(client, &request_id).with_stream_usage(self.pricing.is_some()))
+            .collect();
         let toolbox = self.toolbox.clone();
 
         let msg_arc = self.messages.clone();
+        let observer = self.observer.clone();
+        let mut filtered = StreamFilter::new(self.response_filter.clone());
 
-        tokio::spawn(async move {
+        let parallel_tools = self.parallel_tools;
+        let dedupe_tool_calls = self.dedupe_tool_calls;
+        let tool_list_in_prompt = self.tool_list_in_prompt;
+        let tool_tracer = self.tool_tracer.clone();
+        let (pricing, max_session_cost, session_cost) = (self.pricing, self.max_session_cost, self.session_cost.clone());
+
+        let history = tokio::spawn(async move {
+            let mut turn = 0;
+            let mut cancelled = false;
+            let mut last_call = None;
             'stream: loop {
-                let mut chunk_receiver = Box::new(openai_client.chat_completion_stream(
-                        &curr_msg, 
-                        Some(Box::new(toolbox.get_tools()))
-                ).await);
+                turn += 1;
+                let tools = toolbox.get_tools();
+                let request = match tool_list_in_prompt {
+                    true => Cow::Owned(tool_prompt::with_tool_list(&curr_msg, &tools)),
+                    false => Cow::Borrowed(&curr_msg),
+                };
+                if let (Some(pricing), Some(limit)) = (pricing, max_session_cost) {
+                    let spent = *session_cost.lock().unwrap();
+                    if let Err(e) = pricing.check_limit(&request, spent, limit) {
+                        let _ = tx.send(StreamEvent::Error(e.to_string())).await;
+                        break 'stream;
+                    }
+                }
+                let started = tokio::select! {
+                    _ = cancel.cancelled() => {
+                        cancelled = true;
+                        break 'stream;
+                    }
+                    started = Self::start_stream(&clients, &request, tools, &request_id) => started,
+                };
+                let mut chunk_receiver = match started {
+                    Ok(receiver) => receiver,
+                    Err(e) => {
+                        eprintln!("API Error (request {}): {}", request_id, e);
+                        let _ = tx.send(StreamEvent::Error(e.to_string())).await;
+                        break 'stream;
+                    }
+                };
+
+                loop {
+                    let chunk = tokio::select! {
+                        _ = cancel.cancelled() => {
+                            cancelled = true;
+                            break 'stream;
+                        }
+                        chunk = chunk_receiver.recv() => chunk,
+                    };
+                    let Some(chunk) = chunk else {
+                        break;
+                    };
+                    if let (Some(pricing), Some(usage)) = (pricing, &chunk.usage) {
+                        *session_cost.lock().unwrap() += pricing.cost(usage);
+                    }
 
-                while let Some(chunk) = chunk_receiver.recv().await {
                     if chunk.finished {
-                        curr_msg.push(openai::simple_message(
-                            chunk.final_content.unwrap(),
-                            openai::Role::Assistant
-                        ));
+                        let rest = filtered.finish();
+                        if !rest.is_empty() {
+                            let _ = tx.send(StreamEvent::Text(rest)).await;
+                        }
+                        let content = chunk.final_content.unwrap();
+                        // Models can drift from the schema despite the constraint
+                        if let Some(schema) = clients[0].options().response_format.as_ref().and_then(schema::response_schema) {
+                            let errors = schema::violations(schema, &content);
+                            if !errors.is_empty() {
+                                let _ = tx.send(StreamEvent::SchemaViolation { errors }).await;
+                            }
+                        }
+
+                        let mut final_message = openai::simple_message(content, openai::Role::Assistant);
+                        // Kept for transcripts, never sent back to the model
+                        final_message.reasoning_content = chunk.final_reasoning;
+                        Self::notify(&observer, &final_message);
+                        curr_msg.push(final_message);
 
-                        let mut msg_lock = msg_arc.lock().unwrap();
-                        *msg_lock = curr_msg.clone();
+                        if let Some(error) = chunk.error {
+                            let _ = tx.send(StreamEvent::Error(error)).await;
+                        }
                         break 'stream;
                     }
 
+                    let argument_error = chunk.error;
                     for choice in chunk.choices {
                         if let Some(message) = choice.delta {
-                            if let Some(tool_calls) = message.clone().tool_calls {
+                            if let Some(mut tool_calls) = message.tool_calls.clone() {
+                                let rest = filtered.finish();
+                                if !rest.is_empty() {
+                                    let _ = tx.send(StreamEvent::Text(rest)).await;
+                                }
+                                assign_tool_call_ids(&mut tool_calls, turn);
+                                let message = openai::assistant_tool_call_message(tool_calls.clone());
                                 curr_msg.push(message.clone());
+                                Self::notify(&observer, &message);
 
-                                for tool_call in tool_calls {
-                                    println!("Processing tool call: {}", 
-                                        tool_call.function.name.clone().unwrap());
-                                    println!("Arguments: {}", tool_call.function.arguments.clone());
-
-                                    let args = match serde_json::from_str::<serde_json::Value>(&tool_call.function.arguments.clone()) {
-                                        Ok(args) => args,
-                                        Err(e) => {
-                                            eprintln!("Error parsing arguments: {}", e);
-                                            continue;
-                                        }
-                                    };
-
-                                    let result = match toolbox.run_tool(&tool_call.function.name.clone().unwrap(), args) {
-                                        Ok(res) => res,
-                                        Err(e) => {
-                                            eprintln!("Error running tool: {}", e);
-                                            serde_json::json!({"error": e.to_string()})
-                                        }
-                                    };
-
-                                    curr_msg.push(openai::tool_call_result(
-                                            tool_call.id.clone().unwrap(), 
-                                            result.to_string()
-                                    ));
-                                    continue 'stream;
+                                let calls: Vec<PendingToolCall> = tool_calls.into_iter()
+                                    .map(|tool_call| PendingToolCall {
+                                        id: tool_call.id.unwrap_or_default(),
+                                        name: tool_call.function.name.unwrap_or_default(),
+                                        arguments: tool_call.function.arguments,
+                                    })
+                                    .collect();
+
+                                if cancel.is_cancelled() {
+                                    cancelled = true;
+                                    break 'stream;
                                 }
-                            }
-                            if let Some(content) = message.content {
-                                if let Err(e) = tx.send(content.clone()).await {
-                                    eprintln!("Error sending message: {}", e);
+                                for call in &calls {
+                                    let _ = tx.send(StreamEvent::ToolRunning { id: call.id.clone(), name: call.name.clone() }).await;
                                 }
+                                let running = async {
+                                    if let Some(error) = &argument_error {
+                                        // The arguments were dropped, so there is nothing to run
+                                        Ok(calls.iter().map(|call| (call.id.clone(), serde_json::json!({"error": error}).to_string())).collect())
+                                    } else if dedupe_tool_calls {
+                                        execute_tool_calls_deduplicated(&toolbox, &calls, parallel_tools, tool_tracer.as_ref(), &mut last_call).await
+                                    } else {
+                                        execute_tool_calls(&toolbox, &calls, parallel_tools, tool_tracer.as_ref()).await
+                                    }
+                                };
+                                // Tools already running can't be interrupted, but their results are dropped
+                                // along with the rest of the cancelled exchange
+                                let tool_results = tokio::select! {
+                                    _ = cancel.cancelled() => {
+                                        cancelled = true;
+                                        break 'stream;
+                                    }
+                                    results = running => results,
+                                };
+                                let tool_results = tool_results
+                                    .unwrap_or_else(|e| {
+                                        eprintln!("Error running tools: {}", e);
+                                        HashMap::new()
+                                    });
+                                let results = ordered_tool_results(&calls, tool_results);
+                                for (call, result) in calls.iter().zip(&results) {
+                                    let _ = tx.send(StreamEvent::ToolDone {
+                                        id: call.id.clone(),
+                                        name: call.name.clone(),
+                                        result: result.content.clone().unwrap_or_default(),
+                                    }).await;
+                                }
+                                curr_msg.extend(results);
+                                continue 'stream;
+                            }
+                            // A delta may carry both; reasoning comes first, as the model thought it before answering
+                            if let Some(reasoning) = message.reasoning_content.filter(|reasoning| !reasoning.is_empty()) {
+                                let _ = tx.send(StreamEvent::Reasoning(reasoning)).await;
+                            }
+                            if let Some(content) = message.content.map(|content| filtered.push(&content)).filter(|content| !content.is_empty())
+                                && let Err(e) = tx.send(StreamEvent::Text(content)).await {
+                                eprintln!("Error sending message: {}", e);
                             }
                         }
                     }
                 }
+
+                // The stream ended without finishing, don't retry it
+                let rest = filtered.finish();
+                if !rest.is_empty() {
+                    let _ = tx.send(StreamEvent::Text(rest)).await;
+                }
+                let _ = tx.send(StreamEvent::Error("The response ended before it finished".to_string())).await;
+                break;
             }
+
+            // Like a prompt that fails, a stream that fails keeps what it reached; a cancelled one keeps nothing
+            if cancelled {
+                curr_msg.truncate(history_len);
+            } else {
+                Self::commit_exchange(&msg_arc, history_len, curr_msg.clone());
+            }
+
+            curr_msg
         });
 
-        rx
+        // Fan every event out to subscribers as well as to the caller
+        let (forward, rx) = tokio::sync::mpsc::channel(100);
+        let subscribers = self.events.clone();
+        tokio::spawn(async move {
+            while let Some(event) = produced.recv().await {
+                // No subscribers is not an error
+                let _ = subscribers.send(event.clone());
+                let _ = forward.send(event).await;
+            }
+        });
+
+        (rx, history)
+    }
+}
+
+/// Runs the response filter over streamed text a line at a time, so a match
+/// split across chunks is still caught. The unfinished last line is held back
+/// until it ends or the answer does.
+struct StreamFilter {
+    filter: Option<ResponseFilter>,
+    held: String,
+}
+
+impl StreamFilter {
+    fn new(filter: Option<ResponseFilter>) -> Self {
+        StreamFilter { filter, held: String::new() }
+    }
+
+    /// Appends a chunk and returns the filtered text of the lines it completed.
+    fn push(&mut self, chunk: &str) -> String {
+        let Some(filter) = &self.filter else {
+            return chunk.to_string();
+        };
+        self.held.push_str(chunk);
+        match self.held.rfind('\n') {
+            Some(end) => filter(self.held.drain(..=end).collect()),
+            None => String::new(),
+        }
+    }
+
+    /// Returns the filtered text held back.
+    fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.held);
+        if rest.is_empty() {
+            return rest;
+        }
+        Codr::apply_filter(&self.filter, rest)
+    }
+}
+
+/// Cuts `text` to `max_chars` characters, noting how much was left out.
+fn shorten(text: &str, max_chars: usize) -> String {
+    let total = text.chars().count();
+    if total <= max_chars {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(max_chars).collect();
+    format!("{}... ({} more characters)", kept, total - max_chars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai::test_util::{MockResponse, MockServer, stream_chunk, stream_usage_chunk, text_completion, text_completion_with_usage, tool_call_completion};
+
+    #[test]
+    fn test_facade_exports() {
+        use crate::{Codr, Config, Message, OpenAIClient, OpenAIError, Role, Tool, ToolBox, simple_message};
+
+        let message: Message = simple_message("Hi".to_string(), Role::User);
+        let tools: Vec<Tool> = ToolBox::new().get_tools();
+        let client = OpenAIClient::new("http://localhost".to_string(), "key".to_string(), "model".to_string());
+        let codr = Codr::with_client(client, "You are a test".to_string());
+        let error = OpenAIError::MalformedResponse("test".to_string());
+
+        assert!(message.content.is_some());
+        assert!(!tools.is_empty());
+        assert!(Config::default().headers.is_empty());
+        assert!(error.to_string().contains("test"));
+        drop(codr);
+    }
+
+    #[tokio::test]
+    async fn test_message_without_choice_message() {
+        let server = MockServer::start(vec![
+            MockResponse::json(serde_json::json!({
+                "choices": [{
+                    "delta": {"role": "assistant", "content": "Hello"},
+                    "finish_reason": "stop"
+                }]
+            })),
+        ]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let err = codr.message("Hi".to_string()).await.unwrap_err();
+
+        match err {
+            CodrError::Api(OpenAIError::MalformedResponse(details)) => assert!(details.contains("message")),
+            other => panic!("Expected MalformedResponse, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_tool_call_is_not_kept() {
+        let server = MockServer::start(vec![
+            MockResponse::json(serde_json::json!({
+                "choices": [{
+                    "message": {
+                        "role": "assistant",
+                        "tool_calls": [{"type": "function", "function": {"name": "read_file", "arguments": "{}"}}]
+                    },
+                    "finish_reason": "tool_calls"
+                }]
+            })),
+        ]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let err = codr.message("Hi".to_string()).await.unwrap_err();
+
+        assert!(matches!(err, CodrError::Api(OpenAIError::MalformedResponse(_))));
+        assert!(codr.messages().iter().all(|message| message.tool_calls.is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_observer_fires_once_per_assistant_turn() {
+        let server = MockServer::start(vec![
+            tool_call_completion("call_1", "read_file", serde_json::json!({"file_path": "Cargo.toml"})),
+            text_completion("Done"),
+        ]).await;
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let observed = seen.clone();
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.set_observer(Box::new(move |message| {
+            observed.lock().unwrap().push(message.clone());
+        }));
+
+        let results = codr.message("Read the manifest".to_string()).await.unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert!(seen[0].tool_calls.is_some());
+        assert_eq!(seen[1].content.as_deref(), Some("Done"));
+        assert_eq!(results, vec![Some("Done".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_interim_content_with_tool_calls() {
+        let server = MockServer::start(vec![
+            MockResponse::json(serde_json::json!({
+                "choices": [{
+                    "message": {
+                        "role": "assistant",
+                        "content": "Let me check the manifest.",
+                        "tool_calls": [{
+                            "id": "call_1",
+                            "type": "function",
+                            "function": {"name": "read_file", "arguments": "{\"file_path\": \"Cargo.toml\"}"}
+                        }]
+                    },
+                    "finish_reason": "tool_calls"
+                }]
+            })),
+            text_completion("It is a workspace."),
+        ]).await;
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let observed = seen.clone();
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.set_observer(Box::new(move |message| {
+            observed.lock().unwrap().push(message.clone());
+        }));
+
+        let results = codr.message("What is in the manifest?".to_string()).await.unwrap();
+
+        assert_eq!(results, vec![Some("Let me check the manifest.".to_string()), Some("It is a workspace.".to_string())]);
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen[0].content.as_deref(), Some("Let me check the manifest."));
+        assert!(seen[0].tool_calls.is_some());
+    }
+
+    fn redact_keys(content: String) -> String {
+        regex::Regex::new(r"sk-[A-Za-z0-9]+").unwrap()
+            .replace_all(&content, "[REDACTED]")
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_response_filter() {
+        let server = MockServer::start(vec![text_completion("Your key is sk-abc123")]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.set_response_filter(Box::new(redact_keys));
+
+        let results = codr.message("What is my key?".to_string()).await.unwrap();
+        assert_eq!(results, vec![Some("Your key is [REDACTED]".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_response_filter_streaming() {
+        let server = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"role": "assistant", "content": "Your key is sk-abc123"}), None),
+            stream_chunk(serde_json::json!({}), Some("stop")),
+        ])]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.set_response_filter(Box::new(redact_keys));
+
+        let mut receiver = codr.message_stream("What is my key?".to_string()).await;
+        let mut streamed = String::new();
+        while let Some(chunk) = receiver.recv().await {
+            streamed.push_str(&chunk);
+        }
+
+        assert_eq!(streamed, "Your key is [REDACTED]");
+    }
+
+    #[tokio::test]
+    async fn test_response_filter_streaming_split_match() {
+        let server = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"role": "assistant", "content": "Your key is sk-ab"}), None),
+            stream_chunk(serde_json::json!({"content": "c123\nKeep it safe, sk-"}), None),
+            stream_chunk(serde_json::json!({"content": "def456"}), None),
+            stream_chunk(serde_json::json!({}), Some("stop")),
+        ])]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.set_response_filter(Box::new(redact_keys));
+
+        let mut receiver = codr.message_stream("What is my key?".to_string()).await;
+        let mut streamed = String::new();
+        while let Some(chunk) = receiver.recv().await {
+            streamed.push_str(&chunk);
+        }
+
+        assert_eq!(streamed, "Your key is [REDACTED]\nKeep it safe, [REDACTED]");
+    }
+
+    #[tokio::test]
+    async fn test_message_stream_with_history() {
+        let server = MockServer::start(vec![
+            MockResponse::sse(vec![
+                stream_chunk(serde_json::json!({"role": "assistant", "tool_calls": [{
+                    "index": 0,
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "read_file", "arguments": "{\"file_path\": \"missing.txt\"}"}
+                }]}), None),
+                stream_chunk(serde_json::json!({}), Some("tool_calls")),
+            ]),
+            MockResponse::sse(vec![
+                stream_chunk(serde_json::json!({"role": "assistant", "content": "No such file"}), None),
+                stream_chunk(serde_json::json!({}), Some("stop")),
+            ]),
+        ]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let (mut receiver, history) = codr.message_stream_with_history("Read missing.txt".to_string()).await;
+        let mut streamed = String::new();
+        while let Some(chunk) = receiver.recv().await {
+            streamed.push_str(&chunk);
+        }
+        let history = history.await.unwrap();
+
+        assert_eq!(streamed, "No such file");
+        assert_eq!(history.len(), 5);
+        assert_eq!(history[2].tool_calls.as_ref().unwrap()[0].id.as_deref(), Some("call_1"));
+        assert_eq!(history[3].tool_call_id.as_deref(), Some("call_1"));
+        assert_eq!(history[4].content.as_deref(), Some("No such file"));
+        assert_eq!(codr.messages().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_after_server_error() {
+        let primary = MockServer::start(vec![
+            MockResponse::status(503, r#"{"error": "overloaded"}"#),
+            MockResponse::status(503, r#"{"error": "overloaded"}"#),
+        ]).await;
+        let secondary = MockServer::start(vec![text_completion("From the secondary")]).await;
+
+        let mut codr = Codr::with_client(primary.client(), "You are a test".to_string());
+        codr.add_fallback(secondary.client());
+        let results = codr.message("Hello".to_string()).await.unwrap();
+
+        assert_eq!(results, vec![Some("From the secondary".to_string())]);
+        assert_eq!(primary.requests().len(), 1);
+        assert_eq!(secondary.requests()[0].json()["messages"][1]["content"], "Hello");
+    }
+
+    #[tokio::test]
+    async fn test_stream_fallback_after_server_error() {
+        let primary = MockServer::start(vec![MockResponse::status(503, r#"{"error": "overloaded"}"#)]).await;
+        let secondary = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"content": "From the secondary"}), None),
+            stream_chunk(serde_json::json!({}), Some("stop")),
+        ])]).await;
+
+        let mut codr = Codr::with_client(primary.client(), "You are a test".to_string());
+        codr.add_fallback(secondary.client());
+        let (mut receiver, history) = codr.message_stream_with_history("Hello".to_string()).await;
+        let mut streamed = String::new();
+        while let Some(chunk) = receiver.recv().await {
+            streamed.push_str(&chunk);
+        }
+
+        assert_eq!(streamed, "From the secondary");
+        assert_eq!(history.await.unwrap().last().unwrap().content.as_deref(), Some("From the secondary"));
+        assert_eq!(primary.requests().len(), 1);
+        assert_eq!(secondary.requests()[0].json()["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn test_no_fallback_after_client_error() {
+        let primary = MockServer::start(vec![MockResponse::status(400, r#"{"error": "bad request"}"#)]).await;
+        let secondary = MockServer::start(vec![text_completion("From the secondary")]).await;
+
+        let mut codr = Codr::with_client(primary.client(), "You are a test".to_string());
+        codr.add_fallback(secondary.client());
+
+        assert!(codr.message("Hello".to_string()).await.is_err());
+        assert!(secondary.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let recording = dir.path().join("session.jsonl");
+        let server = MockServer::start(vec![
+            tool_call_completion("call_1", "read_file", serde_json::json!({"file_path": "missing.txt"})),
+            text_completion("That file does not exist"),
+        ]).await;
+
+        let client = server.client().with_recorder(&recording).unwrap();
+        let codr = Codr::with_client(client, "You are a test".to_string());
+        let recorded = codr.message("Read missing.txt".to_string()).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&recording).unwrap().lines().count(), 2);
+
+        // Nothing listens on the discard port, so any network access would fail
+        let client = OpenAIClient::new("http://127.0.0.1:9".to_string(), "key".to_string(), "model".to_string())
+            .with_replay(&recording)
+            .unwrap();
+        let codr = Codr::with_client(client, "You are a test".to_string());
+        let replayed = codr.message("Read missing.txt".to_string()).await.unwrap();
+
+        assert_eq!(replayed, recorded);
+        assert_eq!(replayed, vec![Some("That file does not exist".to_string())]);
+        assert_eq!(codr.messages().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_conversation_from_fixtures() {
+        let dir = tempfile::tempdir().unwrap();
+        let fixtures = dir.path().join("fixtures.json");
+        std::fs::write(&fixtures, serde_json::json!([
+            {"prompt": "Create hello.txt", "tool_calls": [{"name": "write_file", "arguments": {"file_path": "hello.txt", "content": "Hello!"}}]},
+            {"prompt": "Create hello.txt", "tool_calls": [{"name": "read_file", "arguments": {"file_path": dir.path().join("hello.txt")}}]},
+            {"prompt": "Create hello.txt", "content": "Created hello.txt"}
+        ]).to_string()).unwrap();
+
+        let client = OpenAIClient::from_fixtures(&fixtures).unwrap();
+        let mut codr = Codr::with_client(client, "You are a test".to_string());
+        *codr.toolbox.context_mut() = ToolContext::new(dir.path());
+
+        let answer = codr.message("Create hello.txt".to_string()).await.unwrap();
+
+        assert_eq!(answer, vec![Some("Created hello.txt".to_string())]);
+        assert_eq!(std::fs::read_to_string(dir.path().join("hello.txt")).unwrap(), "Hello!");
+        let read = codr.messages().iter()
+            .find(|message| message.tool_call_id.as_deref() == Some("call_2"))
+            .and_then(|message| message.content.clone())
+            .unwrap();
+        assert!(read.contains("Hello!"));
+        assert!(codr.message("Anything else?".to_string()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ask_user_resumes_with_answer() {
+        let server = MockServer::start(vec![
+            tool_call_completion("call_1", "ask_user", serde_json::json!({"question": "Which file should I fix?"})),
+            text_completion("Fixing src/main.rs"),
+        ]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.set_user_input_handler(Box::new(|question| {
+            assert_eq!(question, "Which file should I fix?");
+            "src/main.rs".to_string()
+        }));
+        let results = codr.message("Fix the bug".to_string()).await.unwrap();
+
+        assert_eq!(results, vec![Some("Fixing src/main.rs".to_string())]);
+        let tool_result = server.requests()[1].json()["messages"][3].clone();
+        assert_eq!(tool_result["tool_call_id"], "call_1");
+        assert_eq!(tool_result["content"], r#"{"answer":"src/main.rs"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_stream_tool_call_without_id() {
+        let server = MockServer::start(vec![
+            MockResponse::sse(vec![
+                stream_chunk(serde_json::json!({"role": "assistant", "tool_calls": [{
+                    "index": 0,
+                    "type": "function",
+                    "function": {"name": "read_file", "arguments": "{\"file_path\": \"missing.txt\"}"}
+                }]}), None),
+                stream_chunk(serde_json::json!({}), Some("tool_calls")),
+            ]),
+            MockResponse::sse(vec![
+                stream_chunk(serde_json::json!({"role": "assistant", "content": "No such file"}), None),
+                stream_chunk(serde_json::json!({}), Some("stop")),
+            ]),
+        ]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let (mut receiver, history) = codr.message_stream_with_history("Read missing.txt".to_string()).await;
+        while receiver.recv().await.is_some() {}
+        let history = history.await.unwrap();
+
+        let id = history[2].tool_calls.as_ref().unwrap()[0].id.clone().unwrap();
+        assert!(!id.is_empty());
+        assert_eq!(history[3].tool_call_id.as_deref(), Some(id.as_str()));
+        assert_eq!(history[4].content.as_deref(), Some("No such file"));
+    }
+
+    #[tokio::test]
+    async fn test_message_stream_error() {
+        let server = MockServer::start(vec![MockResponse::status(401, "bad key")]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let mut receiver = codr.message_stream_events("Hi".to_string()).await;
+        let mut events = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            events.push(event);
+        }
+
+        assert_eq!(events, vec![StreamEvent::Error("Authentication failed: bad key".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_message_stream_partial_json() {
+        let server = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"role": "assistant", "content": "{\"files\": [\"src/ma"}), None),
+            stream_chunk(serde_json::json!({"content": "in.rs\", \"README"}), None),
+            stream_chunk(serde_json::json!({"content": ".md\"]}"}), None),
+            stream_chunk(serde_json::json!({}), Some("stop")),
+        ])]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let mut events = codr.message_stream_events("List the files".to_string()).await;
+        let mut partials = Vec::new();
+        let mut text = String::new();
+        while let Some(event) = events.recv().await {
+            match event {
+                StreamEvent::Text(chunk) => text.push_str(&chunk),
+                StreamEvent::PartialJson(value) => partials.push(value),
+                event => panic!("Unexpected event {:?}", event),
+            }
+        }
+
+        assert_eq!(partials, vec![
+            serde_json::json!({"files": ["src/ma"]}),
+            serde_json::json!({"files": ["src/main.rs", "README"]}),
+            serde_json::json!({"files": ["src/main.rs", "README.md"]}),
+        ]);
+        assert_eq!(text, r#"{"files": ["src/main.rs", "README.md"]}"#);
+    }
+
+    #[tokio::test]
+    async fn test_message_stream_cancelled() {
+        let server = MockServer::start(vec![
+            MockResponse::sse(vec![
+                stream_chunk(serde_json::json!({"role": "assistant", "content": "Once upon"}), None),
+                stream_chunk(serde_json::json!({"content": " a time"}), None),
+                stream_chunk(serde_json::json!({}), Some("stop")),
+            ]).with_chunk_delay(std::time::Duration::from_secs(5)),
+        ]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let cancel = CancellationToken::new();
+        let (mut receiver, history) = codr.message_stream_cancellable("Tell a long story".to_string(), cancel.clone()).await;
+
+        assert_eq!(receiver.recv().await.as_deref(), Some("Once upon"));
+        cancel.cancel();
+        let history = tokio::time::timeout(std::time::Duration::from_secs(1), history).await.unwrap().unwrap();
+
+        assert!(receiver.recv().await.is_none());
+        assert_eq!(history.len(), 1);
+        assert_eq!(codr.messages().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_message_stream_cancelled_during_tool_call() {
+        let server = MockServer::start(vec![
+            MockResponse::sse(vec![
+                stream_chunk(serde_json::json!({"tool_calls": [{
+                    "index": 0,
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "ask_user", "arguments": "{\"question\": \"Which file?\"}"}
+                }]}), None),
+                stream_chunk(serde_json::json!({}), Some("tool_calls")),
+            ]),
+        ]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let started_tx = Mutex::new(started_tx);
+        codr.set_user_input_handler(Box::new(move |_| {
+            let _ = started_tx.lock().unwrap().send(());
+            std::thread::sleep(std::time::Duration::from_secs(2));
+            "main.rs".to_string()
+        }));
+        let cancel = CancellationToken::new();
+        let (_receiver, history) = codr.message_stream_cancellable("Fix the bug".to_string(), cancel.clone()).await;
+
+        tokio::task::spawn_blocking(move || started_rx.recv().unwrap()).await.unwrap();
+        cancel.cancel();
+        let history = tokio::time::timeout(std::time::Duration::from_secs(1), history).await.unwrap().unwrap();
+
+        assert_eq!(history.len(), 1);
+        assert_eq!(codr.messages().len(), 1);
+        assert!(!codr.messages().iter().any(|message| matches!(message.role, Some(openai::Role::Tool))));
+    }
+
+    #[tokio::test]
+    async fn test_export_markdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = MockServer::start(vec![
+            tool_call_completion("call_1", "read_file", serde_json::json!({"file_path": "missing.txt"})),
+            text_completion_with_usage("The file doesn't exist.", 1_000_000, 0),
+        ]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.set_pricing(Pricing { input_per_million: 2.0, output_per_million: 8.0 });
+        codr.message("Read missing.txt".to_string()).await.unwrap();
+        let path = dir.path().join("session.md");
+        codr.export_markdown(&path).unwrap();
+        let report = fs::read_to_string(&path).unwrap();
+
+        assert!(report.starts_with("# Codr session\n"));
+        assert!(report.contains("- **Messages:** 5\n"));
+        assert!(report.contains("- **Tool calls:** 1\n"));
+        assert!(report.contains("- **Cost:** $2.0000\n"));
+        assert!(report.contains("<summary>System prompt</summary>"));
+        assert!(report.contains("## User\n\nRead missing.txt\n"));
+        assert!(report.contains("<summary>Tool call: <code>read_file</code></summary>"));
+        assert!(report.contains("\"file_path\": \"missing.txt\""));
+        assert!(report.contains("## Assistant\n\nThe file doesn't exist.\n"));
+        // The stats come before the conversation
+        assert!(report.find("**Cost:**").unwrap() < report.find("## User").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_messages_through_arc() {
+        let server = MockServer::start(vec![
+            text_completion("First answer"),
+            text_completion("Second answer"),
+        ]).await;
+
+        let codr = Arc::new(Codr::with_client(server.client(), "You are a test".to_string()));
+        let prompts = ["Question A", "Question B"].map(|prompt| {
+            let codr = codr.clone();
+            tokio::spawn(async move { codr.message(prompt.to_string()).await.unwrap() })
+        });
+        let mut answers = Vec::new();
+        for prompt in prompts {
+            answers.extend(prompt.await.unwrap().into_iter().flatten());
+        }
+        answers.sort();
+        assert_eq!(answers, vec!["First answer", "Second answer"]);
+
+        // Both exchanges are kept whole, each prompt followed by its answer
+        let messages = codr.messages();
+        assert_eq!(messages.len(), 5);
+        for exchange in messages[1..].chunks(2) {
+            assert!(matches!(exchange[0].role, Some(Role::User)));
+            assert!(matches!(exchange[1].role, Some(Role::Assistant)));
+        }
+        // Neither prompt saw the other
+        for request in server.requests() {
+            assert_eq!(request.json()["messages"].as_array().unwrap().len(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_message_stream_start_failure_keeps_prompt() {
+        let server = MockServer::start(vec![MockResponse::status(400, "Bad request")]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let (mut receiver, history) = codr.message_stream_with_history("Hello".to_string()).await;
+        while receiver.recv().await.is_some() {}
+        let history = history.await.unwrap();
+
+        // The same as `message` keeps after a failed request
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].content.as_deref(), Some("Hello"));
+        assert_eq!(codr.messages().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_message_stream_cut_off_keeps_tool_results() {
+        let server = MockServer::start(vec![
+            MockResponse::sse(vec![
+                stream_chunk(serde_json::json!({"role": "assistant", "tool_calls": [{
+                    "index": 0,
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "read_file", "arguments": "{\"file_path\": \"missing.txt\"}"}
+                }]}), None),
+                stream_chunk(serde_json::json!({}), Some("tool_calls")),
+            ]),
+            // Ends without a finish reason or [DONE]
+            MockResponse::raw_stream(vec![
+                format!("data: {}\n\n", stream_chunk(serde_json::json!({"role": "assistant", "content": "The file"}), None)),
+            ]),
+        ]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let (mut receiver, history) = codr.message_stream_with_history("Read missing.txt".to_string()).await;
+        while receiver.recv().await.is_some() {}
+        let history = history.await.unwrap();
+
+        assert_eq!(history.len(), 5);
+        assert_eq!(history[3].tool_call_id.as_deref(), Some("call_1"));
+        assert_eq!(history[4].content.as_deref(), Some("The file"));
+        let messages = codr.messages();
+        assert_eq!(messages.len(), history.len());
+        assert_eq!(messages[3].tool_call_id, history[3].tool_call_id);
+    }
+
+    #[tokio::test]
+    async fn test_stream_and_message_through_arc() {
+        let server = MockServer::start(vec![
+            MockResponse::sse(vec![
+                stream_chunk(serde_json::json!({"role": "assistant", "content": "Streamed "}), None),
+                stream_chunk(serde_json::json!({"content": "answer"}), None),
+                stream_chunk(serde_json::json!({}), Some("stop")),
+            ]).with_chunk_delay(std::time::Duration::from_millis(100)),
+            text_completion("Plain answer"),
+        ]).await;
+
+        let codr = Arc::new(Codr::with_client(server.client(), "You are a test".to_string()));
+        let (mut receiver, history) = codr.message_stream_with_history("Stream it".to_string()).await;
+        let mut streamed = receiver.recv().await.unwrap();
+
+        // Sent and answered while the stream is still going
+        let answer = codr.message("Answer plainly".to_string()).await.unwrap();
+        assert_eq!(answer, vec![Some("Plain answer".to_string())]);
+        assert_eq!(server.requests()[1].json()["messages"].as_array().unwrap().len(), 2);
+
+        while let Some(chunk) = receiver.recv().await {
+            streamed.push_str(&chunk);
+        }
+        history.await.unwrap();
+        assert_eq!(streamed, "Streamed answer");
+
+        let contents: Vec<String> = codr.messages().iter().skip(1).map(|m| m.content.clone().unwrap_or_default()).collect();
+        assert_eq!(contents, vec!["Answer plainly", "Plain answer", "Stream it", "Streamed answer"]);
+    }
+
+    #[tokio::test]
+    async fn test_dump_context() {
+        let server = MockServer::start(vec![
+            tool_call_completion("call_1", "read_file", serde_json::json!({"file_path": "missing.txt"})),
+            text_completion(&"x".repeat(2000)),
+        ]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.message("Read missing.txt".to_string()).await.unwrap();
+        let dump = codr.dump_context();
+
+        assert!(dump.contains("[0] system\n    You are a test"));
+        assert!(dump.contains("[1] user\n    Read missing.txt"));
+        assert!(dump.contains("[2] assistant\n    -> read_file (call_1): "));
+        assert!(dump.contains("[3] tool (result of call_1)"));
+        assert!(dump.contains("[4] assistant"));
+        assert!(dump.contains("... (1500 more characters)"));
+    }
+
+    #[tokio::test]
+    async fn test_message_stream_tool_events() {
+        let server = MockServer::start(vec![
+            MockResponse::sse(vec![
+                stream_chunk(serde_json::json!({"role": "assistant", "content": "Let me look"}), None),
+                stream_chunk(serde_json::json!({"tool_calls": [{
+                    "index": 0,
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "read_file", "arguments": "{\"file_path\": \"missing.txt\"}"}
+                }]}), None),
+                stream_chunk(serde_json::json!({}), Some("tool_calls")),
+            ]),
+            MockResponse::sse(vec![
+                stream_chunk(serde_json::json!({"role": "assistant", "content": "No such file"}), None),
+                stream_chunk(serde_json::json!({}), Some("stop")),
+            ]),
+        ]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let mut receiver = codr.message_stream_events("Read missing.txt".to_string()).await;
+        let mut events = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            events.push(event);
+        }
+
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0], StreamEvent::Text("Let me look".to_string()));
+        assert_eq!(events[1], StreamEvent::ToolRunning { id: "call_1".to_string(), name: "read_file".to_string() });
+        match &events[2] {
+            StreamEvent::ToolDone { id, name, result } => {
+                assert_eq!((id.as_str(), name.as_str()), ("call_1", "read_file"));
+                assert!(result.contains("error"));
+            }
+            other => panic!("Expected ToolDone, got {:?}", other),
+        }
+        assert_eq!(events[3], StreamEvent::Text("No such file".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_stream_oversized_tool_arguments_not_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.txt");
+        let arguments = serde_json::json!({"file_path": path, "content": "x".repeat(500)}).to_string();
+        let server = MockServer::start(vec![
+            MockResponse::sse(vec![
+                stream_chunk(serde_json::json!({"tool_calls": [{
+                    "index": 0,
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "write_file", "arguments": arguments}
+                }]}), None),
+                stream_chunk(serde_json::json!({}), Some("tool_calls")),
+            ]),
+            MockResponse::sse(vec![
+                stream_chunk(serde_json::json!({"content": "I'll write it in parts"}), None),
+                stream_chunk(serde_json::json!({}), Some("stop")),
+            ]),
+        ]).await;
+
+        let codr = Codr::with_client(server.client().with_max_tool_argument_bytes(256), "You are a test".to_string());
+        let mut receiver = codr.message_stream_events("Write big.txt".to_string()).await;
+        let mut events = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            events.push(event);
+        }
+
+        match &events[1] {
+            StreamEvent::ToolDone { result, .. } => assert!(result.contains("over the 256 byte limit")),
+            other => panic!("Expected ToolDone, got {:?}", other),
+        }
+        assert!(!path.exists());
+        assert_eq!(events[2], StreamEvent::Text("I'll write it in parts".to_string()));
+        let sent = server.requests()[1].json()["messages"].clone();
+        assert_eq!(sent[2]["tool_calls"][0]["function"]["arguments"], "{}");
+    }
+
+    #[tokio::test]
+    async fn test_stream_interleaved_reasoning_and_content() {
+        let server = MockServer::start(vec![
+            MockResponse::sse(vec![
+                stream_chunk(serde_json::json!({"role": "assistant", "reasoning_content": "The user wants "}), None),
+                stream_chunk(serde_json::json!({"reasoning_content": "a greeting.", "content": "Hello"}), None),
+                stream_chunk(serde_json::json!({"reasoning": " Keep it short.", "content": ""}), None),
+                stream_chunk(serde_json::json!({"content": " there!"}), None),
+                stream_chunk(serde_json::json!({}), Some("stop")),
+            ]),
+            text_completion("Bye"),
+        ]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let mut receiver = codr.message_stream_events("Greet me".to_string()).await;
+        let mut events = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            events.push(event);
+        }
+
+        assert_eq!(events, vec![
+            StreamEvent::Reasoning("The user wants ".to_string()),
+            StreamEvent::Reasoning("a greeting.".to_string()),
+            StreamEvent::Text("Hello".to_string()),
+            StreamEvent::Reasoning(" Keep it short.".to_string()),
+            StreamEvent::Text(" there!".to_string()),
+        ]);
+        let answer = codr.messages().pop().unwrap();
+        assert_eq!(answer.content.as_deref(), Some("Hello there!"));
+        assert_eq!(answer.reasoning_content.as_deref(), Some("The user wants a greeting. Keep it short."));
+
+        // Reasoning stays out of later requests
+        codr.message("Now say goodbye".to_string()).await.unwrap();
+        let sent = server.requests()[1].json()["messages"].clone();
+        assert_eq!(sent[2]["content"], "Hello there!");
+        assert!(sent[2].get("reasoning_content").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_stream_role_only_first_delta() {
+        let server = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"role": "assistant", "content": ""}), None),
+            stream_chunk(serde_json::json!({"content": "Hello"}), None),
+            stream_chunk(serde_json::json!({"content": " wor"}), None),
+            stream_chunk(serde_json::json!({"content": "ld"}), None),
+            stream_chunk(serde_json::json!({"content": "!"}), Some("stop")),
+        ])]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let mut receiver = codr.message_stream_events("Hi".to_string()).await;
+        let mut events = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            events.push(event);
+        }
+
+        let texts: Vec<_> = ["Hello", " wor", "ld", "!"].iter().map(|text| StreamEvent::Text(text.to_string())).collect();
+        assert_eq!(events, texts);
+        let last = codr.messages().pop().unwrap();
+        assert!(matches!(last.role, Some(Role::Assistant)));
+        assert_eq!(last.content.as_deref(), Some("Hello world!"));
+    }
+
+    #[tokio::test]
+    async fn test_stream_schema_violation() {
+        let server = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"role": "assistant", "content": "{\"answer\": "}), None),
+            stream_chunk(serde_json::json!({"content": "42}"}), Some("stop")),
+        ])]).await;
+        let client = server.client().with_options(CompletionOptions {
+            response_format: Some(serde_json::json!({
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "answer",
+                    "schema": {"type": "object", "properties": {"answer": {"type": "string"}}, "required": ["answer"]}
+                }
+            })),
+            ..Default::default()
+        });
+
+        let codr = Codr::with_client(client, "You are a test".to_string());
+        let mut receiver = codr.message_stream_events("What is the answer?".to_string()).await;
+        let mut events = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            events.push(event);
+        }
+
+        assert_eq!(events.last(), Some(&StreamEvent::SchemaViolation {
+            errors: vec![r#"42 is not of type "string" at /answer"#.to_string()],
+        }));
+        assert_eq!(codr.messages().last().unwrap().content.as_deref(), Some("{\"answer\": 42}"));
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_see_full_stream() {
+        let server = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"role": "assistant", "content": "Hello"}), None),
+            stream_chunk(serde_json::json!({"content": " world"}), None),
+            stream_chunk(serde_json::json!({}), Some("stop")),
+        ])]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let mut gui = codr.subscribe();
+        let mut logger = codr.subscribe();
+
+        let mut receiver = codr.message_stream("Hi".to_string()).await;
+        let mut streamed = Vec::new();
+        while let Some(chunk) = receiver.recv().await {
+            streamed.push(chunk);
+        }
+
+        let expected = vec![StreamEvent::Text("Hello".to_string()), StreamEvent::Text(" world".to_string())];
+        for subscriber in [&mut gui, &mut logger] {
+            let mut seen = Vec::new();
+            while let Ok(event) = subscriber.try_recv() {
+                seen.push(event);
+            }
+            assert_eq!(seen, expected);
+        }
+        assert_eq!(streamed, vec!["Hello", " world"]);
+    }
+
+    #[tokio::test]
+    async fn test_tool_tracer() {
+        let server = MockServer::start(vec![
+            tool_call_completion("call_1", "read_file", serde_json::json!({"file_path": "missing.txt"})),
+            text_completion("Done"),
+        ]).await;
+        let traced = Arc::new(Mutex::new(Vec::new()));
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let sink = traced.clone();
+        codr.set_tool_tracer(Box::new(move |call, result| {
+            sink.lock().unwrap().push((call.name.clone(), result.to_string()));
+        }));
+        codr.message("Read missing.txt".to_string()).await.unwrap();
+
+        let traced = traced.lock().unwrap();
+        assert_eq!(traced.len(), 1);
+        assert_eq!(traced[0].0, "read_file");
+        assert!(traced[0].1.contains("error"));
+    }
+
+    #[tokio::test]
+    async fn test_repeated_tool_call_answered_from_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.txt");
+        fs::write(&path, "").unwrap();
+        let args = serde_json::json!({"file_path": path, "content": "entry\n"});
+        let server = MockServer::start(vec![
+            tool_call_completion("call_1", "append_to_file", args.clone()),
+            tool_call_completion("call_2", "append_to_file", args),
+            text_completion("Done"),
+        ]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        *codr.toolbox.context_mut() = ToolContext::new(dir.path());
+        codr.set_dedupe_tool_calls(true);
+        codr.message("Log an entry".to_string()).await.unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "entry\n");
+        let results: Vec<serde_json::Value> = codr.messages().iter()
+            .filter(|message| message.tool_call_id.is_some())
+            .map(|message| serde_json::from_str(message.content.as_deref().unwrap()).unwrap())
+            .collect();
+        assert_eq!(results[1]["notice"], tool_calls::REPEATED_CALL_NOTICE);
+        assert_eq!(results[1]["result"], results[0]);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_reaches_request() {
+        let server = MockServer::start(vec![text_completion("Hello")]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.set_metadata(HashMap::from([("feature".to_string(), "review".to_string())]));
+        codr.message("Hi".to_string()).await.unwrap();
+
+        assert_eq!(server.requests()[0].json()["metadata"]["feature"], "review");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_tool_lists_available_tools() {
+        let server = MockServer::start(vec![
+            tool_call_completion("call_1", "frobnicate", serde_json::json!({"target": "main.rs"})),
+            text_completion("Sorry, I'll read it instead"),
+        ]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.toolbox.set_allowed_tools(Some(vec!["read_file".to_string(), "get_folder_files".to_string()]));
+        let response = codr.message("Frobnicate main.rs".to_string()).await.unwrap();
+
+        assert_eq!(response, vec![Some("Sorry, I'll read it instead".to_string())]);
+        let result: serde_json::Value = serde_json::from_str(codr.messages()[3].content.as_deref().unwrap()).unwrap();
+        assert_eq!(result["error"], "Tool not found: frobnicate. Available tools: read_file, get_folder_files");
+    }
+
+    #[tokio::test]
+    async fn test_request_id_header_stable_within_message() {
+        let server = MockServer::start(vec![
+            tool_call_completion("call_1", "read_file", serde_json::json!({"file_path": "missing.txt"})),
+            text_completion("No such file"),
+            text_completion("Hello"),
+        ]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.set_request_id_header(true);
+        codr.message("Read missing.txt".to_string()).await.unwrap();
+        let first = codr.request_id().unwrap();
+        codr.message("Hi".to_string()).await.unwrap();
+
+        let requests = server.requests();
+        let ids: Vec<_> = requests.iter().map(|request| request.header("X-Request-Id").unwrap().to_string()).collect();
+        assert_eq!(ids[0], first);
+        assert_eq!(ids[1], first);
+        assert_ne!(ids[2], first);
+        assert_eq!(codr.request_id().as_deref(), Some(ids[2].as_str()));
+        assert!(uuid::Uuid::parse_str(&first).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_tool_list_in_system_prompt() {
+        let server = MockServer::start(vec![text_completion("Hello"), text_completion("Hello")]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.message("Hi".to_string()).await.unwrap();
+        codr.set_tool_list_in_prompt(true);
+        codr.message("Hi again".to_string()).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].json()["messages"][0]["content"], "You are a test");
+        let system_prompt = requests[1].json()["messages"][0]["content"].as_str().unwrap().to_string();
+        assert!(system_prompt.starts_with("You are a test\n\n"));
+        for tool in codr.toolbox.get_tools() {
+            assert!(system_prompt.contains(&format!("\n- {}: ", tool.function.name)), "{} missing", tool.function.name);
+        }
+        assert_eq!(codr.messages()[0].content.as_deref(), Some("You are a test"));
+    }
+
+    #[tokio::test]
+    async fn test_context_file_in_first_request() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("lib.rs"), "pub fn answer() -> u32 { 42 }").unwrap();
+        let server = MockServer::start(vec![text_completion("It returns 42")]).await;
+
+        let messages = vec![simple_message("You are a test".to_string(), Role::System)];
+        let mut codr = Codr::from_messages(server.client(), messages, ToolBox::with_context(ToolContext::new(dir.path())));
+        codr.add_context_file("lib.rs").unwrap();
+        codr.message("Explain this".to_string()).await.unwrap();
+
+        let sent = server.requests()[0].json()["messages"].clone();
+        assert!(sent[1]["content"].as_str().unwrap().contains("pub fn answer() -> u32 { 42 }"));
+        assert_eq!(sent[2]["content"], "Explain this");
+    }
+
+    #[tokio::test]
+    async fn test_auto_read_referenced_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("src/lib.rs"), "pub fn answer() -> u32 { 42 }").unwrap();
+        std::fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(dir.path().join("big.txt"), "x".repeat(4000)).unwrap();
+        let server = MockServer::start(vec![
+            tool_call_completion("call_1", "head_tail", serde_json::json!({"file_path": "src/main.rs", "mode": "head"})),
+            text_completion("`answer` lives in `src/lib.rs`, called from src/main.rs. See big.txt and missing.rs."),
+            text_completion("It returns 42"),
+        ]).await;
+
+        let messages = vec![simple_message("You are a test".to_string(), Role::System)];
+        let mut codr = Codr::from_messages(server.client(), messages, ToolBox::with_context(ToolContext::new(dir.path())));
+        codr.set_auto_read_budget(Some(100));
+        codr.message("Where is answer?".to_string()).await.unwrap();
+        codr.message("What does it return?".to_string()).await.unwrap();
+
+        let sent = server.requests()[2].json()["messages"].clone();
+        let sent = sent.as_array().unwrap();
+        // Read before, over budget or not in the workspace: none of these are added
+        assert_eq!(sent.len(), 7);
+        assert_eq!(sent[5]["content"], "Contents of src/lib.rs:\n```\npub fn answer() -> u32 { 42 }\n```");
+        assert_eq!(sent[6]["content"], "What does it return?");
+    }
+
+    #[tokio::test]
+    async fn test_language_instruction() {
+        let server = MockServer::start(vec![text_completion("Hallo")]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.set_language(Some("French".to_string()));
+        codr.set_language(Some("German".to_string()));
+        codr.set_persona("reviewer").unwrap();
+        codr.message("Hi".to_string()).await.unwrap();
+
+        let system_prompt = server.requests()[0].json()["messages"][0]["content"].as_str().unwrap().to_string();
+        assert!(system_prompt.starts_with("You are Codr AI acting as a meticulous code reviewer."));
+        assert_eq!(system_prompt.matches("Always respond in").count(), 1);
+        assert!(system_prompt.contains("Always respond in German"));
+
+        codr.set_language(None);
+        assert!(!codr.messages()[0].content.as_deref().unwrap().contains("Always respond in"));
+    }
+
+    #[tokio::test]
+    async fn test_fork_diverges_independently() {
+        let server = MockServer::start(vec![
+            text_completion("Use a HashMap"),
+            text_completion("Branch A"),
+            text_completion("Branch B"),
+        ]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.message("How should I store users?".to_string()).await.unwrap();
+
+        let fork = codr.fork();
+        codr.message("What about a Vec?".to_string()).await.unwrap();
+        fork.message("What about a BTreeMap?".to_string()).await.unwrap();
+
+        let contents = |codr: &Codr| -> Vec<String> {
+            codr.messages().iter().skip(1).map(|m| m.content.clone().unwrap_or_default()).collect()
+        };
+        assert_eq!(contents(&codr), vec!["How should I store users?", "Use a HashMap", "What about a Vec?", "Branch A"]);
+        assert_eq!(contents(&fork), vec!["How should I store users?", "Use a HashMap", "What about a BTreeMap?", "Branch B"]);
+
+        // The fork sent the shared history plus its own prompt only
+        let forked_request = server.requests()[2].json();
+        assert_eq!(forked_request["messages"].as_array().unwrap().len(), 4);
+        assert_eq!(forked_request["messages"][3]["content"], "What about a BTreeMap?");
+
+        let dir = tempfile::tempdir().unwrap();
+        codr.toolbox.context().pending_edits.propose(&dir.path().join("main.rs"), "fn main() {}").unwrap();
+        assert!(fork.toolbox.context().pending_edits.ids().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_compare_with_model() {
+        let server = MockServer::start(vec![
+            text_completion("Answer from the test model"),
+            text_completion("Answer from the other model"),
+        ]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        assert!(codr.compare_with_model("other-model").await.is_err());
+        codr.message("Which sort is stable?".to_string()).await.unwrap();
+
+        let comparison = codr.compare_with_model("other-model").await.unwrap();
+
+        assert_eq!(comparison, Comparison {
+            prompt: "Which sort is stable?".to_string(),
+            original_model: "test-model".to_string(),
+            original: "Answer from the test model".to_string(),
+            model: "other-model".to_string(),
+            response: "Answer from the other model".to_string(),
+        });
+
+        // Same input, different model, and the conversation is untouched
+        let requests = server.requests();
+        assert_eq!(requests[0].json()["messages"], requests[1].json()["messages"]);
+        assert_eq!((requests[0].json()["model"].clone(), requests[1].json()["model"].clone()), (serde_json::json!("test-model"), serde_json::json!("other-model")));
+        assert_eq!(codr.messages().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_max_session_cost() {
+        let server = MockServer::start(vec![
+            text_completion_with_usage("One", 5_000, 1_000),
+            text_completion_with_usage("Two", 5_000, 1_000),
+            text_completion_with_usage("Three", 5_000, 1_000),
+        ]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.set_pricing(Pricing { input_per_million: 1.0, output_per_million: 2.0 });
+        codr.set_max_session_cost(Some(0.01));
+
+        codr.message("First".to_string()).await.unwrap();
+        codr.message("Second".to_string()).await.unwrap();
+        let error = codr.message("Third".to_string()).await.unwrap_err();
+
+        assert!(error.to_string().contains("$0.0140"));
+        let CodrError::CostLimitExceeded(exceeded) = error else {
+            panic!("Expected CostLimitExceeded, got {:?}", error);
+        };
+        assert!((exceeded.spent - 0.014).abs() < 1e-9);
+        assert_eq!(exceeded.limit, 0.01);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_max_session_cost_streaming() {
+        let answer = |text: &str| MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"content": text}), None),
+            stream_chunk(serde_json::json!({}), Some("stop")),
+            stream_usage_chunk(5_000, 1_000),
+        ]);
+        let server = MockServer::start(vec![answer("One"), answer("Two"), answer("Three")]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.set_pricing(Pricing { input_per_million: 1.0, output_per_million: 2.0 });
+        codr.set_max_session_cost(Some(0.01));
+
+        for prompt in ["First", "Second"] {
+            let (_receiver, history) = codr.message_stream_with_history(prompt.to_string()).await;
+            history.await.unwrap();
+        }
+        let mut events = codr.message_stream_events("Third".to_string()).await;
+
+        assert!((codr.session_cost() - 0.014).abs() < 1e-9);
+        assert!(matches!(events.recv().await, Some(StreamEvent::Error(error)) if error.contains("$0.0140")));
+        assert_eq!(server.requests().len(), 2);
+        assert_eq!(server.requests()[0].json()["stream_options"]["include_usage"], true);
+    }
+
+    #[tokio::test]
+    async fn test_messages_share_connection() {
+        let server = MockServer::start(vec![text_completion("One"), text_completion("Two")]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        codr.message("First".to_string()).await.unwrap();
+        codr.message("Second".to_string()).await.unwrap();
+
+        assert_eq!(server.connections(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_from_messages_continues_history() {
+        let server = MockServer::start(vec![text_completion("It returned 42")]).await;
+
+        let history = vec![
+            simple_message("You are a test".to_string(), Role::System),
+            simple_message("Run the answer tool".to_string(), Role::User),
+            assistant_tool_call_message(vec![ToolCall {
+                id: Some("call_1".to_string()),
+                index: None,
+                tool_type: Some("function".to_string()),
+                function: openai::FunctionCall {
+                    name: Some("answer".to_string()),
+                    arguments: "{}".to_string(),
+                },
+            }]),
+            tool_call_result("call_1".to_string(), "42".to_string()),
+            simple_message("The tool says 42".to_string(), Role::Assistant),
+        ];
+
+        let codr = Codr::from_messages(server.client(), history, ToolBox::new());
+        codr.message("What did it return?".to_string()).await.unwrap();
+
+        let sent = server.requests()[0].json()["messages"].clone();
+        assert_eq!(sent.as_array().unwrap().len(), 6);
+        assert_eq!(sent[2]["tool_calls"][0]["id"], "call_1");
+        assert_eq!(sent[3]["tool_call_id"], "call_1");
+        assert_eq!(sent[5]["content"], "What did it return?");
+        assert_eq!(codr.messages().len(), 7);
+    }
+
+    #[test]
+    fn test_from_messages_inserts_system_prompt() {
+        let client = OpenAIClient::new("http://localhost".to_string(), "key".to_string(), "model".to_string());
+        let history = vec![simple_message("Hi".to_string(), Role::User)];
+
+        let codr = Codr::from_messages(client, history, ToolBox::new());
+
+        let messages = codr.messages();
+        assert_eq!(messages.len(), 2);
+        assert!(matches!(messages[0].role, Some(Role::System)));
+        assert_eq!(messages[0].content.as_deref(), Some(DEFAULT_SYSTEM_PROMPT));
+    }
+
+    #[test]
+    fn test_set_persona_swaps_prompt_and_tools() {
+        let client = OpenAIClient::new("http://localhost".to_string(), "key".to_string(), "model".to_string());
+        let mut codr = Codr::with_client(client, "You are a test".to_string());
+        let all_tools = codr.toolbox.get_tools().len();
+
+        codr.set_persona("reviewer").unwrap();
+
+        let messages = codr.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].content.as_deref().unwrap().contains("code reviewer"));
+        let tool_names: Vec<String> = codr.toolbox.get_tools().into_iter().map(|tool| tool.function.name).collect();
+        assert!(tool_names.contains(&"read_file".to_string()));
+        assert!(!tool_names.contains(&"write_file".to_string()));
+
+        codr.add_persona("custom".to_string(), Persona {
+            system_prompt: "Custom prompt".to_string(),
+            tools: None,
+        });
+        codr.set_persona("custom").unwrap();
+
+        assert_eq!(codr.messages()[0].content.as_deref(), Some("Custom prompt"));
+        assert_eq!(codr.toolbox.get_tools().len(), all_tools);
+        assert!(codr.set_persona("nonexistent").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parallel_tool_results_keep_call_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<String> = (0..4).map(|i| {
+            let path = dir.path().join(format!("{}.txt", i));
+            fs::write(&path, i.to_string().repeat(100_000 * (4 - i))).unwrap();
+            path.to_str().unwrap().to_string()
+        }).collect();
+
+        let tool_calls: Vec<serde_json::Value> = files.iter().enumerate().map(|(i, file)| serde_json::json!({
+            "id": format!("call_{}", i),
+            "type": "function",
+            "function": {"name": "read_file", "arguments": serde_json::json!({"file_path": file}).to_string()}
+        })).collect();
+
+        let server = MockServer::start(vec![
+            MockResponse::json(serde_json::json!({
+                "choices": [{
+                    "message": {"role": "assistant", "tool_calls": tool_calls},
+                    "finish_reason": "tool_calls"
+                }]
+            })),
+            text_completion("Read them all"),
+        ]).await;
+
+        let mut codr = Codr::with_client(server.client(), "You are a test".to_string());
+        *codr.toolbox.context_mut() = ToolContext::new(dir.path());
+        codr.set_parallel_tools(true);
+        codr.message("Read the files".to_string()).await.unwrap();
+
+        let messages = codr.messages();
+        let tool_ids: Vec<String> = messages.iter().filter_map(|m| m.tool_call_id.clone()).collect();
+        assert_eq!(tool_ids, vec!["call_0", "call_1", "call_2", "call_3"]);
+        for (i, message) in messages.iter().filter(|m| m.tool_call_id.is_some()).enumerate() {
+            let result: serde_json::Value = serde_json::from_str(message.content.as_deref().unwrap()).unwrap();
+            assert!(result["content"].as_str().unwrap().starts_with(&i.to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_config_headers_reach_request() {
+        let server = MockServer::start(vec![text_completion("Hello")]).await;
+
+        let config = Config::parse(r#"
+            [headers]
+            X-Gateway-Route = "team-a"
+        "#).unwrap();
+        let client = server.client().with_headers(config.resolved_headers().unwrap());
+
+        let codr = Codr::with_client(client, "You are a test".to_string());
+        codr.message("Hi".to_string()).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("X-Gateway-Route"), Some("team-a"));
+        assert_eq!(requests[0].header("Authorization"), Some("Bearer test-key"));
     }
 }
@@ -0,0 +1,158 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use tools::{ToolBox, tool_box::pending_edits::PendingEdits};
+
+use crate::Codr;
+
+/// Hosts many independent conversations, e.g. for a server. Every session has
+/// its own history, pending edits and session id, which scopes its scratchpad,
+/// but shares the manager's client, and with it the connection pool.
+pub struct CodrManager {
+    openai_client: openai::OpenAIClient,
+    toolbox: ToolBox,
+    system_prompt: String,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+/// Handle to one conversation of a [`CodrManager`]. Clones refer to the same
+/// conversation; messages sent to it are handled one at a time.
+#[derive(Clone)]
+pub struct Session {
+    codr: Arc<tokio::sync::Mutex<Codr>>,
+}
+
+impl CodrManager {
+    pub fn new(openai_client: openai::OpenAIClient, toolbox: ToolBox, system_prompt: String) -> Self {
+        CodrManager {
+            openai_client,
+            toolbox,
+            system_prompt,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the session with the given id, starting a new conversation if there is none.
+    pub fn session(&self, id: &str) -> Session {
+        self.sessions.lock().unwrap()
+            .entry(id.to_string())
+            .or_insert_with(|| {
+                let messages = vec![openai::simple_message(self.system_prompt.clone(), openai::Role::System)];
+                let mut toolbox = self.toolbox.clone();
+                toolbox.context_mut().session_id = id.to_string();
+                toolbox.context_mut().pending_edits = PendingEdits::default();
+                let codr = Codr::from_messages(self.openai_client.clone(), messages, toolbox);
+                Session { codr: Arc::new(tokio::sync::Mutex::new(codr)) }
+            })
+            .clone()
+    }
+
+    /// Ends a conversation. Handles still held elsewhere keep working but are no longer tracked.
+    pub fn remove_session(&self, id: &str) -> bool {
+        self.sessions.lock().unwrap().remove(id).is_some()
+    }
+
+    pub fn session_ids(&self) -> Vec<String> {
+        self.sessions.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+impl Session {
+    pub async fn message(&self, message: String) -> Result<Vec<Option<String>>, crate::CodrError> {
+        self.codr.lock().await.message(message).await
+    }
+
+    /// Streams the answer to `message`. The session stays busy until the answer
+    /// is complete, even if the receiver is dropped early.
+    pub async fn message_stream(&self, message: String) -> tokio::sync::mpsc::Receiver<String> {
+        let codr = self.codr.clone().lock_owned().await;
+        let mut chunks = codr.message_stream(message).await;
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+
+        tokio::spawn(async move {
+            // Holding the lock until the stream ends, which is once its exchange is in the history
+            let _codr = codr;
+            while let Some(chunk) = chunks.recv().await {
+                let _ = tx.send(chunk).await;
+            }
+        });
+
+        rx
+    }
+
+    pub async fn messages(&self) -> Vec<openai::Message> {
+        self.codr.lock().await.messages()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai::test_util::{MockResponse, MockServer, stream_chunk, text_completion};
+
+    #[tokio::test]
+    async fn test_sessions_have_isolated_histories() {
+        let server = MockServer::start(vec![
+            text_completion("Hi Alice"),
+            text_completion("Hi Bob"),
+            text_completion("Your name is Alice"),
+        ]).await;
+        let manager = CodrManager::new(server.client(), ToolBox::new(), "You are a test".to_string());
+
+        // Sessions can be driven from spawned tasks, as a server would
+        let alice = manager.session("alice");
+        tokio::spawn(async move { alice.message("I am Alice".to_string()).await.map_err(|e| e.to_string()) })
+            .await.unwrap().unwrap();
+        manager.session("bob").message("I am Bob".to_string()).await.unwrap();
+        let answer = manager.session("alice").message("What is my name?".to_string()).await.unwrap();
+
+        assert_eq!(answer, vec![Some("Your name is Alice".to_string())]);
+        assert_eq!(manager.session("alice").messages().await.len(), 5);
+        assert_eq!(manager.session("bob").messages().await.len(), 3);
+
+        let sent = server.requests()[2].json()["messages"].clone();
+        assert_eq!(sent.as_array().unwrap().len(), 4);
+        assert!(!sent.to_string().contains("Bob"));
+        assert_eq!(server.connections(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_session_handles_streamed_prompt_before_next() {
+        let server = MockServer::start(vec![
+            MockResponse::sse(vec![
+                stream_chunk(serde_json::json!({"role": "assistant", "content": "Streamed "}), None),
+                stream_chunk(serde_json::json!({"content": "answer"}), None),
+                stream_chunk(serde_json::json!({}), Some("stop")),
+            ]).with_chunk_delay(std::time::Duration::from_millis(100)),
+            text_completion("Plain answer"),
+        ]).await;
+        let manager = CodrManager::new(server.client(), ToolBox::new(), "You are a test".to_string());
+        let session = manager.session("alice");
+
+        // Dropped right away, the answer still completes before the next prompt runs
+        drop(session.message_stream("Stream it".to_string()).await);
+        session.message("Answer plainly".to_string()).await.unwrap();
+
+        let sent = server.requests()[1].json()["messages"].clone();
+        assert_eq!(sent.as_array().unwrap().len(), 4);
+        assert_eq!(sent[2]["content"], "Streamed answer");
+        assert_eq!(session.messages().await.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_have_own_tool_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = MockServer::start(vec![]).await;
+        let manager = CodrManager::new(server.client(), ToolBox::new(), "You are a test".to_string());
+
+        let alice = manager.session("alice");
+        let bob = manager.session("bob");
+        alice.codr.lock().await.toolbox.context().pending_edits.propose(&dir.path().join("main.rs"), "fn main() {}").unwrap();
+
+        let alice = alice.codr.lock().await;
+        let bob = bob.codr.lock().await;
+        assert_eq!(alice.toolbox.context().session_id, "alice");
+        assert_eq!(bob.toolbox.context().session_id, "bob");
+        assert_eq!(alice.toolbox.context().pending_edits.ids().len(), 1);
+        assert!(bob.toolbox.context().pending_edits.ids().is_empty());
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::DEFAULT_SYSTEM_PROMPT;
+
+/// A named system prompt, optionally paired with the tools it may use.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Persona {
+    pub system_prompt: String,
+    /// Names of the tools offered to the model. `None` offers every tool.
+    #[serde(default)]
+    pub tools: Option<Vec<String>>,
+}
+
+const READ_ONLY_TOOLS: &[&str] = &["read_file", "head_tail", "diff_files", "get_folder_files", "project_overview", "workspace_info", "find_definition", "summarize_file", "code_stats", "scratchpad", "ask_user", "get_file_changes"];
+
+const REVIEWER_PROMPT: &str = "You are Codr AI acting as a meticulous code reviewer. \
+Read the code you are pointed at and report bugs, risky patterns, missing error handling, \
+unclear naming and missing tests, ordered by severity. Quote the relevant lines and suggest \
+concrete fixes, but do not modify any files.";
+
+const REFACTORER_PROMPT: &str = "You are Codr AI acting as a careful refactoring assistant. \
+Improve the structure, naming and readability of the code you are pointed at without changing \
+its behavior. Always read a file before editing it, keep changes small and consistent with the \
+surrounding code, and explain each change you made.";
+
+const EXPLAINER_PROMPT: &str = "You are Codr AI acting as a patient teacher. \
+Explain how the code you are pointed at works: its purpose, the flow of data, and any \
+non-obvious language features or design decisions. Use short examples where they help, \
+and do not modify any files.";
+
+fn read_only_tools() -> Option<Vec<String>> {
+    Some(READ_ONLY_TOOLS.iter().map(|name| name.to_string()).collect())
+}
+
+/// Personas available out of the box. `default` restores Codr's regular behavior.
+pub fn builtin_personas() -> HashMap<String, Persona> {
+    HashMap::from([
+        ("default".to_string(), Persona {
+            system_prompt: DEFAULT_SYSTEM_PROMPT.to_string(),
+            tools: None,
+        }),
+        ("reviewer".to_string(), Persona {
+            system_prompt: REVIEWER_PROMPT.to_string(),
+            tools: read_only_tools(),
+        }),
+        ("refactorer".to_string(), Persona {
+            system_prompt: REFACTORER_PROMPT.to_string(),
+            tools: None,
+        }),
+        ("explainer".to_string(), Persona {
+            system_prompt: EXPLAINER_PROMPT.to_string(),
+            tools: read_only_tools(),
+        }),
+    ])
+}
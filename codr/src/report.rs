@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+
+use openai::{Message, Role};
+
+use crate::cost::estimate_tokens;
+
+/// Renders a conversation as a markdown report for people to read: stats
+/// first, then every turn, with tool calls folded into `<details>` blocks.
+/// `cost` is the session cost in dollars, if pricing is known.
+pub fn render_markdown(messages: &[Message], cost: Option<f64>) -> String {
+    let results: HashMap<&str, &str> = messages.iter()
+        .filter_map(|message| Some((message.tool_call_id.as_deref()?, message.content.as_deref().unwrap_or_default())))
+        .collect();
+    let tool_calls = messages.iter().flat_map(|message| message.tool_calls.iter().flatten()).count();
+
+    let mut report = String::from("# Codr session\n\n");
+    report.push_str(&format!("- **Messages:** {}\n", messages.len()));
+    report.push_str(&format!("- **Tool calls:** {}\n", tool_calls));
+    report.push_str(&format!("- **Estimated tokens:** ~{}\n", estimate_tokens(messages)));
+    if let Some(cost) = cost {
+        report.push_str(&format!("- **Cost:** ${:.4}\n", cost));
+    }
+
+    for message in messages {
+        let content = message.content.as_deref().unwrap_or_default().trim();
+        match message.role {
+            Some(Role::System) => {
+                report.push_str("\n<details>\n<summary>System prompt</summary>\n\n");
+                report.push_str(&fenced("", content));
+                report.push_str("\n</details>\n");
+            }
+            Some(Role::User) => report.push_str(&format!("\n## User\n\n{}\n", content)),
+            Some(Role::Assistant) => {
+                report.push_str("\n## Assistant\n");
+                if !content.is_empty() {
+                    report.push_str(&format!("\n{}\n", content));
+                }
+                for call in message.tool_calls.iter().flatten() {
+                    let name = call.function.name.as_deref().unwrap_or("?");
+                    let arguments = serde_json::from_str::<serde_json::Value>(&call.function.arguments)
+                        .and_then(|arguments| serde_json::to_string_pretty(&arguments))
+                        .unwrap_or_else(|_| call.function.arguments.clone());
+                    let result = call.id.as_deref().and_then(|id| results.get(id)).copied();
+
+                    report.push_str(&format!("\n<details>\n<summary>Tool call: <code>{}</code></summary>\n\n", name));
+                    report.push_str("**Arguments**\n\n");
+                    report.push_str(&fenced("json", &arguments));
+                    report.push_str("\n**Result**\n\n");
+                    report.push_str(&fenced("", result.unwrap_or("(no result)")));
+                    report.push_str("\n</details>\n");
+                }
+            }
+            // Shown with the call they answer
+            Some(Role::Tool) | Some(Role::Function) | None => {}
+        }
+    }
+    report
+}
+
+/// A code block whose fence is longer than any backtick run in `text`.
+fn fenced(language: &str, text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or_default();
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{}{}\n{}\n{}\n", fence, language, text.trim_end(), fence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fence_longer_than_content_backticks() {
+        assert_eq!(fenced("", "plain"), "```\nplain\n```\n");
+        assert_eq!(fenced("md", "```rust\nfn main() {}\n```"), "````md\n```rust\nfn main() {}\n```\n````\n");
+    }
+}
@@ -0,0 +1,49 @@
+/// The schema a `response_format` of type `json_schema` constrains answers to.
+pub fn response_schema(response_format: &serde_json::Value) -> Option<&serde_json::Value> {
+    if response_format["type"] != "json_schema" {
+        return None;
+    }
+    response_format["json_schema"].get("schema")
+}
+
+/// What is wrong with `content` as an instance of `schema`, empty if nothing.
+pub fn violations(schema: &serde_json::Value, content: &str) -> Vec<String> {
+    let instance: serde_json::Value = match serde_json::from_str(content) {
+        Ok(instance) => instance,
+        Err(e) => return vec![format!("The response is not valid JSON: {}", e)],
+    };
+
+    match jsonschema::validator_for(schema) {
+        Ok(validator) => validator.iter_errors(&instance)
+            .map(|error| format!("{} at {}", error, error.instance_path))
+            .collect(),
+        Err(e) => vec![format!("The schema itself is invalid: {}", e)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_violations() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {"answer": {"type": "string"}},
+            "required": ["answer"]
+        });
+
+        assert!(violations(&schema, r#"{"answer": "42"}"#).is_empty());
+        assert_eq!(violations(&schema, r#"{"answer": 42}"#), vec![r#"42 is not of type "string" at /answer"#]);
+        assert!(violations(&schema, r#"{"answer": "#)[0].starts_with("The response is not valid JSON"));
+    }
+
+    #[test]
+    fn test_response_schema() {
+        let schema = serde_json::json!({"type": "object"});
+        let format = serde_json::json!({"type": "json_schema", "json_schema": {"name": "answer", "schema": schema}});
+
+        assert_eq!(response_schema(&format), Some(&schema));
+        assert_eq!(response_schema(&serde_json::json!({"type": "json_object"})), None);
+    }
+}
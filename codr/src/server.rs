@@ -0,0 +1,191 @@
+//! A small HTTP server streaming Codr's answers as server-sent events, so
+//! editors and web frontends can talk to Codr without the terminal.
+//!
+//! `POST /prompt` with `{"prompt": "..."}` answers with a `text/event-stream`
+//! of `text`, `partial_json`, `tool_running` and `tool_done` events, closed by
+//! a `done` event. Prompts share one conversation and run one at a time.
+
+use std::sync::Arc;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+};
+
+use crate::{Codr, StreamEvent};
+
+/// Largest request body accepted, well above any prompt typed by hand.
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Serves prompts arriving on `listener` until accepting a connection fails.
+pub async fn serve(codr: Codr, listener: TcpListener) -> std::io::Result<()> {
+    let codr = Arc::new(Mutex::new(codr));
+    loop {
+        let (socket, _) = listener.accept().await?;
+        tokio::spawn(handle_connection(socket, codr.clone()));
+    }
+}
+
+async fn handle_connection(mut socket: TcpStream, codr: Arc<Mutex<Codr>>) {
+    let prompt = match read_request(&mut socket).await {
+        Ok(prompt) => prompt,
+        Err((status, message)) => {
+            let _ = write_error(&mut socket, status, message).await;
+            return;
+        }
+    };
+
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+    if socket.write_all(head.as_bytes()).await.is_err() {
+        return;
+    }
+
+    let codr = codr.lock_owned().await;
+    let mut events = codr.message_stream_events(prompt).await;
+    while let Some(event) = events.recv().await {
+        if socket.write_all(sse_event(&event).as_bytes()).await.is_err() {
+            // The client went away, let the answer finish so the history stays whole
+            while events.recv().await.is_some() {}
+            return;
+        }
+        let _ = socket.flush().await;
+    }
+
+    let _ = socket.write_all(b"event: done\ndata: {}\n\n").await;
+    let _ = socket.flush().await;
+}
+
+/// Reads one request and returns its prompt, or the status and message to reject it with.
+async fn read_request(socket: &mut TcpStream) -> Result<String, (&'static str, &'static str)> {
+    const BAD_REQUEST: &str = "400 Bad Request";
+
+    let mut buffer = Vec::new();
+    let header_end = loop {
+        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if buffer.len() > MAX_BODY_BYTES {
+            return Err(("413 Payload Too Large", "Request too large"));
+        }
+        read_more(socket, &mut buffer).await.ok_or((BAD_REQUEST, "Incomplete request"))?;
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    if (request_line.next(), request_line.next()) != (Some("POST"), Some("/prompt")) {
+        return Err(("404 Not Found", "Only POST /prompt is served"));
+    }
+
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .ok_or(("411 Length Required", "Content-Length is required"))?;
+    if content_length > MAX_BODY_BYTES {
+        return Err(("413 Payload Too Large", "Request too large"));
+    }
+
+    while buffer.len() < header_end + content_length {
+        read_more(socket, &mut buffer).await.ok_or((BAD_REQUEST, "Incomplete request"))?;
+    }
+
+    let body: serde_json::Value = serde_json::from_slice(&buffer[header_end..header_end + content_length])
+        .map_err(|_| (BAD_REQUEST, "Body must be JSON"))?;
+    match body["prompt"].as_str() {
+        Some(prompt) if !prompt.trim().is_empty() => Ok(prompt.to_string()),
+        _ => Err((BAD_REQUEST, "prompt is required")),
+    }
+}
+
+/// Appends whatever the socket has next, `None` once it is closed.
+async fn read_more(socket: &mut TcpStream, buffer: &mut Vec<u8>) -> Option<()> {
+    let mut chunk = [0u8; 4096];
+    match socket.read(&mut chunk).await {
+        Ok(0) | Err(_) => None,
+        Ok(read) => {
+            buffer.extend_from_slice(&chunk[..read]);
+            Some(())
+        }
+    }
+}
+
+async fn write_error(socket: &mut TcpStream, status: &str, message: &str) -> std::io::Result<()> {
+    let body = serde_json::json!({"error": message}).to_string();
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body,
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.flush().await
+}
+
+/// Formats an event in the SSE wire format, with its payload as JSON.
+fn sse_event(event: &StreamEvent) -> String {
+    let (name, data) = match event {
+        StreamEvent::Text(text) => ("text", serde_json::json!({"text": text})),
+        StreamEvent::Reasoning(text) => ("reasoning", serde_json::json!({"text": text})),
+        StreamEvent::PartialJson(value) => ("partial_json", serde_json::json!({"value": value})),
+        StreamEvent::ToolRunning { id, name } => ("tool_running", serde_json::json!({"id": id, "name": name})),
+        StreamEvent::ToolDone { id, name, result } => ("tool_done", serde_json::json!({"id": id, "name": name, "result": result})),
+        StreamEvent::SchemaViolation { errors } => ("schema_violation", serde_json::json!({"errors": errors})),
+        StreamEvent::Error(message) => ("error", serde_json::json!({"message": message})),
+    };
+    format!("event: {}\ndata: {}\n\n", name, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai::test_util::{MockResponse, MockServer, stream_chunk};
+
+    async fn post(address: std::net::SocketAddr, path: &str, body: &str) -> String {
+        let mut socket = TcpStream::connect(address).await.unwrap();
+        let request = format!("POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", path, body.len(), body);
+        socket.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_prompt_streams_events() {
+        let server = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"role": "assistant", "content": "Hello"}), None),
+            stream_chunk(serde_json::json!({"content": " there"}), None),
+            stream_chunk(serde_json::json!({}), Some("stop")),
+        ])]).await;
+
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve(codr, listener));
+
+        let response = post(address, "/prompt", r#"{"prompt": "Hi"}"#).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Content-Type: text/event-stream"));
+
+        let body = response.split_once("\r\n\r\n").unwrap().1;
+        assert_eq!(body, concat!(
+            "event: text\ndata: {\"text\":\"Hello\"}\n\n",
+            "event: text\ndata: {\"text\":\" there\"}\n\n",
+            "event: done\ndata: {}\n\n",
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_requests() {
+        let server = MockServer::start(vec![]).await;
+        let codr = Codr::with_client(server.client(), "You are a test".to_string());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve(codr, listener));
+
+        assert!(post(address, "/other", "{}").await.starts_with("HTTP/1.1 404"));
+        assert!(post(address, "/prompt", "not json").await.starts_with("HTTP/1.1 400"));
+        assert!(post(address, "/prompt", r#"{"prompt": ""}"#).await.starts_with("HTTP/1.1 400"));
+        assert!(server.requests().is_empty());
+    }
+}
@@ -0,0 +1,437 @@
+use std::{collections::{HashMap, HashSet}, sync::Arc};
+
+use openai::ToolCall;
+use tools::ToolBox;
+
+use crate::{context_files::is_context_message, cost::estimate_text_tokens};
+
+/// A tool call from the model, validated to carry what is needed to run it.
+#[derive(Debug, Clone)]
+pub struct PendingToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: String,
+}
+
+/// Replaces the content of a tool result once it is stale.
+pub const STALE_TOOL_RESULT: &str = "[tool result omitted to save context, run the tool again if needed]";
+
+/// Callback seeing each tool call together with the result sent back to the model.
+pub type ToolTraceFn = dyn Fn(&PendingToolCall, &str) + Send + Sync;
+
+pub type ToolTracer = Arc<ToolTraceFn>;
+
+/// Gives every call of a model turn a usable, unique id. Some providers omit
+/// ids on streamed deltas or repeat them, which would make results ambiguous.
+pub fn assign_tool_call_ids(tool_calls: &mut [ToolCall], turn: usize) {
+    let mut seen = HashSet::new();
+
+    for (index, tool_call) in tool_calls.iter_mut().enumerate() {
+        let usable = tool_call.id.as_ref().is_some_and(|id| !id.is_empty() && !seen.contains(id));
+        if !usable {
+            tool_call.id = Some(format!("call_codr_{}_{}", turn, index));
+        }
+        seen.insert(tool_call.id.clone().unwrap_or_default());
+    }
+}
+
+/// Runs one tool call and returns the result to send back to the model.
+/// Failures are reported to the model as `{"error": ...}` rather than aborting.
+pub async fn execute_tool_call(toolbox: &ToolBox, call: &PendingToolCall) -> String {
+    let args = match serde_json::from_str::<serde_json::Value>(&call.arguments) {
+        Ok(args) => args,
+        Err(e) => {
+            return serde_json::json!({"error": format!("Failed to parse arguments: {}", e)}).to_string();
+        }
+    };
+
+    let result = match toolbox.run_tool(&call.name, args).await {
+        Ok(res) => res,
+        Err(e) => serde_json::json!({"error": e.to_string()}),
+    };
+
+    match toolbox.max_result_tokens() {
+        Some(max_tokens) => fit_to_tokens(result, max_tokens).to_string(),
+        None => result.to_string(),
+    }
+}
+
+/// Like [`execute_tool_call`], but on a task of its own, so a tool that panics
+/// fails only its own call, with an `{"error": ...}` result, not the whole turn.
+async fn execute_tool_call_isolated(toolbox: &ToolBox, call: &PendingToolCall) -> String {
+    let (toolbox, call) = (toolbox.clone(), call.clone());
+    tokio::spawn(async move { execute_tool_call(&toolbox, &call).await }).await
+        .unwrap_or_else(|e| serde_json::json!({"error": format!("Tool failed: {}", e)}).to_string())
+}
+
+/// Trims a tool result to about `max_tokens`. The longest string in it, usually
+/// the file content or command output, is cut and marked, so the result stays
+/// valid JSON with its other fields intact. Results that can't be trimmed that
+/// way are replaced by a marked prefix of their text.
+pub fn fit_to_tokens(result: serde_json::Value, max_tokens: u64) -> serde_json::Value {
+    let text = result.to_string();
+    let tokens = estimate_text_tokens(&text);
+    if tokens <= max_tokens {
+        return result;
+    }
+
+    let marker = format!("\n[... trimmed, the full result was about {} tokens ...]", tokens);
+    let max_chars = max_tokens as usize * 4;
+
+    // Escaped characters count more than once, so cut again by whatever still overruns
+    let longest = longest_string_len(&result);
+    let mut keep = longest.min(max_chars);
+    while keep > 0 {
+        let mut trimmed = result.clone();
+        cut_string_of_len(&mut trimmed, longest, keep, &marker);
+        let overrun = trimmed.to_string().chars().count().saturating_sub(max_chars);
+        if overrun == 0 {
+            return trimmed;
+        }
+        keep = keep.saturating_sub(overrun);
+    }
+
+    let mut keep = max_chars.saturating_sub(marker.chars().count() + 64);
+    loop {
+        let prefix: String = text.chars().take(keep).collect();
+        let fallback = serde_json::json!({"truncated": true, "partial_result": prefix + &marker});
+        let overrun = fallback.to_string().chars().count().saturating_sub(max_chars);
+        if overrun == 0 || keep == 0 {
+            return fallback;
+        }
+        keep = keep.saturating_sub(overrun);
+    }
+}
+
+fn longest_string_len(value: &serde_json::Value) -> usize {
+    match value {
+        serde_json::Value::String(text) => text.chars().count(),
+        serde_json::Value::Array(items) => items.iter().map(longest_string_len).max().unwrap_or(0),
+        serde_json::Value::Object(fields) => fields.values().map(longest_string_len).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Cuts the first string of `len` characters down to `keep` and appends `marker`.
+fn cut_string_of_len(value: &mut serde_json::Value, len: usize, keep: usize, marker: &str) -> bool {
+    match value {
+        serde_json::Value::String(text) if text.chars().count() == len => {
+            *text = text.chars().take(keep).collect::<String>() + marker;
+            true
+        }
+        serde_json::Value::Array(items) => items.iter_mut().any(|item| cut_string_of_len(item, len, keep, marker)),
+        serde_json::Value::Object(fields) => fields.values_mut().any(|field| cut_string_of_len(field, len, keep, marker)),
+        _ => false,
+    }
+}
+
+/// Runs all calls, concurrently if `parallel` is set, and returns the results keyed by call id.
+pub async fn execute_tool_calls(
+    toolbox: &ToolBox,
+    calls: &[PendingToolCall],
+    parallel: bool,
+    tracer: Option<&ToolTracer>,
+) -> Result<HashMap<String, String>, tokio::task::JoinError> {
+    let mut results = HashMap::new();
+
+    if !parallel {
+        for call in calls {
+            let result = execute_tool_call_isolated(toolbox, call).await;
+            if let Some(tracer) = tracer {
+                tracer(call, &result);
+            }
+            results.insert(call.id.clone(), result);
+        }
+        return Ok(results);
+    }
+
+    let mut running = tokio::task::JoinSet::new();
+    for call in calls.iter().cloned() {
+        let toolbox = toolbox.clone();
+        running.spawn(async move {
+            let result = execute_tool_call_isolated(&toolbox, &call).await;
+            (call, result)
+        });
+    }
+
+    // Results arrive in completion order, which need not match the call order
+    while let Some(joined) = running.join_next().await {
+        let (call, result) = joined?;
+        if let Some(tracer) = tracer {
+            tracer(&call, &result);
+        }
+        results.insert(call.id, result);
+    }
+
+    Ok(results)
+}
+
+/// Sent along with a cached result when the model repeats its previous call.
+pub const REPEATED_CALL_NOTICE: &str = "You just made this exact call, so it was not run again and this is the previous result. \
+Don't repeat it; use the result or try something different.";
+
+/// The last call run in a conversation and the result it got.
+#[derive(Debug, Clone)]
+pub struct LastToolCall {
+    pub name: String,
+    pub arguments: String,
+    pub result: String,
+}
+
+/// Whether `call` repeats a call of `name` with `arguments`. Arguments compare
+/// as JSON, so formatting and key order don't matter.
+fn repeats(name: &str, arguments: &str, call: &PendingToolCall) -> bool {
+    if name != call.name {
+        return false;
+    }
+    match (
+        serde_json::from_str::<serde_json::Value>(arguments),
+        serde_json::from_str::<serde_json::Value>(&call.arguments),
+    ) {
+        (Ok(previous), Ok(current)) => previous == current,
+        _ => arguments == call.arguments,
+    }
+}
+
+/// The result sent instead of running a repeated call.
+fn repeated_call_result(cached: &str) -> String {
+    let cached = serde_json::from_str(cached).unwrap_or_else(|_| serde_json::Value::String(cached.to_string()));
+    serde_json::json!({"notice": REPEATED_CALL_NOTICE, "result": cached}).to_string()
+}
+
+/// Like [`execute_tool_calls`], but a call identical to the one right before
+/// it, possibly from the previous turn in `last`, isn't run again. It gets the
+/// earlier result back with [`REPEATED_CALL_NOTICE`], which breaks loops some
+/// models fall into. `last` is updated to the final call.
+pub async fn execute_tool_calls_deduplicated(
+    toolbox: &ToolBox,
+    calls: &[PendingToolCall],
+    parallel: bool,
+    tracer: Option<&ToolTracer>,
+    last: &mut Option<LastToolCall>,
+) -> Result<HashMap<String, String>, tokio::task::JoinError> {
+    let mut fresh: Vec<PendingToolCall> = Vec::new();
+    let mut previous = last.as_ref().map(|last| (last.name.as_str(), last.arguments.as_str()));
+    for call in calls {
+        if !previous.is_some_and(|(name, arguments)| repeats(name, arguments, call)) {
+            fresh.push(call.clone());
+        }
+        previous = Some((&call.name, &call.arguments));
+    }
+
+    let mut results = execute_tool_calls(toolbox, &fresh, parallel, tracer).await?;
+
+    for call in calls {
+        match last.as_ref() {
+            Some(previous) if !fresh.iter().any(|fresh| fresh.id == call.id) => {
+                let result = repeated_call_result(&previous.result);
+                if let Some(tracer) = tracer {
+                    tracer(call, &result);
+                }
+                results.insert(call.id.clone(), result);
+            }
+            _ => {
+                *last = Some(LastToolCall {
+                    name: call.name.clone(),
+                    arguments: call.arguments.clone(),
+                    result: results.get(&call.id).cloned().unwrap_or_default(),
+                });
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+/// Builds one tool message per call, in the order the model issued the calls,
+/// regardless of the order the results were produced in.
+pub fn ordered_tool_results(calls: &[PendingToolCall], mut results: HashMap<String, String>) -> Vec<openai::Message> {
+    calls.iter()
+        .map(|call| {
+            let result = results.remove(&call.id).unwrap_or_else(|| {
+                serde_json::json!({"error": "Tool produced no result"}).to_string()
+            });
+            openai::tool_call_result(call.id.clone(), result)
+        })
+        .collect()
+}
+
+/// Shrinks tool results older than the last `keep_turns` prompts to
+/// [`STALE_TOOL_RESULT`]. Files added as context don't count as prompts. The
+/// messages themselves stay, so every tool call still has its result and the
+/// history remains valid to send.
+pub fn compact_stale_tool_results(messages: &mut [openai::Message], keep_turns: usize) {
+    let mut turns = 0;
+    for message in messages.iter_mut().rev() {
+        match message.role {
+            Some(openai::Role::User) if !is_context_message(message) => turns += 1,
+            Some(openai::Role::Tool) if turns >= keep_turns => {
+                message.content = Some(STALE_TOOL_RESULT.to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(id: &str) -> PendingToolCall {
+        PendingToolCall {
+            id: id.to_string(),
+            name: "read_file".to_string(),
+            arguments: "{}".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_ordered_tool_results_out_of_order_completion() {
+        let calls = vec![call("call_a"), call("call_b"), call("call_c")];
+
+        // Simulate completion in reverse order
+        let mut results = HashMap::new();
+        results.insert("call_c".to_string(), "c".to_string());
+        results.insert("call_a".to_string(), "a".to_string());
+        results.insert("call_b".to_string(), "b".to_string());
+
+        let messages = ordered_tool_results(&calls, results);
+
+        let ids: Vec<_> = messages.iter().map(|m| m.tool_call_id.clone().unwrap()).collect();
+        let contents: Vec<_> = messages.iter().map(|m| m.content.clone().unwrap()).collect();
+        assert_eq!(ids, vec!["call_a", "call_b", "call_c"]);
+        assert_eq!(contents, vec!["a", "b", "c"]);
+    }
+
+    /// Panics on every call, like a buggy custom tool.
+    struct Panicking;
+
+    impl tools::ToolHandler for Panicking {
+        fn name(&self) -> &str {
+            "panic"
+        }
+
+        fn description(&self) -> &str {
+            "Panics"
+        }
+
+        fn parameters(&self) -> serde_json::Value {
+            serde_json::json!({"type": "object", "properties": {}})
+        }
+
+        fn run<'a>(&'a self, _ctx: &'a tools::ToolContext, _args: serde_json::Value) -> tools::ToolFuture<'a> {
+            Box::pin(async { panic!("tool bug") })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicking_tool_fails_only_its_call() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut toolbox = ToolBox::with_context(tools::ToolContext::new(dir.path()));
+        toolbox.register_handler(Panicking).unwrap();
+        let calls = vec![
+            PendingToolCall { id: "call_a".to_string(), name: "panic".to_string(), arguments: "{}".to_string() },
+            PendingToolCall { id: "call_b".to_string(), name: "get_folder_files".to_string(), arguments: r#"{"folder_path": "."}"#.to_string() },
+        ];
+
+        for parallel in [false, true] {
+            let results = execute_tool_calls(&toolbox, &calls, parallel, None).await.unwrap();
+
+            let failed: serde_json::Value = serde_json::from_str(&results["call_a"]).unwrap();
+            assert!(failed["error"].as_str().unwrap().contains("panicked"));
+            assert!(!results["call_b"].contains("error"));
+        }
+    }
+
+    #[test]
+    fn test_assign_tool_call_ids() {
+        let tool_call = |id: Option<&str>| ToolCall {
+            id: id.map(String::from),
+            index: None,
+            tool_type: Some("function".to_string()),
+            function: openai::FunctionCall { name: Some("read_file".to_string()), arguments: "{}".to_string() },
+        };
+        let mut tool_calls = vec![tool_call(Some("call_a")), tool_call(None), tool_call(Some("call_a"))];
+
+        assign_tool_call_ids(&mut tool_calls, 2);
+
+        let ids: Vec<_> = tool_calls.iter().map(|call| call.id.clone().unwrap()).collect();
+        assert_eq!(ids, vec!["call_a", "call_codr_2_1", "call_codr_2_2"]);
+    }
+
+    #[tokio::test]
+    async fn test_large_result_trimmed_to_token_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("big.txt"), "line of text\n".repeat(5000)).unwrap();
+        let mut toolbox = ToolBox::with_context(tools::ToolContext::new(dir.path()));
+        toolbox.set_max_result_tokens(Some(500));
+
+        let result = execute_tool_call(&toolbox, &PendingToolCall {
+            id: "call_a".to_string(),
+            name: "read_file".to_string(),
+            arguments: serde_json::json!({"file_path": dir.path().join("big.txt")}).to_string(),
+        }).await;
+
+        assert!(estimate_text_tokens(&result) <= 500);
+        let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+        let content = result["content"].as_str().unwrap();
+        assert!(content.starts_with("line of text\n"));
+        assert!(content.ends_with("[... trimmed, the full result was about 17504 tokens ...]"));
+    }
+
+    #[test]
+    fn test_fit_to_tokens_without_long_string() {
+        let result = serde_json::json!((0..1000).collect::<Vec<_>>());
+
+        let trimmed = fit_to_tokens(result, 100);
+
+        assert!(estimate_text_tokens(&trimmed.to_string()) <= 100);
+        assert_eq!(trimmed["truncated"], true);
+        assert!(trimmed["partial_result"].as_str().unwrap().starts_with("[0,1,2,"));
+        assert_eq!(fit_to_tokens(serde_json::json!({"status": "success"}), 100), serde_json::json!({"status": "success"}));
+    }
+
+    #[test]
+    fn test_compact_stale_tool_results() {
+        let exchange = |prompt: &str, id: &str| vec![
+            openai::simple_message(prompt.to_string(), openai::Role::User),
+            openai::assistant_tool_call_message(vec![ToolCall {
+                id: Some(id.to_string()),
+                index: None,
+                tool_type: Some("function".to_string()),
+                function: openai::FunctionCall { name: Some("read_file".to_string()), arguments: "{}".to_string() },
+            }]),
+            openai::tool_call_result(id.to_string(), format!("contents for {}", id)),
+            openai::simple_message("Done".to_string(), openai::Role::Assistant),
+        ];
+        let mut messages = vec![openai::simple_message("You are a test".to_string(), openai::Role::System)];
+        messages.extend(exchange("first", "call_1"));
+        messages.extend(exchange("second", "call_2"));
+        // Context files come with a prompt, they aren't turns of their own
+        messages.push(openai::simple_message("Contents of notes.md:\n```\nnotes\n```".to_string(), openai::Role::User));
+        messages.extend(exchange("third", "call_3"));
+        let before = messages.len();
+
+        compact_stale_tool_results(&mut messages, 2);
+
+        assert_eq!(messages.len(), before);
+        let results: Vec<_> = messages.iter()
+            .filter(|message| matches!(message.role, Some(openai::Role::Tool)))
+            .map(|message| (message.tool_call_id.as_deref().unwrap(), message.content.as_deref().unwrap()))
+            .collect();
+        assert_eq!(results, vec![
+            ("call_1", STALE_TOOL_RESULT),
+            ("call_2", "contents for call_2"),
+            ("call_3", "contents for call_3"),
+        ]);
+        assert_eq!(messages[2].tool_calls.as_ref().unwrap()[0].id.as_deref(), Some("call_1"));
+    }
+
+    #[test]
+    fn test_ordered_tool_results_missing_result() {
+        let messages = ordered_tool_results(&[call("call_a")], HashMap::new());
+
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].content.as_deref().unwrap().contains("error"));
+    }
+}
@@ -0,0 +1,55 @@
+/// Heads the list of tools appended to the system prompt.
+const TOOL_LIST_HEADING: &str = "You have these tools. Use them instead of guessing:";
+
+/// A line per tool with its name and the first sentence of its description.
+pub fn tool_list(tools: &[openai::Tool]) -> String {
+    let mut list = TOOL_LIST_HEADING.to_string();
+    for tool in tools {
+        let description = &tool.function.description;
+        let purpose = description.split_once(". ").map_or(description.as_str(), |(first, _)| first);
+        list.push_str(&format!("\n- {}: {}", tool.function.name, purpose.trim_end_matches('.')));
+    }
+    list
+}
+
+/// `messages` with [`tool_list`] appended to the system prompt, leaving the
+/// stored conversation as it is so the list always matches the tools offered.
+pub fn with_tool_list(messages: &[openai::Message], tools: &[openai::Tool]) -> Vec<openai::Message> {
+    let mut messages = messages.to_vec();
+    if let Some(system_prompt) = messages.first_mut()
+        .filter(|message| matches!(message.role, Some(openai::Role::System)))
+        .and_then(|message| message.content.as_mut()) {
+        system_prompt.push_str("\n\n");
+        system_prompt.push_str(&tool_list(tools));
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool(name: &str, description: &str) -> openai::Tool {
+        openai::Tool {
+            tool_type: "function".to_string(),
+            function: openai::Function {
+                name: name.to_string(),
+                description: description.to_string(),
+                parameters: serde_json::json!({"type": "object", "properties": {}}),
+            },
+        }
+    }
+
+    #[test]
+    fn test_tool_list_uses_first_sentence() {
+        let tools = [
+            tool("read_file", "Reads content from a file"),
+            tool("head_tail", "Returns only the first or last lines of a file. Use it to peek at logs."),
+        ];
+
+        assert_eq!(
+            tool_list(&tools),
+            format!("{}\n- read_file: Reads content from a file\n- head_tail: Returns only the first or last lines of a file", TOOL_LIST_HEADING),
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+/// Appends conversation messages to a JSONL file, one message per line.
+#[derive(Debug)]
+pub struct Transcript {
+    writer: Mutex<BufWriter<File>>,
+    pretty_tool_json: bool,
+}
+
+impl Transcript {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Transcript {
+            writer: Mutex::new(BufWriter::new(File::create(path)?)),
+            pretty_tool_json: false,
+        })
+    }
+
+    /// Records tool call arguments and tool results as indented JSON, for
+    /// reading. Each message still takes one line, and what the model is sent
+    /// stays compact.
+    pub fn with_pretty_tool_json(mut self, pretty: bool) -> Self {
+        self.pretty_tool_json = pretty;
+        self
+    }
+
+    pub fn record(&self, message: &openai::Message) -> io::Result<()> {
+        let message = match self.pretty_tool_json {
+            true => prettified(message),
+            false => message.clone(),
+        };
+        let mut writer = self.writer.lock().unwrap();
+        serde_json::to_writer(&mut *writer, &message)?;
+        writeln!(writer)
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.writer.lock().unwrap().flush()
+    }
+}
+
+/// `text` indented if it is JSON, otherwise as it is.
+pub fn pretty_json(text: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(text)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or_else(|_| text.to_string())
+}
+
+fn prettified(message: &openai::Message) -> openai::Message {
+    let mut message = message.clone();
+    for call in message.tool_calls.iter_mut().flatten() {
+        call.function.arguments = pretty_json(&call.function.arguments);
+    }
+    if message.tool_call_id.is_some() {
+        message.content = message.content.as_deref().map(pretty_json);
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai::{Role, simple_message};
+
+    #[test]
+    fn test_record_writes_jsonl() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transcript.jsonl");
+
+        let transcript = Transcript::create(&path).unwrap();
+        transcript.record(&simple_message("Hi".to_string(), Role::User)).unwrap();
+        transcript.record(&simple_message("Hello!".to_string(), Role::Assistant)).unwrap();
+        transcript.flush().unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["role"], "user");
+        assert_eq!(lines[1]["content"], "Hello!");
+    }
+
+    #[test]
+    fn test_pretty_tool_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transcript.jsonl");
+        let call = openai::ToolCall {
+            id: Some("call_1".to_string()),
+            index: None,
+            tool_type: Some("function".to_string()),
+            function: openai::FunctionCall { name: Some("read_file".to_string()), arguments: r#"{"file_path":"main.rs"}"#.to_string() },
+        };
+        let broken = openai::ToolCall {
+            function: openai::FunctionCall { name: Some("read_file".to_string()), arguments: r#"{"file_path": "ma"#.to_string() },
+            ..call.clone()
+        };
+
+        let transcript = Transcript::create(&path).unwrap().with_pretty_tool_json(true);
+        transcript.record(&openai::assistant_tool_call_message(vec![call, broken])).unwrap();
+        transcript.record(&openai::tool_call_result("call_1".to_string(), r#"{"content":"fn main() {}"}"#.to_string())).unwrap();
+        transcript.flush().unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["tool_calls"][0]["function"]["arguments"], "{\n  \"file_path\": \"main.rs\"\n}");
+        // Not JSON, so kept as it was
+        assert_eq!(lines[0]["tool_calls"][1]["function"]["arguments"], r#"{"file_path": "ma"#);
+        assert_eq!(lines[1]["content"], "{\n  \"content\": \"fn main() {}\"\n}");
+    }
+}
@@ -0,0 +1,33 @@
+use std::{fmt, future::Future, pin::Pin};
+
+/// Error a provider fails with when it can't produce a token.
+pub type AuthError = Box<dyn std::error::Error + Send + Sync>;
+
+/// What [`AuthProvider::token`] returns; implementations typically wrap an
+/// `async move` block in `Box::pin`.
+pub type TokenFuture<'a> = Pin<Box<dyn Future<Output = Result<String, AuthError>> + Send + 'a>>;
+
+/// Supplies the bearer token for each request, so short-lived credentials,
+/// e.g. OIDC-issued ones, can be refreshed without rebuilding the client.
+/// Called before every request, retries included; cache the token inside the
+/// provider if fetching it is expensive.
+pub trait AuthProvider: Send + Sync {
+    fn token(&self) -> TokenFuture<'_>;
+}
+
+impl fmt::Debug for dyn AuthProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the token itself
+        f.write_str("AuthProvider")
+    }
+}
+
+/// The same API key for every request.
+#[derive(Clone)]
+pub struct StaticToken(pub String);
+
+impl AuthProvider for StaticToken {
+    fn token(&self) -> TokenFuture<'_> {
+        Box::pin(async move { Ok(self.0.clone()) })
+    }
+}
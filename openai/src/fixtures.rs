@@ -0,0 +1,153 @@
+use std::{
+    fs, io,
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::Deserialize;
+
+use crate::recording::{Exchange, ResponseSource};
+
+/// A canned assistant turn: text, tool calls or both.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fixture {
+    /// Only answers requests whose last user message is this prompt. Fixtures
+    /// without one answer whatever comes next.
+    #[serde(default)]
+    pub prompt: Option<String>,
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default)]
+    pub tool_calls: Vec<FixtureToolCall>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FixtureToolCall {
+    /// Defaults to `call_<n>`, numbered across the whole file.
+    #[serde(default)]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub arguments: serde_json::Value,
+}
+
+/// Serves the turns of a fixtures file, a JSON array of [`Fixture`]s. Each
+/// request gets the first unused fixture that matches its prompt, so a file
+/// can be played in order, keyed by prompt, or both.
+#[derive(Debug)]
+pub struct Fixtures {
+    state: Mutex<(Vec<Fixture>, usize)>,
+}
+
+impl Fixtures {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(json: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let fixtures: Vec<Fixture> = serde_json::from_str(json)?;
+        Ok(Fixtures { state: Mutex::new((fixtures, 0)) })
+    }
+}
+
+impl ResponseSource for Fixtures {
+    fn respond(&self, request: &serde_json::Value) -> io::Result<Exchange> {
+        let prompt = last_user_prompt(request);
+        let mut state = self.state.lock().unwrap();
+        let (fixtures, calls_served) = &mut *state;
+
+        let index = fixtures.iter()
+            .position(|fixture| fixture.prompt.as_deref().is_none_or(|expected| Some(expected.trim()) == prompt))
+            .ok_or_else(|| io::Error::other(format!("No fixture left for prompt: {}", prompt.unwrap_or_default())))?;
+        let fixture = fixtures.remove(index);
+
+        let tool_calls: Vec<serde_json::Value> = fixture.tool_calls.iter().enumerate()
+            .map(|(index, call)| {
+                *calls_served += 1;
+                serde_json::json!({
+                    "index": index,
+                    "id": call.id.clone().unwrap_or_else(|| format!("call_{}", calls_served)),
+                    "type": "function",
+                    "function": {"name": call.name, "arguments": call.arguments.to_string()}
+                })
+            })
+            .collect();
+        let finish_reason = if tool_calls.is_empty() { "stop" } else { "tool_calls" };
+
+        let mut message = serde_json::json!({"role": "assistant", "content": fixture.content});
+        if !tool_calls.is_empty() {
+            message["tool_calls"] = tool_calls.into();
+        }
+
+        if request["stream"] == true {
+            let chunk = |delta: &serde_json::Value, finish_reason: Option<&str>| format!("data: {}\n\n", serde_json::json!({
+                "object": "chat.completion.chunk",
+                "created": 0,
+                "model": "mock",
+                "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]
+            }));
+            let chunks = vec![
+                chunk(&message, None),
+                chunk(&serde_json::json!({}), Some(finish_reason)),
+                "data: [DONE]\n\n".to_string(),
+            ];
+            Ok(Exchange { request: request.clone(), status: 200, body: None, chunks: Some(chunks) })
+        } else {
+            let body = serde_json::json!({"choices": [{"message": message, "finish_reason": finish_reason}]});
+            Ok(Exchange { request: request.clone(), status: 200, body: Some(body.to_string()), chunks: None })
+        }
+    }
+}
+
+/// The text of the last user message of a request body.
+fn last_user_prompt(request: &serde_json::Value) -> Option<&str> {
+    request["messages"].as_array()?
+        .iter()
+        .rev()
+        .find(|message| message["role"] == "user")
+        .and_then(|message| message["content"].as_str())
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(prompt: &str) -> serde_json::Value {
+        serde_json::json!({"messages": [{"role": "user", "content": prompt}], "stream": false})
+    }
+
+    #[test]
+    fn test_keyed_by_prompt_and_in_order() {
+        let fixtures = Fixtures::parse(r#"[
+            {"prompt": "Bye", "content": "Goodbye"},
+            {"content": "First"},
+            {"content": "Second"}
+        ]"#).unwrap();
+
+        let answer = |prompt: &str| {
+            let body: serde_json::Value = serde_json::from_str(&fixtures.respond(&request(prompt)).unwrap().body.unwrap()).unwrap();
+            body["choices"][0]["message"]["content"].as_str().unwrap().to_string()
+        };
+
+        assert_eq!(answer("Hello"), "First");
+        assert_eq!(answer("Bye"), "Goodbye");
+        assert_eq!(answer("Hello"), "Second");
+        assert!(fixtures.respond(&request("Hello")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_streamed_tool_call() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fixtures.json");
+        fs::write(&path, r#"[{"tool_calls": [{"name": "read_file", "arguments": {"file_path": "main.rs"}}]}]"#).unwrap();
+        let client = crate::OpenAIClient::from_fixtures(&path).unwrap();
+
+        let messages = vec![crate::simple_message("Read main.rs".to_string(), crate::Role::User)];
+        let chunk = client.chat_completion_stream(&messages, None).await.unwrap().recv().await.unwrap();
+
+        let call = &chunk.choices[0].delta.as_ref().unwrap().tool_calls.as_ref().unwrap()[0];
+        assert_eq!(call.id.as_deref(), Some("call_1"));
+        assert_eq!(call.function.arguments, r#"{"file_path":"main.rs"}"#);
+    }
+}
@@ -1,7 +1,161 @@
-use reqwest;
 use serde::{Deserialize, Serialize};
-use serde_json;
-use std::io::{Error, ErrorKind};
+use std::{collections::{BTreeMap, HashMap, VecDeque, btree_map::Entry}, path::Path, sync::Arc, time::Duration};
+
+pub mod auth;
+#[cfg(feature = "mock")]
+pub mod fixtures;
+pub mod partial_json;
+pub mod recording;
+pub mod retry;
+pub mod stop_sequences;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+
+use recording::{Exchange, Recorder, Replay, ResponseSource};
+use stop_sequences::StopSequences;
+pub use auth::{AuthProvider, StaticToken};
+pub use retry::RetryConfig;
+
+/// Everything a request can fail with, so callers can `match` on the cause
+/// instead of inspecting error text.
+#[derive(Debug)]
+pub enum OpenAIError {
+    /// The API answered, but the payload lacks something the client relies on.
+    MalformedResponse(String),
+    /// The API answered with a body that isn't a valid completion.
+    Deserialize(serde_json::Error),
+    /// The request got no response, e.g. the connection failed or timed out.
+    Network(reqwest::Error),
+    /// The API answered with an error status.
+    Api { status: u16, body: String },
+    /// The provider rejected the credentials (status 401 or 403, or an
+    /// `invalid_api_key` error), or the [`AuthProvider`] couldn't supply a
+    /// token, so no request was sent.
+    Auth(String),
+    /// The account is out of credits or over its spending limit.
+    InsufficientQuota(String),
+    /// The conversation doesn't fit in the model's context window.
+    ContextLengthExceeded(String),
+    /// Too many requests or tokens in too short a time (status 429), with how
+    /// long the provider asked to wait, if it said.
+    RateLimited { message: String, retry_after: Option<Duration> },
+    /// The [`CompletionOptions`] are invalid, so no request was sent.
+    InvalidOptions(String),
+    /// A recording or fixtures file had no response to give.
+    Replay(std::io::Error),
+}
+
+impl OpenAIError {
+    /// Whether the same request to the same provider could succeed after a
+    /// wait: network failures, rate limiting and server errors.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            OpenAIError::Network(_) | OpenAIError::RateLimited { .. } => true,
+            OpenAIError::Api { status, .. } => is_retryable_status(*status),
+            OpenAIError::MalformedResponse(_)
+            | OpenAIError::Deserialize(_)
+            | OpenAIError::Auth(_)
+            | OpenAIError::InsufficientQuota(_)
+            | OpenAIError::ContextLengthExceeded(_)
+            | OpenAIError::InvalidOptions(_)
+            | OpenAIError::Replay(_) => false,
+        }
+    }
+
+    /// Whether another provider could serve the request: anything worth
+    /// retrying, plus exhausted quota, which waiting won't fix.
+    pub fn should_fail_over(&self) -> bool {
+        self.is_retryable() || matches!(self, OpenAIError::InsufficientQuota(_))
+    }
+
+    /// The error for a response with an error `status`, typed when the body is
+    /// a provider error of a known type or the status says what went wrong,
+    /// otherwise [`OpenAIError::Api`].
+    pub fn from_response(status: u16, body: String) -> Self {
+        let (message, kind) = match serde_json::from_str::<ErrorBody>(&body) {
+            Ok(ErrorBody { error }) => {
+                // Providers put the specific reason in either field
+                let kinds = [error.code.as_ref().and_then(serde_json::Value::as_str), error.kind.as_deref()];
+                let kind = kinds.into_iter().flatten().find(|kind| KNOWN_ERROR_TYPES.contains(kind)).map(str::to_string);
+                (error.message.unwrap_or_default(), kind)
+            }
+            Err(_) => (body.clone(), None),
+        };
+
+        match (kind.as_deref(), status) {
+            (Some("invalid_api_key"), _) | (None, 401 | 403) => OpenAIError::Auth(message),
+            (Some("insufficient_quota"), _) => OpenAIError::InsufficientQuota(message),
+            (Some("context_length_exceeded"), _) => OpenAIError::ContextLengthExceeded(message),
+            (Some("rate_limit_exceeded"), _) | (None, 429) => OpenAIError::RateLimited { message, retry_after: None },
+            _ => OpenAIError::Api { status, body },
+        }
+    }
+
+    /// Attaches the wait a provider asked for to a [`OpenAIError::RateLimited`].
+    fn with_retry_after(mut self, retry_after: Option<Duration>) -> Self {
+        if let OpenAIError::RateLimited { retry_after: wait, .. } = &mut self {
+            *wait = retry_after;
+        }
+        self
+    }
+}
+
+const KNOWN_ERROR_TYPES: [&str; 4] = ["invalid_api_key", "insufficient_quota", "context_length_exceeded", "rate_limit_exceeded"];
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: ErrorDetails,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetails {
+    message: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    /// A string for most providers, but some send numbers.
+    code: Option<serde_json::Value>,
+}
+
+/// The wait a response asks for in its `Retry-After` header.
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response.headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| retry::parse_retry_after(value, std::time::SystemTime::now()))
+}
+
+fn is_retryable_status(status: u16) -> bool {
+    status == 429 || status >= 500
+}
+
+impl std::fmt::Display for OpenAIError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpenAIError::MalformedResponse(details) => write!(f, "Malformed response: {}", details),
+            OpenAIError::Deserialize(e) => write!(f, "Failed to parse response: {}", e),
+            OpenAIError::Network(e) => write!(f, "Request failed: {}", e),
+            OpenAIError::Api { status, body } => write!(f, "Error {}: {}", status, body),
+            OpenAIError::Auth(details) => write!(f, "Authentication failed: {}", details),
+            OpenAIError::InsufficientQuota(message) => write!(f, "Insufficient quota: {}", message),
+            OpenAIError::ContextLengthExceeded(message) => write!(f, "Context length exceeded: {}", message),
+            OpenAIError::RateLimited { message, retry_after: Some(wait) } => write!(f, "Rate limit exceeded, retry after {:?}: {}", wait, message),
+            OpenAIError::RateLimited { message, retry_after: None } => write!(f, "Rate limit exceeded: {}", message),
+            OpenAIError::InvalidOptions(details) => write!(f, "{}", details),
+            OpenAIError::Replay(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for OpenAIError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OpenAIError::Deserialize(e) => Some(e),
+            OpenAIError::Network(e) => Some(e),
+            OpenAIError::Replay(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Role {
@@ -12,298 +166,1984 @@ pub enum Role {
     #[serde(rename = "assistant")]
     Assistant,
     #[serde(rename = "tool")]
-    Tool
+    Tool,
+    /// Tool results of the legacy function calling API, see [`OpenAIClient::with_legacy_functions`].
+    #[serde(rename = "function")]
+    Function,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Message {
+    #[serde(rename = "role")]
+    pub role: Option<Role>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// Sources cited in `content`, from models with web or file search.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Vec<Annotation>>,
+    /// The call of the legacy function calling API, turned into `tool_calls` as the response is read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<FunctionCall>,
+    /// The thinking of a reasoning model, kept apart from `content`. Providers
+    /// name it `reasoning_content` or `reasoning`.
+    #[serde(default, alias = "reasoning", skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
+}
+
+impl Message {
+    /// URLs and file names cited by the annotations, in order, without duplicates.
+    pub fn sources(&self) -> Vec<String> {
+        let mut sources = Vec::new();
+        for annotation in self.annotations.iter().flatten() {
+            if let Some(source) = annotation.source()
+                && !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+        sources
+    }
+}
+
+/// Metadata attached to a span of a message, such as a citation.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Annotation {
+    /// `"url_citation"` or `"file_citation"`; other kinds are kept as they are.
+    #[serde(rename = "type")]
+    pub annotation_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url_citation: Option<UrlCitation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_citation: Option<FileCitation>,
+}
+
+impl Annotation {
+    /// The cited URL or file, if this is a citation.
+    pub fn source(&self) -> Option<String> {
+        match (&self.url_citation, &self.file_citation) {
+            (Some(url), _) => Some(url.url.clone()),
+            (None, Some(file)) => Some(file.filename.clone().unwrap_or_else(|| file.file_id.clone())),
+            (None, None) => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct UrlCitation {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Character range of `content` the citation supports.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_index: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_index: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FileCitation {
+    pub file_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Choice {
+    pub message: Option<Message>,
+    pub delta: Option<Message>,
+    pub finish_reason: Option<String>,
+    /// Token probabilities, when requested through [`CompletionOptions::logprobs`].
+    #[serde(default)]
+    pub logprobs: Option<Logprobs>,
+}
+
+/// Log probabilities of the tokens in a choice.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Logprobs {
+    #[serde(default)]
+    pub content: Option<Vec<TokenLogprob>>,
+    /// Probabilities of the tokens of a refusal, for models that may refuse.
+    #[serde(default)]
+    pub refusal: Option<Vec<TokenLogprob>>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    /// UTF-8 bytes of the token, for tokens that split a character.
+    #[serde(default)]
+    pub bytes: Option<Vec<u8>>,
+    /// The most likely tokens at this position, up to `top_logprobs` of them.
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    #[serde(default)]
+    pub bytes: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatCompletion {
+    pub choices: Vec<Choice>,
+    /// Token counts, when the provider reports them.
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StreamChunk {
+    pub object: String,
+    pub created: i64,
+    pub model: String,
+    pub choices: Vec<Choice>,
+    /// Token counts of the whole response, usually in a last chunk of their own.
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct StreamChannelChunk {
+    pub finished: bool,
+    pub final_content: Option<String>,
+    /// With `final_content`, everything a reasoning model thought, if it said.
+    #[serde(default)]
+    pub final_reasoning: Option<String>,
+    pub choices: Vec<Choice>,
+    /// Why the tool call in `choices` must not run, e.g. because its arguments
+    /// were too large to buffer and were dropped. On the last chunk of an
+    /// answer, why the stream ended early, e.g. because it stalled.
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Set on the last chunk when the stream was cut off at the limit from
+    /// [`OpenAIClient::with_max_stream_bytes`] rather than finished by the model.
+    #[serde(default)]
+    pub truncated: bool,
+    /// Token counts of the response, on the last chunk of a turn when the
+    /// provider reports them, see [`OpenAIClient::with_stream_usage`].
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+/// Default cap on the streamed arguments of a single tool call, see
+/// [`OpenAIClient::with_max_tool_argument_bytes`].
+pub const DEFAULT_MAX_TOOL_ARGUMENT_BYTES: usize = 16 * 1024 * 1024;
+
+/// Connection settings for the underlying HTTP client. Clones of an
+/// `OpenAIClient` share one connection pool, so connections are reused across
+/// requests (and negotiated as HTTP/2 over TLS when the server offers it).
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    pub pool_max_idle_per_host: usize,
+    pub pool_idle_timeout: Option<Duration>,
+    pub tcp_keepalive: Option<Duration>,
+    /// How long to wait for a connection to be established.
+    pub connect_timeout: Option<Duration>,
+    /// How long a stream may go without receiving data before it is treated
+    /// as dead. Unlike an overall timeout this never cuts off a long stream
+    /// that keeps making progress.
+    pub read_timeout: Option<Duration>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            pool_max_idle_per_host: usize::MAX,
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            tcp_keepalive: Some(Duration::from_secs(60)),
+            connect_timeout: Some(Duration::from_secs(30)),
+            read_timeout: None,
+        }
+    }
+}
+
+impl HttpConfig {
+    fn build_client(&self) -> reqwest::Client {
+        let mut builder = reqwest::Client::builder()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .tcp_keepalive(self.tcp_keepalive);
+
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+
+        builder.build().expect("Failed to build HTTP client")
+    }
+}
+
+/// Optional request parameters sent with every completion. Unset fields are
+/// left out of the request body so providers that don't know them never see them.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CompletionOptions {
+    /// Processing tier, e.g. `"auto"`, `"default"` or `"flex"`, for plans that offer them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<String>,
+    /// Constrains the output format, e.g. `{"type": "json_schema", "json_schema": {...}}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<serde_json::Value>,
+    /// How hard a reasoning model thinks before answering: `"low"`, `"medium"` or `"high"`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<String>,
+    /// Sequences at which the model stops generating, not included in the output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    /// Returns the log probability of each output token in [`Choice::logprobs`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    /// How many of the most likely alternatives to return per token, up to 20. Needs `logprobs`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u8>,
+    /// Tags stored with the request by providers that support it, e.g. a session
+    /// id or feature name for server-side analytics. Left out when empty.
+    #[serde(skip_serializing_if = "is_none_or_empty")]
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+fn is_none_or_empty(metadata: &Option<HashMap<String, String>>) -> bool {
+    metadata.as_ref().is_none_or(HashMap::is_empty)
+}
+
+const REASONING_EFFORTS: &[&str] = &["low", "medium", "high"];
+
+const MAX_TOP_LOGPROBS: u8 = 20;
+
+impl CompletionOptions {
+    /// Checks the options for values no provider would accept.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(effort) = &self.reasoning_effort
+            && !REASONING_EFFORTS.contains(&effort.as_str()) {
+            return Err(format!("Invalid reasoning_effort {:?}, expected one of {}", effort, REASONING_EFFORTS.join(", ")));
+        }
+        if let Some(top_logprobs) = self.top_logprobs {
+            if top_logprobs > MAX_TOP_LOGPROBS {
+                return Err(format!("Invalid top_logprobs {}, expected at most {}", top_logprobs, MAX_TOP_LOGPROBS));
+            }
+            if self.logprobs != Some(true) {
+                return Err("top_logprobs requires logprobs to be enabled".to_string());
+            }
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Message {
-    #[serde(rename = "role")]
-    pub role: Option<Role>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub content: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub tool_calls: Option<Vec<ToolCall>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub tool_call_id: Option<String>,
-}
+#[derive(Debug, Clone)]
+pub struct OpenAIClient {
+    http_client: reqwest::Client,
+    auth: Arc<dyn AuthProvider>,
+    base_url: String,
+    model: String,
+    headers: HashMap<String, String>,
+    read_timeout: Option<Duration>,
+    options: CompletionOptions,
+    retry: RetryConfig,
+    recorder: Option<Arc<Recorder>>,
+    replay: Option<Arc<dyn ResponseSource>>,
+    tool_results_as_user: bool,
+    max_tool_argument_bytes: usize,
+    max_stream_bytes: Option<usize>,
+    stream_usage: bool,
+    legacy_functions: bool,
+}
+
+impl OpenAIClient {
+    pub fn new(base_url: String, api_key: String, model: String) -> Self {
+        let http_config = HttpConfig::default();
+        let mut client = Self::with_http_client(http_config.build_client(), base_url, api_key, model);
+        client.read_timeout = http_config.read_timeout;
+        client
+    }
+
+    /// Creates a client sending its requests through `http_client`, e.g. one
+    /// shared with the rest of an application or wrapped in custom middleware.
+    /// [`HttpConfig`] settings don't apply to it, except through [`Self::with_http_config`].
+    pub fn with_http_client(http_client: reqwest::Client, base_url: String, api_key: String, model: String) -> Self {
+        let base_url = base_url.trim_end_matches('/').to_string();
+
+        OpenAIClient {
+            http_client,
+            auth: Arc::new(StaticToken(api_key)),
+            base_url,
+            model,
+            headers: HashMap::new(),
+            read_timeout: None,
+            options: CompletionOptions::default(),
+            retry: RetryConfig::default(),
+            recorder: None,
+            replay: None,
+            tool_results_as_user: false,
+            max_tool_argument_bytes: DEFAULT_MAX_TOOL_ARGUMENT_BYTES,
+            max_stream_bytes: None,
+            stream_usage: false,
+            legacy_functions: false,
+        }
+    }
+
+    /// Fetches the bearer token from `provider` before every request instead of
+    /// using the fixed API key, for credentials that expire.
+    pub fn with_auth_provider(mut self, provider: impl AuthProvider + 'static) -> Self {
+        self.auth = Arc::new(provider);
+        self
+    }
+
+    /// Adds extra headers sent with every request, e.g. for gateway routing.
+    pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    /// Sends requests to `model` instead, keeping every other setting.
+    pub fn with_model(mut self, model: String) -> Self {
+        self.model = model;
+        self
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Rebuilds the HTTP client with the given connection and timeout settings.
+    pub fn with_http_config(mut self, config: HttpConfig) -> Self {
+        self.http_client = config.build_client();
+        self.read_timeout = config.read_timeout;
+        self
+    }
+
+    /// Sets the optional parameters sent with every completion request.
+    pub fn with_options(mut self, options: CompletionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn options(&self) -> &CompletionOptions {
+        &self.options
+    }
+
+    /// Sends tool results as user messages naming the call they answer, for
+    /// gateways and local models that don't support the `tool` role.
+    pub fn with_tool_results_as_user(mut self, as_user: bool) -> Self {
+        self.tool_results_as_user = as_user;
+        self
+    }
+
+    /// Offers tools through the legacy `functions` parameter and sends calls and
+    /// results as `function_call` and `function` messages, for older endpoints
+    /// without tool calling. Only one call per turn is possible that way.
+    pub fn with_legacy_functions(mut self, legacy: bool) -> Self {
+        self.legacy_functions = legacy;
+        self
+    }
+
+    /// Caps how many bytes of arguments a streamed tool call may have. Past
+    /// that, the arguments are dropped instead of buffered and the call is
+    /// handed over with an [`StreamChannelChunk::error`] saying so.
+    pub fn with_max_tool_argument_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_tool_argument_bytes = max_bytes;
+        self
+    }
+
+    /// Caps how many bytes a single stream may receive, so a model or endpoint
+    /// that never stops can't stream forever. Past that, the connection is
+    /// closed and what arrived is handed over in a last chunk marked
+    /// [`StreamChannelChunk::truncated`]. Unlimited by default.
+    pub fn with_max_stream_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_stream_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Asks for the token counts of streamed responses, handed over on the last
+    /// chunk of each turn as [`StreamChannelChunk::usage`]. They arrive after
+    /// the model finished, so that chunk waits for the end of the stream.
+    pub fn with_stream_usage(mut self, enabled: bool) -> Self {
+        self.stream_usage = enabled;
+        self
+    }
+
+    /// Retries failed non-streaming requests with jittered backoff, honoring `Retry-After`.
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Records every request and its response, streamed chunks included, to a
+    /// JSONL file that [`OpenAIClient::with_replay`] can play back later.
+    pub fn with_recorder(mut self, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        self.recorder = Some(Arc::new(Recorder::create(path)?));
+        Ok(self)
+    }
+
+    /// Answers requests with the responses of a recording, in order, without
+    /// touching the network. Fails once the recording runs out.
+    pub fn with_replay(mut self, path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        self.replay = Some(Arc::new(Replay::load(path)?));
+        Ok(self)
+    }
+
+    /// A client answering every request from a fixtures file, see
+    /// [`fixtures::Fixtures`], for deterministic tests that never call an API.
+    #[cfg(feature = "mock")]
+    pub fn from_fixtures(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut client = Self::with_http_client(reqwest::Client::new(), "http://fixtures.invalid".to_string(), String::new(), "mock".to_string());
+        client.replay = Some(Arc::new(fixtures::Fixtures::load(path)?));
+        Ok(client)
+    }
+
+    fn record(&self, exchange: Exchange) {
+        if let Some(recorder) = &self.recorder
+            && let Err(e) = recorder.record(&exchange) {
+            eprintln!("Error recording exchange: {}", e);
+        }
+    }
+
+    fn request_body(&self, messages: &[Message], tools: Option<&[Tool]>, stream: bool) -> serde_json::Value {
+        // Annotations and reasoning are output only, providers may reject them in a request
+        let messages: Vec<_> = messages.iter()
+            .map(|message| match (&message.role, &message.tool_call_id) {
+                (Some(Role::Tool), Some(id)) if self.tool_results_as_user => simple_message(
+                    format!("Result of tool call {}:\n{}", id, message.content.as_deref().unwrap_or_default()),
+                    Role::User,
+                ),
+                _ => Message { annotations: None, reasoning_content: None, ..message.clone() },
+            })
+            .collect();
+
+        let mut body = match self.legacy_functions {
+            true => serde_json::json!({
+                "model": self.model,
+                "messages": legacy_messages(&messages),
+                "functions": tools.map(|tools| tools.iter().map(|tool| &tool.function).collect::<Vec<_>>()),
+                "stream": stream,
+            }),
+            false => serde_json::json!({
+                "model": self.model,
+                "messages": messages,
+                "tools": tools,
+                "stream": stream,
+            }),
+        };
+
+        if let (Some(body), Ok(serde_json::Value::Object(options))) = (body.as_object_mut(), serde_json::to_value(&self.options)) {
+            body.extend(options);
+        }
+        if stream && self.stream_usage {
+            body["stream_options"] = serde_json::json!({"include_usage": true});
+        }
+
+        body
+    }
+
+    async fn post(&self, url: &str) -> Result<reqwest::RequestBuilder, OpenAIError> {
+        let token = self.auth.token().await.map_err(|e| OpenAIError::Auth(e.to_string()))?;
+        let mut request = self.http_client
+            .post(url)
+            .header("Authorization", format!("Bearer {}", token));
+
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+
+        Ok(request)
+    }
+
+    /// Sends one non-streaming request, returning the status, the body and the `Retry-After` hint.
+    async fn send(&self, url: &str, body: &serde_json::Value) -> Result<(u16, String, Option<Duration>), OpenAIError> {
+        let response = self.post(url).await?
+            .header("Accept", "application/json")
+            .json(body)
+            .send()
+            .await
+            .map_err(OpenAIError::Network)?;
+
+        let status = response.status().as_u16();
+        let retry_after = retry_after(&response);
+        let text = response.text().await.map_err(OpenAIError::Network)?;
+
+        Ok((status, text, retry_after))
+    }
+
+    pub async fn chat_completion(&self, messages: &[Message], tools: Option<Box<Vec<Tool>>>) -> Result<ChatCompletion, OpenAIError> {
+        let url = format!("{}/chat/completions", self.base_url);
+        self.options.validate().map_err(OpenAIError::InvalidOptions)?;
+
+        let body = self.request_body(messages, tools.as_deref().map(Vec::as_slice), false);
+
+        let (status, text, retry_after) = match &self.replay {
+            Some(replay) => {
+                let exchange = replay.respond(&body).map_err(OpenAIError::Replay)?;
+                (exchange.status, exchange.body.unwrap_or_default(), None)
+            }
+            None => {
+                let mut attempt = 0;
+                loop {
+                    let outcome = self.send(&url, &body).await;
+                    let retry_after = match &outcome {
+                        // Classify by the body too, a 429 for exhausted quota won't clear up by waiting
+                        Ok((status, text, retry_after)) if is_retryable_status(*status)
+                            && OpenAIError::from_response(*status, text.clone()).is_retryable() => Some(*retry_after),
+                        Err(e) if e.is_retryable() => Some(None),
+                        _ => None,
+                    };
+
+                    match retry_after {
+                        Some(retry_after) if attempt < self.retry.max_retries => {
+                            tokio::time::sleep(self.retry.delay(attempt, retry_after)).await;
+                            attempt += 1;
+                        }
+                        _ => break outcome?,
+                    }
+                }
+            }
+        };
+
+        self.record(Exchange { request: body, status, body: Some(text.clone()), chunks: None });
+
+        if (200..300).contains(&status) {
+            let mut chat_completion = serde_json::from_str::<ChatCompletion>(&text).map_err(OpenAIError::Deserialize)?;
+            for message in chat_completion.choices.iter_mut().filter_map(|choice| choice.message.as_mut()) {
+                normalize_function_call(message, Some(LEGACY_FUNCTION_CALL_ID));
+            }
+            Ok(chat_completion)
+        } else {
+            Err(OpenAIError::from_response(status, text).with_retry_after(retry_after))
+        }
+    }
+
+    /// Starts a streamed completion. Failures before the stream starts, such as
+    /// an error status, are returned; failures while it streams end it with a
+    /// finished chunk carrying the `error`.
+    pub async fn chat_completion_stream(&self, messages: &[Message], tools: Option<Box<Vec<Tool>>>) -> Result<tokio::sync::mpsc::Receiver<StreamChannelChunk>, OpenAIError> {
+        let url = format!("{}/chat/completions", self.base_url);
+        self.options.validate().map_err(OpenAIError::InvalidOptions)?;
+
+        let body = self.request_body(messages, tools.as_deref().map(Vec::as_slice), true);
+
+        let mut all_content = String::new();
+        // Reasoning streams separately from the answer, sometimes interleaved with it
+        let mut all_reasoning = String::new();
+
+        let (tx, rx) = tokio::sync::mpsc::channel::<StreamChannelChunk>(1);
+
+        let mut source = match &self.replay {
+            Some(replay) => {
+                let exchange = replay.respond(&body).map_err(OpenAIError::Replay)?;
+                if !(200..300).contains(&exchange.status) {
+                    return Err(OpenAIError::from_response(exchange.status, exchange.body.unwrap_or_default()));
+                }
+                ChunkSource::Replay(exchange.chunks.unwrap_or_default().into())
+            }
+            None => {
+                let response = self.post(&url).await?
+                    .header("Accept", "text/event-stream")
+                    .json(&body)
+                    .send()
+                    .await
+                    .map_err(OpenAIError::Network)?;
+
+                if !response.status().is_success() {
+                    let status = response.status().as_u16();
+                    let retry_after = retry_after(&response);
+                    let text = response.text().await.map_err(OpenAIError::Network)?;
+                    return Err(OpenAIError::from_response(status, text).with_retry_after(retry_after));
+                }
+
+                ChunkSource::Http(response)
+            }
+        };
+
+        let read_timeout = self.read_timeout;
+        let recorder = self.recorder.clone();
+        let mut stops = StopSequences::new(self.options.stop.clone().unwrap_or_default());
+        let max_argument_bytes = self.max_tool_argument_bytes;
+        let max_stream_bytes = self.max_stream_bytes;
+        let stream_usage = self.stream_usage;
+
+        tokio::spawn(async move {
+            // Calls being streamed, keyed by their index, as parallel calls arrive interleaved
+            let mut tool_calls: BTreeMap<usize, ToolCall> = BTreeMap::new();
+            // Set once the tool call's arguments outgrew the limit and were dropped
+            let mut argument_error: Option<String> = None;
+            let mut decoder = Utf8Decoder::default();
+            let mut pending = String::new();
+            let mut recorded_chunks = Vec::new();
+            // Whether the final chunk or the tool call was sent, i.e. the model finished its turn
+            let mut finished = false;
+            let mut received = 0;
+            let mut truncated = false;
+            // Why the stream ended before the server finished it, handed over with what arrived
+            let mut interrupted: Option<String> = None;
+            let mut usage: Option<Usage> = None;
+            // The last chunks of the turn, kept until the usage that follows them arrives
+            let mut held: Vec<StreamChannelChunk> = Vec::new();
+            loop {
+                let chunk = match read_timeout {
+                    Some(timeout) => match tokio::time::timeout(timeout, source.next()).await {
+                        Ok(chunk) => chunk.map_err(|e| format!("Error reading chunk: {}", e)),
+                        Err(_) => Err(format!("Stream stalled: no data received for {:?}", timeout)),
+                    },
+                    None => source.next().await.map_err(|e| format!("Error reading chunk: {}", e)),
+                };
+                let ended = match chunk {
+                    Ok(Some(data)) if max_stream_bytes.is_some_and(|max| received + data.len() > max) => {
+                        // Dropping the source below closes the connection
+                        truncated = true;
+                        interrupted = Some(format!("The response was cut off at the {} byte stream limit", max_stream_bytes.unwrap_or_default()));
+                        true
+                    }
+                    Ok(Some(data)) => {
+                        received += data.len();
+                        let text = decoder.decode(&data);
+                        if recorder.is_some() {
+                            recorded_chunks.push(text.clone());
+                        }
+                        pending.push_str(&text.replace("\r\n", "\n"));
+                        false
+                    }
+                    Ok(None) => {
+                        // Terminate a last event the server left open
+                        pending.push_str("\n\n");
+                        true
+                    }
+                    Err(e) => {
+                        interrupted = Some(e);
+                        true
+                    }
+                };
+
+                // Only handle complete events, the rest waits for the next chunk
+                let mut done = false;
+                while let Some(event) = next_sse_event(&mut pending) {
+                    match event.event.as_deref() {
+                        Some("done") => {
+                            done = true;
+                            break;
+                        }
+                        Some("error") => {
+                            eprintln!("Stream error: {}", event.data);
+                            continue;
+                        }
+                        _ => {}
+                    }
+
+                    // Some gateways keep the connection open after the marker, don't wait for them to close it
+                    if is_done_marker(&event.data) {
+                        done = true;
+                        break;
+                    }
+
+                    // Parse the JSON chunk
+                    if let Ok(stream_chunk) = serde_json::from_str::<StreamChunk>(&event.data) {
+                        usage = stream_chunk.usage.or(usage);
+                        for mut choice in stream_chunk.choices {
+                            if let Some(delta) = choice.delta.as_mut() {
+                                normalize_function_call(delta, None);
+                            }
+                            // Text held back as a possible stop sequence is sent once that's decided
+                            let mut held_back = false;
+                            if let Some(content) = choice.delta.as_mut().and_then(|delta| delta.content.as_mut()) {
+                                let visible = stops.push(content);
+                                all_content.push_str(&visible);
+                                held_back = !content.is_empty() && visible.is_empty();
+                                *content = visible;
+                            }
+                            if let Some(reasoning) = choice.delta.as_ref().and_then(|delta| delta.reasoning_content.as_deref()) {
+                                all_reasoning.push_str(reasoning);
+                                // Reasoning is never held back, even when the content next to it is
+                                held_back = held_back && reasoning.is_empty();
+                            }
+
+                            // The last delta may still carry output, so handle it before the finish
+                            let finish_reason = choice.finish_reason.take();
+
+                            if let Some(deltas) = choice.delta.as_ref()
+                                .and_then(|delta| delta.tool_calls.as_ref())
+                                .filter(|deltas| !deltas.is_empty()) {
+                                for curr_call in deltas {
+                                    let call = match tool_calls.entry(curr_call.index.unwrap_or_default()) {
+                                        Entry::Vacant(entry) => entry.insert(curr_call.clone()),
+                                        Entry::Occupied(entry) => {
+                                            let call = entry.into_mut();
+                                            // Only the first delta of a call names it, but don't count on that
+                                            call.id = call.id.take().or_else(|| curr_call.id.clone());
+                                            call.function.name = call.function.name.take().or_else(|| curr_call.function.name.clone());
+                                            if argument_error.is_none() {
+                                                call.function.arguments.push_str(&curr_call.function.arguments);
+                                            }
+                                            call
+                                        }
+                                    };
+                                    if call.function.arguments.len() > max_argument_bytes {
+                                        argument_error = Some(format!(
+                                            "The arguments of {} were over the {} byte limit, so the call was not run. Split the work into smaller calls",
+                                            call.function.name.as_deref().unwrap_or("the tool call"), max_argument_bytes,
+                                        ));
+                                        // Still valid JSON, so the call can be sent back in the history
+                                        call.function.arguments = "{}".to_string();
+                                    }
+                                }
+                            } else if !held_back && carries_output(&choice) {
+                                let chunk = StreamChannelChunk {
+                                    finished: false,
+                                    final_content: None,
+                                    final_reasoning: None,
+                                    choices: vec![choice],
+                                    error: None,
+                                    truncated: false,
+                                    usage: None,
+                                };
+                                // The receiver is dropped when the consumer stops listening, e.g. on cancellation
+                                if tx.send(chunk).await.is_err() {
+                                    return;
+                                }
+                            }
+
+                            if finish_reason.is_some() {
+                                let chunks = match (finish_reason.as_deref(), tool_calls.is_empty()) {
+                                    (Some("tool_calls") | Some("function_call"), false) => {
+                                        vec![tool_call_chunk(std::mem::take(&mut tool_calls).into_values().collect(), argument_error.clone())]
+                                    }
+                                    // A tool call finish without a call is a plain answer
+                                    (Some("stop") | Some("tool_calls") | Some("function_call"), _) => {
+                                        let rest = stops.finish();
+                                        let mut chunks = Vec::new();
+                                        if !rest.is_empty() {
+                                            all_content.push_str(&rest);
+                                            chunks.push(text_chunk(rest));
+                                        }
+                                        chunks.push(final_chunk(all_content.clone(), &all_reasoning));
+                                        chunks
+                                    }
+                                    _ => Vec::new(),
+                                };
+                                finished = !chunks.is_empty();
+                                if stream_usage {
+                                    held = chunks;
+                                } else {
+                                    for chunk in with_usage(chunks, usage.clone()) {
+                                        if tx.send(chunk).await.is_err() {
+                                            return;
+                                        }
+                                    }
+                                }
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                if ended || done {
+                    // The stream is over even if no finish_reason said so, hand over what arrived
+                    if !finished {
+                        let rest = stops.finish();
+                        if !rest.is_empty() {
+                            all_content.push_str(&rest);
+                            let _ = tx.send(text_chunk(rest)).await;
+                        }
+                        let calls: Vec<ToolCall> = std::mem::take(&mut tool_calls).into_values().collect();
+                        let mut chunk = match (calls.is_empty(), interrupted) {
+                            (false, Some(reason)) => {
+                                // The arguments are incomplete, keep the calls valid for the history but don't run them
+                                let calls = calls.into_iter()
+                                    .map(|mut call| {
+                                        call.function.arguments = "{}".to_string();
+                                        call
+                                    })
+                                    .collect();
+                                tool_call_chunk(calls, Some(format!("{} before the call was complete", reason)))
+                            }
+                            (false, None) => tool_call_chunk(calls, argument_error.take()),
+                            (true, reason) => StreamChannelChunk {
+                                error: reason,
+                                ..final_chunk(all_content.clone(), &all_reasoning)
+                            },
+                        };
+                        chunk.truncated = truncated;
+                        chunk.usage = usage;
+                        let _ = tx.send(chunk).await;
+                    } else {
+                        for chunk in with_usage(held, usage) {
+                            if tx.send(chunk).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    break;
+                }
+            }
+
+            if let Some(recorder) = recorder
+                && let Err(e) = recorder.record(&Exchange { request: body, status: 200, body: None, chunks: Some(recorded_chunks) }) {
+                eprintln!("Error recording exchange: {}", e);
+            }
+        });
+
+        Ok(rx)
+    }
+}
+
+/// Whether an event's data is the `[DONE]` end-of-stream marker, tolerating
+/// the stray whitespace some providers and gateways send around it.
+fn is_done_marker(data: &str) -> bool {
+    data.trim() == "[DONE]"
+}
+
+/// Whether a streamed choice has anything to show. The first delta of a
+/// stream often only announces the assistant role, with no or empty content.
+fn carries_output(choice: &Choice) -> bool {
+    let non_empty = |text: &Option<String>| text.as_deref().is_some_and(|text| !text.is_empty());
+    let has_content = choice.delta.as_ref()
+        .is_some_and(|delta| non_empty(&delta.content) || non_empty(&delta.reasoning_content) || delta.annotations.is_some());
+    has_content || choice.logprobs.is_some()
+}
+
+/// The chunk closing a stream that ended in a regular answer.
+fn final_chunk(content: String, reasoning: &str) -> StreamChannelChunk {
+    StreamChannelChunk {
+        finished: true,
+        final_content: Some(content),
+        final_reasoning: Some(reasoning.to_string()).filter(|reasoning| !reasoning.is_empty()),
+        choices: vec![],
+        error: None,
+        truncated: false,
+        usage: None,
+    }
+}
+
+/// A chunk of response text.
+fn text_chunk(content: String) -> StreamChannelChunk {
+    StreamChannelChunk {
+        finished: false,
+        final_content: None,
+        final_reasoning: None,
+        choices: vec![Choice {
+            delta: Some(simple_message(content, Role::Assistant)),
+            message: None,
+            finish_reason: None,
+            logprobs: None,
+        }],
+        error: None,
+        truncated: false,
+        usage: None,
+    }
+}
+
+/// The chunk handing over the fully streamed tool calls of a turn, with `error` if they must not run.
+fn tool_call_chunk(calls: Vec<ToolCall>, error: Option<String>) -> StreamChannelChunk {
+    StreamChannelChunk {
+        finished: false,
+        final_content: None,
+        final_reasoning: None,
+        choices: vec![Choice {
+            delta: Some(assistant_tool_call_message(calls)),
+            message: None,
+            finish_reason: None,
+            logprobs: None,
+        }],
+        error,
+        truncated: false,
+        usage: None,
+    }
+}
+
+/// `chunks` with `usage` put on the last of them.
+fn with_usage(mut chunks: Vec<StreamChannelChunk>, usage: Option<Usage>) -> Vec<StreamChannelChunk> {
+    if let Some(last) = chunks.last_mut() {
+        last.usage = usage;
+    }
+    chunks
+}
+
+/// Where a streamed response's bytes come from: the network, or a recording.
+enum ChunkSource {
+    Http(reqwest::Response),
+    Replay(VecDeque<String>),
+}
+
+impl ChunkSource {
+    async fn next(&mut self) -> Result<Option<Vec<u8>>, reqwest::Error> {
+        match self {
+            ChunkSource::Http(response) => Ok(response.chunk().await?.map(|bytes| bytes.to_vec())),
+            ChunkSource::Replay(chunks) => Ok(chunks.pop_front().map(String::into_bytes)),
+        }
+    }
+}
+
+/// One server-sent event: an optional `event:` type and its (joined) `data:` lines.
+#[derive(Debug, PartialEq)]
+struct SseEvent {
+    event: Option<String>,
+    data: String,
+}
+
+/// Takes the next complete, blank-line terminated event off the front of
+/// `pending`, leaving an incomplete one in place.
+fn next_sse_event(pending: &mut String) -> Option<SseEvent> {
+    loop {
+        let end = pending.find("\n\n")?;
+        let block: String = pending.drain(..end + 2).collect();
+
+        let mut event = None;
+        let mut data: Vec<&str> = Vec::new();
+        for line in block.lines() {
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => event = Some(value.to_string()),
+                "data" => data.push(value),
+                // Comments (`: keep-alive`), `id:` and `retry:` carry nothing we use
+                _ => {}
+            }
+        }
+
+        if event.is_some() || !data.is_empty() {
+            return Some(SseEvent { event, data: data.join("\n") });
+        }
+    }
+}
+
+/// Decodes a byte stream as UTF-8, carrying a multi-byte sequence split across
+/// network chunks over to the next chunk instead of garbling it. Bytes that are
+/// invalid UTF-8 on their own are replaced with U+FFFD.
+#[derive(Debug, Default)]
+struct Utf8Decoder {
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    fn decode(&mut self, bytes: &[u8]) -> String {
+        self.pending.extend_from_slice(bytes);
+        let mut decoded = String::new();
+
+        loop {
+            match std::str::from_utf8(&self.pending) {
+                Ok(text) => {
+                    decoded.push_str(text);
+                    self.pending.clear();
+                    break;
+                }
+                Err(e) => {
+                    let valid = e.valid_up_to();
+                    decoded.push_str(std::str::from_utf8(&self.pending[..valid]).unwrap());
+
+                    match e.error_len() {
+                        // Incomplete sequence at the end, wait for more bytes
+                        None => {
+                            self.pending.drain(..valid);
+                            break;
+                        }
+                        Some(len) => {
+                            decoded.push(char::REPLACEMENT_CHARACTER);
+                            self.pending.drain(..valid + len);
+                        }
+                    }
+                }
+            }
+        }
+
+        decoded
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Tool {
+    #[serde(rename = "type")]
+    pub tool_type: String,
+    pub function: Function, 
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Function {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ToolCall {
+    #[serde(rename = "id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    #[serde(rename = "index", skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub tool_type: Option<String>,
+
+    pub function: FunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FunctionCall {
+    pub name: Option<String>,
+    /// Missing from some streamed deltas, e.g. the one naming a legacy function call.
+    #[serde(default)]
+    pub arguments: String,
+}
+
+/// Id given to legacy function calls, which have none. There is at most one per turn.
+pub const LEGACY_FUNCTION_CALL_ID: &str = "call_function";
+
+/// Turns a legacy `function_call` into the equivalent `tool_calls`, so the
+/// rest of the client and its callers only deal with tool calls. Streamed
+/// deltas keep no id, as only the first one names the call.
+fn normalize_function_call(message: &mut Message, id: Option<&str>) {
+    if let Some(call) = message.function_call.take() {
+        message.tool_calls = Some(vec![ToolCall {
+            id: id.map(str::to_string),
+            index: Some(0),
+            tool_type: Some("function".to_string()),
+            function: call,
+        }]);
+    }
+}
+
+/// The messages in the shape of the legacy function calling API: assistant
+/// turns call their (first) tool through `function_call`, and tool results
+/// are `function` messages naming the function they answer.
+fn legacy_messages(messages: &[Message]) -> Vec<serde_json::Value> {
+    let mut names = HashMap::new();
+    messages.iter()
+        .map(|message| {
+            let mut value = serde_json::to_value(message).unwrap_or_default();
+            let Some(fields) = value.as_object_mut() else {
+                return value;
+            };
+            if let Some(call) = message.tool_calls.iter().flatten().next() {
+                let name = call.function.name.clone().unwrap_or_default();
+                names.insert(call.id.clone().unwrap_or_default(), name.clone());
+                fields.remove("tool_calls");
+                fields.insert("function_call".to_string(), serde_json::json!({"name": name, "arguments": call.function.arguments}));
+            }
+            if let Some(id) = fields.remove("tool_call_id").and_then(|id| id.as_str().map(str::to_string)) {
+                fields.insert("role".to_string(), serde_json::json!("function"));
+                fields.insert("name".to_string(), serde_json::json!(names.get(&id).cloned().unwrap_or_default()));
+            }
+            value
+        })
+        .collect()
+}
+
+pub fn simple_message(message: String, role: Role) -> Message {
+    Message {
+        role: Some(role),
+        content: Some(message),
+        tool_calls: None,
+        tool_call_id: None,
+        annotations: None,
+        function_call: None,
+        reasoning_content: None,
+    }
+}
+
+pub fn tool_call_result(id: String, result: String) -> Message {
+    Message {
+        role: Some(Role::Tool),
+        content: Some(result),
+        tool_calls: None,
+        tool_call_id: Some(id),
+        annotations: None,
+        function_call: None,
+        reasoning_content: None,
+    }
+}
+
+/// An assistant turn requesting the given tool calls, as the model sends it.
+pub fn assistant_tool_call_message(calls: Vec<ToolCall>) -> Message {
+    Message {
+        role: Some(Role::Assistant),
+        content: None,
+        tool_calls: Some(calls),
+        tool_call_id: None,
+        annotations: None,
+        function_call: None,
+        reasoning_content: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use super::*;
+    use crate::test_util::{MockResponse, MockServer, stream_chunk, stream_usage_chunk, text_completion};
+
+    fn setup_client() -> OpenAIClient {
+        let base_url = env::var("CODR_BASE_URL").expect("CODR_BASE_URL must be set");
+        let api_key = env::var("CODR_API_KEY").expect("CODR_API_KEY must be set");
+        let model = env::var("CODR_MODEL").expect("CODR_MODEL must be set");
+
+        OpenAIClient::new(base_url, api_key, model)
+    }
+
+    #[test]
+    fn test_assistant_tool_call_message() {
+        let message = assistant_tool_call_message(vec![ToolCall {
+            id: Some("call_1".to_string()),
+            index: None,
+            tool_type: Some("function".to_string()),
+            function: FunctionCall {
+                name: Some("read_file".to_string()),
+                arguments: r#"{"file_path":"src/main.rs"}"#.to_string(),
+            },
+        }]);
+
+        assert_eq!(serde_json::to_value(&message).unwrap(), serde_json::json!({
+            "role": "assistant",
+            "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": "read_file", "arguments": r#"{"file_path":"src/main.rs"}"#}
+            }]
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_non_streaming_request_shape() {
+        let server = MockServer::start(vec![text_completion("Hi")]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+
+        server.client().chat_completion(&messages, None).await.unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.header("Accept"), Some("application/json"));
+        assert_eq!(request.json()["stream"], false);
+    }
+
+    #[tokio::test]
+    async fn test_service_tier_only_sent_when_set() {
+        let server = MockServer::start(vec![text_completion("Hi"), text_completion("Hi")]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+
+        server.client().chat_completion(&messages, None).await.unwrap();
+        server.client()
+            .with_options(CompletionOptions { service_tier: Some("flex".to_string()), ..Default::default() })
+            .chat_completion(&messages, None).await.unwrap();
+
+        let requests = server.requests();
+        assert!(requests[0].json().get("service_tier").is_none());
+        assert_eq!(requests[1].json()["service_tier"], "flex");
+    }
+
+    #[tokio::test]
+    async fn test_no_retries_for_insufficient_quota() {
+        let quota = serde_json::json!({"error": {"message": "out of credits", "type": "insufficient_quota", "code": "insufficient_quota"}});
+        let server = MockServer::start(vec![
+            MockResponse::status(429, &quota.to_string()),
+            text_completion("Hi"),
+        ]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+
+        let retry = RetryConfig { max_retries: 2, base_delay: Duration::from_millis(10), max_delay: Duration::from_secs(1) };
+        let error = server.client().with_retry(retry).chat_completion(&messages, None).await.unwrap_err();
+
+        assert!(matches!(error, OpenAIError::InsufficientQuota(_)));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retries_honor_retry_after() {
+        let server = MockServer::start(vec![
+            MockResponse::status(429, "slow down").with_header("Retry-After", "0"),
+            MockResponse::status(503, "unavailable"),
+            text_completion("Hi"),
+        ]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+
+        let retry = RetryConfig { max_retries: 2, base_delay: Duration::from_millis(10), max_delay: Duration::from_secs(1) };
+        let completion = server.client().with_retry(retry).chat_completion(&messages, None).await.unwrap();
+
+        assert_eq!(completion.choices[0].message.as_ref().unwrap().content.as_deref(), Some("Hi"));
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn test_typed_provider_errors() {
+        let body = |kind: &str, code: serde_json::Value| serde_json::json!({
+            "error": {"message": "details", "type": kind, "param": null, "code": code}
+        }).to_string();
+
+        let error = OpenAIError::from_response(401, body("invalid_request_error", "invalid_api_key".into()));
+        assert!(matches!(error, OpenAIError::Auth(ref message) if message == "details"));
+        let error = OpenAIError::from_response(429, body("insufficient_quota", "insufficient_quota".into()));
+        assert!(matches!(error, OpenAIError::InsufficientQuota(_)));
+        assert!(!error.is_retryable());
+        assert!(error.should_fail_over());
+        let error = OpenAIError::from_response(400, body("invalid_request_error", "context_length_exceeded".into()));
+        assert!(matches!(error, OpenAIError::ContextLengthExceeded(_)));
+        assert!(!error.should_fail_over());
+        let error = OpenAIError::from_response(429, body("tokens", "rate_limit_exceeded".into()));
+        assert!(matches!(error, OpenAIError::RateLimited { retry_after: None, .. }));
+        // Only the type given, with a numeric code
+        let error = OpenAIError::from_response(429, body("insufficient_quota", 429.into()));
+        assert!(matches!(error, OpenAIError::InsufficientQuota(_)));
+    }
+
+    #[test]
+    fn test_untyped_provider_errors() {
+        let unknown = serde_json::json!({"error": {"message": "bad", "type": "server_error", "code": null}}).to_string();
+        assert!(matches!(OpenAIError::from_response(500, unknown.clone()), OpenAIError::Api { status: 500, body } if body == unknown));
+        assert!(matches!(OpenAIError::from_response(502, "Bad Gateway".to_string()), OpenAIError::Api { status: 502, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_provider_error_surfaced_typed() {
+        let server = MockServer::start(vec![
+            MockResponse::status(401, &serde_json::json!({
+                "error": {"message": "Incorrect API key", "type": "invalid_request_error", "code": "invalid_api_key"}
+            }).to_string()),
+        ]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+
+        let error = server.client().chat_completion(&messages, None).await.unwrap_err();
+
+        assert!(matches!(error, OpenAIError::Auth(message) if message == "Incorrect API key"));
+    }
+
+    #[tokio::test]
+    async fn test_errors_typed_by_status() {
+        let server = MockServer::start(vec![
+            MockResponse::status(429, "Slow down").with_header("Retry-After", "7"),
+            MockResponse::status(401, "Unauthorized"),
+            MockResponse::status(403, "Forbidden"),
+            MockResponse::json(serde_json::json!({"choices": "not a list"})),
+        ]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+        let client = server.client().with_retry(RetryConfig { max_retries: 0, ..Default::default() });
+
+        match client.chat_completion(&messages, None).await.unwrap_err() {
+            OpenAIError::RateLimited { message, retry_after } => {
+                assert_eq!(message, "Slow down");
+                assert_eq!(retry_after, Some(Duration::from_secs(7)));
+            }
+            other => panic!("expected a rate limit, got {:?}", other),
+        }
+        assert!(matches!(client.chat_completion(&messages, None).await.unwrap_err(), OpenAIError::Auth(message) if message == "Unauthorized"));
+        assert!(matches!(client.chat_completion(&messages, None).await.unwrap_err(), OpenAIError::Auth(message) if message == "Forbidden"));
+        assert!(matches!(client.chat_completion(&messages, None).await.unwrap_err(), OpenAIError::Deserialize(_)));
+    }
+
+    #[tokio::test]
+    async fn test_retries_exhausted() {
+        let server = MockServer::start(vec![
+            MockResponse::status(500, "boom"),
+            MockResponse::status(500, "boom again"),
+        ]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+
+        let retry = RetryConfig { max_retries: 1, base_delay: Duration::from_millis(10), max_delay: Duration::from_secs(1) };
+        let error = server.client().with_retry(retry).chat_completion(&messages, None).await.unwrap_err();
+
+        assert!(error.to_string().contains("boom again"));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_with_http_client() {
+        let server = MockServer::start(vec![text_completion("Hi")]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+
+        let mut default_headers = reqwest::header::HeaderMap::new();
+        default_headers.insert("X-Custom-Client", "shared".parse().unwrap());
+        let http_client = reqwest::Client::builder().default_headers(default_headers).build().unwrap();
+
+        let client = OpenAIClient::with_http_client(http_client, server.url(), "test-key".to_string(), "test-model".to_string());
+        client.chat_completion(&messages, None).await.unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.header("X-Custom-Client"), Some("shared"));
+        assert_eq!(request.header("Authorization"), Some("Bearer test-key"));
+    }
+
+    /// Hands out a new token on every call, like a provider refreshing short-lived credentials.
+    struct RotatingTokens(std::sync::atomic::AtomicUsize);
+
+    impl AuthProvider for RotatingTokens {
+        fn token(&self) -> auth::TokenFuture<'_> {
+            Box::pin(async move {
+                let issued = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                Ok(format!("token-{}", issued))
+            })
+        }
+    }
+
+    struct FailingAuth;
+
+    impl AuthProvider for FailingAuth {
+        fn token(&self) -> auth::TokenFuture<'_> {
+            Box::pin(async { Err("identity provider unreachable".into()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auth_provider_token_per_request() {
+        let server = MockServer::start(vec![text_completion("One"), text_completion("Two")]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+
+        let client = server.client().with_auth_provider(RotatingTokens(Default::default()));
+        client.chat_completion(&messages, None).await.unwrap();
+        client.chat_completion(&messages, None).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("Authorization"), Some("Bearer token-1"));
+        assert_eq!(requests[1].header("Authorization"), Some("Bearer token-2"));
+    }
+
+    #[tokio::test]
+    async fn test_auth_provider_failure() {
+        let server = MockServer::start(vec![text_completion("Unreachable")]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+
+        let client = server.client()
+            .with_auth_provider(FailingAuth)
+            .with_retry(RetryConfig { max_retries: 3, ..Default::default() });
+        let error = client.chat_completion(&messages, None).await.unwrap_err();
+
+        assert!(matches!(error, OpenAIError::Auth(details) if details == "identity provider unreachable"));
+        assert!(server.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_annotations() {
+        let server = MockServer::start(vec![
+            MockResponse::json(serde_json::json!({
+                "choices": [{
+                    "message": {
+                        "role": "assistant",
+                        "content": "Rust 1.85 stabilized async closures.",
+                        "annotations": [
+                            {"type": "url_citation", "url_citation": {
+                                "url": "https://blog.rust-lang.org/2025/02/20/Rust-1.85.0.html",
+                                "title": "Announcing Rust 1.85.0",
+                                "start_index": 0,
+                                "end_index": 36
+                            }},
+                            {"type": "file_citation", "file_citation": {"file_id": "file-123", "filename": "notes.md"}}
+                        ]
+                    },
+                    "finish_reason": "stop"
+                }]
+            })),
+            text_completion("Hi"),
+        ]).await;
+        let mut messages = vec![simple_message("What's new in Rust?".to_string(), Role::User)];
+
+        let completion = server.client().chat_completion(&messages, None).await.unwrap();
+        let message = completion.choices[0].message.clone().unwrap();
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct Choice {
-    pub message: Option<Message>,
-    pub delta: Option<Message>,
-    pub finish_reason: Option<String>,
-}
+        let annotations = message.annotations.as_ref().unwrap();
+        assert_eq!(annotations[0].url_citation.as_ref().unwrap().title.as_deref(), Some("Announcing Rust 1.85.0"));
+        assert_eq!(annotations[0].url_citation.as_ref().unwrap().end_index, Some(36));
+        assert_eq!(message.sources(), vec!["https://blog.rust-lang.org/2025/02/20/Rust-1.85.0.html", "notes.md"]);
+        assert_eq!(serde_json::to_value(&message).unwrap()["annotations"][1]["type"], "file_citation");
+        assert!(serde_json::to_value(simple_message("Hi".to_string(), Role::User)).unwrap().get("annotations").is_none());
 
-#[derive(Debug, Deserialize)]
-pub struct ChatCompletion {
-    pub choices: Vec<Choice>,
-}
+        // Sent back as history, the annotations are left out
+        messages.push(message);
+        server.client().chat_completion(&messages, None).await.unwrap();
+        assert!(server.requests()[1].json()["messages"][1].get("annotations").is_none());
+    }
 
-#[derive(Debug, Deserialize)]
-pub struct StreamChunk {
-    pub object: String,
-    pub created: i64,
-    pub model: String,
-    pub choices: Vec<Choice>,
-}
+    #[tokio::test]
+    async fn test_tool_results_as_user() {
+        let server = MockServer::start(vec![text_completion("Hi"), text_completion("Hi")]).await;
+        let messages = vec![
+            simple_message("Read main.rs".to_string(), Role::User),
+            tool_call_result("call_1".to_string(), "fn main() {}".to_string()),
+        ];
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct StreamChannelChunk {
-    pub finished: bool,
-    pub final_content: Option<String>,
-    pub choices: Vec<Choice>,
-}
+        server.client().chat_completion(&messages, None).await.unwrap();
+        server.client().with_tool_results_as_user(true).chat_completion(&messages, None).await.unwrap();
 
+        let requests = server.requests();
+        let native = &requests[0].json()["messages"][1];
+        assert_eq!(native["role"], "tool");
+        assert_eq!(native["tool_call_id"], "call_1");
+        let as_user = &requests[1].json()["messages"][1];
+        assert_eq!(as_user["role"], "user");
+        assert_eq!(as_user["content"], "Result of tool call call_1:\nfn main() {}");
+        assert!(as_user.get("tool_call_id").is_none());
+    }
 
-#[derive(Debug, Clone)]
-pub struct OpenAIClient {
-    http_client: reqwest::Client,
-    api_key: String,
-    base_url: String,
-    model: String,
-}
+    #[tokio::test]
+    async fn test_legacy_functions_request_shape() {
+        let server = MockServer::start(vec![text_completion("Hi"), text_completion("Hi")]).await;
+        let call = ToolCall {
+            id: Some("call_1".to_string()),
+            index: None,
+            tool_type: Some("function".to_string()),
+            function: FunctionCall { name: Some("read_file".to_string()), arguments: r#"{"file_path":"main.rs"}"#.to_string() },
+        };
+        let messages = vec![
+            simple_message("Read main.rs".to_string(), Role::User),
+            assistant_tool_call_message(vec![call]),
+            tool_call_result("call_1".to_string(), "fn main() {}".to_string()),
+        ];
+        let tools = || Some(Box::new(vec![Tool {
+            tool_type: "function".to_string(),
+            function: Function { name: "read_file".to_string(), description: "Reads a file".to_string(), parameters: serde_json::json!({"type": "object"}) },
+        }]));
 
-impl OpenAIClient {
-    pub fn new(base_url: String, api_key: String, model: String) -> Self {
-        let base_url = base_url.trim_end_matches('/').to_string();
-        let http_client = reqwest::Client::new();
+        server.client().chat_completion(&messages, tools()).await.unwrap();
+        server.client().with_legacy_functions(true).chat_completion(&messages, tools()).await.unwrap();
 
-        OpenAIClient {
-            http_client,
-            api_key,
-            base_url,
-            model,
+        let requests = server.requests();
+        let modern = requests[0].json();
+        assert_eq!(modern["tools"][0]["function"]["name"], "read_file");
+        assert_eq!(modern["messages"][2]["role"], "tool");
+
+        let legacy = requests[1].json();
+        assert!(legacy.get("tools").is_none());
+        assert_eq!(legacy["functions"], serde_json::json!([{"name": "read_file", "description": "Reads a file", "parameters": {"type": "object"}}]));
+        assert_eq!(legacy["messages"][1]["function_call"], serde_json::json!({"name": "read_file", "arguments": r#"{"file_path":"main.rs"}"#}));
+        assert!(legacy["messages"][1].get("tool_calls").is_none());
+        assert_eq!(legacy["messages"][2], serde_json::json!({"role": "function", "name": "read_file", "content": "fn main() {}"}));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_function_call_response() {
+        let server = MockServer::start(vec![MockResponse::json(serde_json::json!({
+            "choices": [{
+                "message": {"role": "assistant", "content": null, "function_call": {"name": "read_file", "arguments": "{}"}},
+                "finish_reason": "function_call"
+            }]
+        }))]).await;
+        let messages = vec![simple_message("Read main.rs".to_string(), Role::User)];
+
+        let completion = server.client().with_legacy_functions(true).chat_completion(&messages, None).await.unwrap();
+
+        let message = completion.choices[0].message.as_ref().unwrap();
+        assert!(message.function_call.is_none());
+        let call = &message.tool_calls.as_ref().unwrap()[0];
+        assert_eq!(call.id.as_deref(), Some(LEGACY_FUNCTION_CALL_ID));
+        assert_eq!(call.function.name.as_deref(), Some("read_file"));
+    }
+
+    #[tokio::test]
+    async fn test_metadata_in_request_body() {
+        let server = MockServer::start(vec![text_completion("Hi"), text_completion("Hi")]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+        let options = |metadata: HashMap<String, String>| CompletionOptions { metadata: Some(metadata), ..Default::default() };
+
+        let tags = HashMap::from([("session".to_string(), "abc123".to_string())]);
+        server.client().with_options(options(tags)).chat_completion(&messages, None).await.unwrap();
+        server.client().with_options(options(HashMap::new())).chat_completion(&messages, None).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].json()["metadata"], serde_json::json!({"session": "abc123"}));
+        assert!(requests[1].json().get("metadata").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reasoning_effort() {
+        let server = MockServer::start(vec![text_completion("Hi")]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+
+        let options = |effort: &str| CompletionOptions { reasoning_effort: Some(effort.to_string()), ..Default::default() };
+        server.client().with_options(options("high")).chat_completion(&messages, None).await.unwrap();
+        let invalid = server.client().with_options(options("extreme")).chat_completion(&messages, None).await;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].json()["reasoning_effort"], "high");
+        assert!(invalid.unwrap_err().to_string().contains("extreme"));
+    }
+
+    #[tokio::test]
+    async fn test_logprobs() {
+        let server = MockServer::start(vec![MockResponse::json(serde_json::json!({
+            "choices": [{
+                "message": {"role": "assistant", "content": "Yes"},
+                "finish_reason": "stop",
+                "logprobs": {
+                    "content": [{
+                        "token": "Yes",
+                        "logprob": -0.01,
+                        "bytes": [89, 101, 115],
+                        "top_logprobs": [
+                            {"token": "Yes", "logprob": -0.01, "bytes": [89, 101, 115]},
+                            {"token": "No", "logprob": -4.6, "bytes": [78, 111]}
+                        ]
+                    }],
+                    "refusal": null
+                }
+            }]
+        }))]).await;
+        let messages = vec![simple_message("Is it?".to_string(), Role::User)];
+
+        let options = CompletionOptions { logprobs: Some(true), top_logprobs: Some(2), ..Default::default() };
+        let response = server.client().with_options(options).chat_completion(&messages, None).await.unwrap();
+
+        let request = server.requests()[0].json();
+        assert_eq!((request["logprobs"].clone(), request["top_logprobs"].clone()), (serde_json::json!(true), serde_json::json!(2)));
+
+        let logprobs = response.choices[0].logprobs.as_ref().unwrap();
+        assert_eq!(logprobs.refusal, None);
+        let tokens = logprobs.content.as_ref().unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!((tokens[0].token.as_str(), tokens[0].logprob), ("Yes", -0.01));
+        assert_eq!(tokens[0].bytes.as_deref(), Some("Yes".as_bytes()));
+        let alternatives: Vec<_> = tokens[0].top_logprobs.iter().map(|top| top.token.as_str()).collect();
+        assert_eq!(alternatives, vec!["Yes", "No"]);
+
+        // Unrequested, they are neither sent nor expected back
+        let server = MockServer::start(vec![text_completion("Yes")]).await;
+        let response = server.client().chat_completion(&messages, None).await.unwrap();
+        assert!(server.requests()[0].json().get("logprobs").is_none());
+        assert!(response.choices[0].logprobs.is_none());
+
+        let invalid = CompletionOptions { top_logprobs: Some(3), ..Default::default() };
+        assert!(invalid.validate().unwrap_err().contains("requires logprobs"));
+    }
+
+    #[tokio::test]
+    async fn test_streaming_request_shape() {
+        let server = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"role": "assistant", "content": "Hi"}), None),
+            stream_chunk(serde_json::json!({}), Some("stop")),
+        ])]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+
+        let mut stream = server.client().chat_completion_stream(&messages, None).await.unwrap();
+        while let Some(chunk) = stream.recv().await {
+            if chunk.finished {
+                break;
+            }
         }
+
+        let request = &server.requests()[0];
+        assert_eq!(request.header("Accept"), Some("text/event-stream"));
+        assert_eq!(request.json()["stream"], true);
     }
 
-    pub async fn chat_completion(&self, messages: &Vec<Message>, tools: Option<Box<Vec<Tool>>>) -> Result<ChatCompletion, Box<dyn std::error::Error>> {
-        let url = format!("{}/chat/completions", self.base_url);
-        
-        let body = serde_json::json!({
-            "model": self.model,
-            "messages": messages,
-            "tools": tools,
+    #[tokio::test]
+    async fn test_connection_reuse() {
+        let server = MockServer::start(vec![
+            text_completion("One"),
+            text_completion("Two"),
+            text_completion("Three"),
+        ]).await;
+        let client = server.client().with_http_config(HttpConfig {
+            pool_max_idle_per_host: 4,
+            pool_idle_timeout: Some(Duration::from_secs(30)),
+            ..Default::default()
         });
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
 
-        let response = self.http_client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&body)
-            .send()
-            .await;
+        client.chat_completion(&messages, None).await.unwrap();
+        client.clone().chat_completion(&messages, None).await.unwrap();
+        client.chat_completion(&messages, None).await.unwrap();
 
-        if let Err(e) = response {
-            eprintln!("Request failed: {}", e);
-            return Err(Box::new(Error::new(ErrorKind::Other, "Request failed")));
-        }
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(server.connections(), 1);
+    }
 
-        let response = response.unwrap();
+    fn stream_events(chunks: usize) -> Vec<Vec<u8>> {
+        let mut events: Vec<Vec<u8>> = (0..chunks)
+            .map(|_| format!("data: {}\n\n", stream_chunk(serde_json::json!({"content": "."}), None)).into_bytes())
+            .collect();
+        events.push(format!("data: {}\n\ndata: [DONE]\n\n", stream_chunk(serde_json::json!({}), Some("stop"))).into_bytes());
+        events
+    }
 
-        if response.status().is_success() {
-            let chat_completion = response.json().await;
-            if let Err(e) = chat_completion {
-                eprintln!("Failed to parse response: {}", e);
-                return Err(Box::new(Error::new(ErrorKind::Other, "Failed to parse response")));
-            }
-            Ok(chat_completion.unwrap())
-        } else {
-            let status = response.status();
-            let error_message = response.text().await?;
-            eprintln!("Error: {}", error_message);
-            Err(Box::new(Error::new(
-                ErrorKind::Other,
-                format!("Error {}: {}", status, error_message),
-            )))
+    #[tokio::test]
+    async fn test_stalled_stream_times_out() {
+        let server = MockServer::start(vec![
+            MockResponse::byte_stream(stream_events(1)).with_chunk_delay(Duration::from_secs(5)),
+        ]).await;
+        let client = server.client().with_http_config(HttpConfig {
+            read_timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        });
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+
+        let started = std::time::Instant::now();
+        let mut stream = client.chat_completion_stream(&messages, None).await.unwrap();
+        let mut received = Vec::new();
+        while let Some(chunk) = stream.recv().await {
+            received.push(chunk);
         }
+
+        // The first chunk arrives, then the stall ends the stream long before the server resumes
+        assert_eq!(received.len(), 2);
+        assert!(!received[0].finished);
+        let last = &received[1];
+        assert!(last.finished);
+        assert_eq!(last.final_content.as_deref(), Some("."));
+        assert!(last.error.as_deref().unwrap().starts_with("Stream stalled"));
+        assert!(started.elapsed() < Duration::from_secs(2));
     }
 
-    pub async fn chat_completion_stream(&self, messages: &Vec<Message>, tools: Option<Box<Vec<Tool>>>) -> tokio::sync::mpsc::Receiver<StreamChannelChunk> {
-        let url = format!("{}/chat/completions", self.base_url);
-        
-        let body = serde_json::json!({
-            "model": self.model,
-            "messages": messages,
-            "tools": tools,
-            "stream": true,
+    #[tokio::test]
+    async fn test_slow_stream_within_read_timeout() {
+        let server = MockServer::start(vec![
+            MockResponse::byte_stream(stream_events(5)).with_chunk_delay(Duration::from_millis(100)),
+        ]).await;
+        let client = server.client().with_http_config(HttpConfig {
+            read_timeout: Some(Duration::from_millis(400)),
+            ..Default::default()
         });
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
 
-        let response = self.http_client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&body)
-            .send()
-            .await.unwrap();
+        // Takes longer than the read timeout overall, but data keeps arriving
+        let mut stream = client.chat_completion_stream(&messages, None).await.unwrap();
+        let mut final_content = None;
+        while let Some(chunk) = stream.recv().await {
+            if chunk.finished {
+                final_content = chunk.final_content;
+            }
+        }
 
-        let mut response = response;
-        let mut all_content = String::new();
+        assert_eq!(final_content.as_deref(), Some("....."));
+    }
 
-        let (tx, rx) = tokio::sync::mpsc::channel::<StreamChannelChunk>(1);
+    #[tokio::test]
+    async fn test_record_and_replay_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let recording = dir.path().join("stream.jsonl");
+        let server = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"role": "assistant", "content": "Hel"}), None),
+            stream_chunk(serde_json::json!({"content": "lo"}), None),
+            stream_chunk(serde_json::json!({}), Some("stop")),
+        ])]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
 
-        if response.status() != reqwest::StatusCode::OK {
-            panic!("Error ({}): {}", response.status(), response.text().await.unwrap());
+        async fn final_content(mut stream: tokio::sync::mpsc::Receiver<StreamChannelChunk>) -> Option<String> {
+            let mut content = None;
+            while let Some(chunk) = stream.recv().await {
+                if chunk.finished {
+                    content = chunk.final_content;
+                }
+            }
+            content
         }
 
-        tokio::spawn(async move {
-            let mut tool_call: Option<ToolCall> = None;
-            loop {
-                let chunk = response.chunk().await;
-                match chunk {
-                    Ok(Some(data)) => {
-                        let chunk_str = String::from_utf8_lossy(&data);
-
-                        //println!("Received chunk: {}", chunk_str);
-                        
-                        for line in chunk_str.lines() {
-                            if line.starts_with("data: ") {
-                                let json_str = line.trim_start_matches("data: ");
-                                
-                                // Check if it's the end marker
-                                if json_str == "[DONE]" {
-                                    continue;
-                                }
+        let client = server.client().with_recorder(&recording).unwrap();
+        let recorded = final_content(client.chat_completion_stream(&messages, None).await.unwrap()).await;
 
-                                // Parse the JSON chunk
-                                match serde_json::from_str::<StreamChunk>(json_str) {
-                                    Ok(stream_chunk) => {
-                                        for choice in stream_chunk.choices {
-                                            all_content.push_str(&choice.delta.clone().unwrap().content.unwrap_or_default());
-
-                                            if choice.finish_reason.is_some() {
-                                                match choice.finish_reason.as_deref() {
-                                                    Some("stop") => {
-                                                        let final_content = all_content.clone();
-                                                        tx.send(StreamChannelChunk {
-                                                            finished: true,
-                                                            final_content: Some(final_content),
-                                                            choices: vec![],
-                                                        }).await.unwrap();
-                                                    }
-                                                    Some("tool_calls") => {
-                                                        println!("Tool calls detected");
-                                                        // Send the tool call to the channel
-                                                        tx.send(StreamChannelChunk {
-                                                            finished: false,
-                                                            final_content: None,
-                                                            choices: vec![Choice {
-                                                                delta: Some(Message {
-                                                                    role: Some(Role::Assistant),
-                                                                    content: None,
-                                                                    tool_calls: Some(vec![tool_call.clone().unwrap()]),
-                                                                    tool_call_id: None,
-                                                                }),
-                                                                message: None,
-                                                                finish_reason: None,
-                                                            }],
-                                                        }).await.unwrap();
-                                                    }
-                                                    _ => {}
-                                                }
-                                                break;
-                                            }
+        let exchange: recording::Exchange = serde_json::from_str(
+            std::fs::read_to_string(&recording).unwrap().lines().next().unwrap()
+        ).unwrap();
+        assert_eq!(exchange.request["stream"], true);
+        assert_eq!(exchange.chunks.as_ref().unwrap().len(), 4);
 
-                                            if let Some(delta) = choice.clone().delta {
-                                                if let Some(tool_calls) = delta.tool_calls.clone() {
-                                                    if let Some(curr_call) = tool_calls.get(0) {
-                                                        match tool_call {
-                                                            Some(ref mut call) => {
-                                                                call.function.arguments.push_str(curr_call.function.arguments.as_str());
-                                                            }
-                                                            None => {
-                                                                tool_call = Some(curr_call.clone());
-                                                            }
-                                                        }
-                                                        continue;
-                                                    }
-                                                }
-                                            }
+        let client = OpenAIClient::new("http://127.0.0.1:9".to_string(), "key".to_string(), "model".to_string())
+            .with_replay(&recording)
+            .unwrap();
+        let replayed = final_content(client.chat_completion_stream(&messages, None).await.unwrap()).await;
 
-                                            tx.send(StreamChannelChunk {
-                                                finished: false,
-                                                final_content: None,
-                                                choices: vec![choice.clone()],
-                                            }).await.unwrap();
-                                        }
-                                    },
-                                    Err(_) => {}
-                                }
-                            }
-                        }
-                    },
-                    Ok(None) => break, // End of stream
-                    Err(e) => {
-                        eprintln!("Error reading chunk: {}", e);
-                    }
+        assert_eq!(recorded.as_deref(), Some("Hello"));
+        assert_eq!(replayed, recorded);
+    }
+
+    #[test]
+    fn test_next_sse_event() {
+        let mut pending = "event: message\ndata: {\"a\":1}\n\n: keep-alive\n\nevent: done\ndata: [DONE]\n\ndata: partial".to_string();
+
+        assert_eq!(next_sse_event(&mut pending), Some(SseEvent {
+            event: Some("message".to_string()),
+            data: "{\"a\":1}".to_string(),
+        }));
+        assert_eq!(next_sse_event(&mut pending), Some(SseEvent {
+            event: Some("done".to_string()),
+            data: "[DONE]".to_string(),
+        }));
+        assert_eq!(next_sse_event(&mut pending), None);
+        assert_eq!(pending, "data: partial");
+    }
+
+    #[tokio::test]
+    async fn test_streaming_with_event_lines() {
+        let event = |delta: serde_json::Value, finish_reason: Option<&str>| {
+            format!("event: message\ndata: {}\n\n", stream_chunk(delta, finish_reason))
+        };
+        let server = MockServer::start(vec![MockResponse::raw_stream(vec![
+            event(serde_json::json!({"role": "assistant", "content": "Hel"}), None),
+            ": keep-alive\n\n".to_string(),
+            event(serde_json::json!({"content": "lo"}), None),
+            event(serde_json::json!({}), Some("stop")),
+            "event: done\ndata: [DONE]\n\n".to_string(),
+        ])]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+
+        let mut stream = server.client().chat_completion_stream(&messages, None).await.unwrap();
+        let mut final_content = None;
+        while let Some(chunk) = stream.recv().await {
+            if chunk.finished {
+                final_content = chunk.final_content;
+            }
+        }
+
+        assert_eq!(final_content.as_deref(), Some("Hello"));
+    }
+
+    /// Collects a stream's final content, failing if it doesn't end within a few seconds.
+    async fn final_content_of(server: &MockServer) -> Option<String> {
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
+        let mut stream = server.client().chat_completion_stream(&messages, None).await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            let mut final_content = None;
+            while let Some(chunk) = stream.recv().await {
+                if chunk.finished {
+                    final_content = chunk.final_content;
                 }
             }
+            final_content
+        }).await.expect("stream did not end")
+    }
+
+    #[tokio::test]
+    async fn test_streaming_done_marker_variants() {
+        let content = format!("data: {}\n\n", stream_chunk(serde_json::json!({"role": "assistant", "content": "Hi"}), None));
+
+        for marker in ["data: [DONE]\n\n", "data:[DONE]\n\n", "data: [DONE]  \n\n", "data:\t[DONE]\r\n\r\n", "event: done\ndata: {}\n\n"] {
+            // The server holds the connection open long after the marker
+            let server = MockServer::start(vec![
+                MockResponse::raw_stream(vec![format!("{}{}", content, marker)]).with_chunk_delay(Duration::from_secs(60)),
+            ]).await;
+
+            assert_eq!(final_content_of(&server).await.as_deref(), Some("Hi"), "marker {:?}", marker);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streaming_stop_sequence_trimmed() {
+        let server = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"role": "assistant", "content": "fn main() {}"}), None),
+            stream_chunk(serde_json::json!({"content": "\n`"}), None),
+            stream_chunk(serde_json::json!({"content": "``\nExplanation"}), None),
+            stream_chunk(serde_json::json!({}), Some("stop")),
+        ])]).await;
+        let messages = vec![simple_message("Write main".to_string(), Role::User)];
+
+        let options = CompletionOptions { stop: Some(vec!["\n```".to_string()]), ..Default::default() };
+        let mut stream = server.client().with_options(options).chat_completion_stream(&messages, None).await.unwrap();
+        let mut streamed = String::new();
+        let mut final_content = None;
+        while let Some(chunk) = stream.recv().await {
+            if chunk.finished {
+                final_content = chunk.final_content;
+            }
+            for choice in chunk.choices {
+                streamed.push_str(&choice.delta.and_then(|delta| delta.content).unwrap_or_default());
+            }
+        }
+
+        assert_eq!(server.requests()[0].json()["stop"], serde_json::json!(["\n```"]));
+        assert_eq!(streamed, "fn main() {}");
+        assert_eq!(final_content.as_deref(), Some("fn main() {}"));
+    }
+
+    #[tokio::test]
+    async fn test_streaming_connection_close_ends_stream() {
+        // Neither a finish_reason nor [DONE], the server just closes the connection
+        let server = MockServer::start(vec![MockResponse::raw_stream(vec![
+            format!("data: {}\n\n", stream_chunk(serde_json::json!({"role": "assistant", "content": "Partial"}), None)),
+        ])]).await;
+
+        assert_eq!(final_content_of(&server).await.as_deref(), Some("Partial"));
+    }
+
+    #[test]
+    fn test_utf8_decoder_split_sequence() {
+        let bytes = "café ✓".as_bytes();
+        let mut decoder = Utf8Decoder::default();
+
+        // Split inside "é" (2 bytes) and inside "✓" (3 bytes)
+        let mut decoded = decoder.decode(&bytes[..4]);
+        decoded.push_str(&decoder.decode(&bytes[4..7]));
+        decoded.push_str(&decoder.decode(&bytes[7..]));
+
+        assert_eq!(decoded, "café ✓");
+    }
+
+    #[test]
+    fn test_utf8_decoder_invalid_bytes() {
+        let mut decoder = Utf8Decoder::default();
+        assert_eq!(decoder.decode(b"a\xffb"), "a\u{FFFD}b");
+    }
+
+    #[tokio::test]
+    async fn test_streaming_oversized_tool_arguments_dropped() {
+        let argument_delta = |arguments: &str| stream_chunk(serde_json::json!({"tool_calls": [{
+            "index": 0,
+            "function": {"arguments": arguments}
+        }]}), None);
+        let server = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"tool_calls": [{
+                "index": 0,
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": "write_file", "arguments": "{\"content\": \""}
+            }]}), None),
+            argument_delta(&"x".repeat(60)),
+            argument_delta(&"y".repeat(60)),
+            argument_delta("\"}"),
+            stream_chunk(serde_json::json!({}), Some("tool_calls")),
+        ])]).await;
+        let messages = vec![simple_message("Write a big file".to_string(), Role::User)];
+
+        let mut stream = server.client().with_max_tool_argument_bytes(100).chat_completion_stream(&messages, None).await.unwrap();
+        let chunk = stream.recv().await.unwrap();
+
+        let call = &chunk.choices[0].delta.as_ref().unwrap().tool_calls.as_ref().unwrap()[0];
+        assert_eq!(call.function.name.as_deref(), Some("write_file"));
+        assert_eq!(call.function.arguments, "{}");
+        assert!(chunk.error.unwrap().contains("over the 100 byte limit"));
+        assert!(stream.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_streaming_parallel_tool_calls() {
+        let call_start = |index: usize, id: &str, path: &str| serde_json::json!({
+            "index": index,
+            "id": id,
+            "type": "function",
+            "function": {"name": "read_file", "arguments": format!("{{\"file_path\": \"{}", path)}
         });
+        let argument_delta = |index: usize| serde_json::json!({"index": index, "function": {"arguments": "\"}"}});
+        let server = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"tool_calls": [call_start(0, "call_a", "a.rs")]}), None),
+            stream_chunk(serde_json::json!({"tool_calls": [call_start(1, "call_b", "b.rs")]}), None),
+            stream_chunk(serde_json::json!({"tool_calls": [argument_delta(1), argument_delta(0)]}), None),
+            stream_chunk(serde_json::json!({}), Some("tool_calls")),
+        ])]).await;
+        let messages = vec![simple_message("Read both files".to_string(), Role::User)];
 
-        rx
+        let mut stream = server.client().chat_completion_stream(&messages, None).await.unwrap();
+        let chunk = stream.recv().await.unwrap();
+
+        let calls = chunk.choices[0].delta.as_ref().unwrap().tool_calls.clone().unwrap();
+        let calls: Vec<(Option<String>, String)> = calls.into_iter().map(|call| (call.id, call.function.arguments)).collect();
+        assert_eq!(calls, vec![
+            (Some("call_a".to_string()), r#"{"file_path": "a.rs"}"#.to_string()),
+            (Some("call_b".to_string()), r#"{"file_path": "b.rs"}"#.to_string()),
+        ]);
+        assert!(chunk.error.is_none());
+        assert!(stream.recv().await.is_none());
     }
-}
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Tool {
-    #[serde(rename = "type")]
-    pub tool_type: String,
-    pub function: Function, 
-}
+    #[tokio::test]
+    async fn test_streaming_usage() {
+        let server = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"content": "Hi"}), None),
+            stream_chunk(serde_json::json!({}), Some("stop")),
+            stream_usage_chunk(12, 3),
+        ])]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Function {
-    pub name: String,
-    pub description: String,
-    pub parameters: serde_json::Value,
-}
+        let mut stream = server.client().with_stream_usage(true).chat_completion_stream(&messages, None).await.unwrap();
+        let mut last = stream.recv().await.unwrap();
+        while !last.finished {
+            last = stream.recv().await.unwrap();
+        }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ToolCall {
-    #[serde(rename = "id", skip_serializing_if = "Option::is_none")]
-    pub id: Option<String>,
+        let usage = last.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (12, 3));
+        assert_eq!(server.requests()[0].json()["stream_options"]["include_usage"], true);
+    }
 
-    #[serde(rename = "index", skip_serializing_if = "Option::is_none")]
-    pub index: Option<usize>,
+    #[tokio::test]
+    async fn test_streaming_error_status() {
+        let server = MockServer::start(vec![
+            MockResponse::status(401, "bad key"),
+            MockResponse::status(429, "slow down").with_header("Retry-After", "3"),
+        ]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
 
-    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
-    pub tool_type: Option<String>,
+        let error = server.client().chat_completion_stream(&messages, None).await.unwrap_err();
+        assert!(matches!(error, OpenAIError::Auth(ref message) if message == "bad key"));
+        let error = server.client().chat_completion_stream(&messages, None).await.unwrap_err();
+        assert!(matches!(error, OpenAIError::RateLimited { retry_after: Some(wait), .. } if wait == Duration::from_secs(3)));
+    }
 
-    pub function: FunctionCall,
-}
+    #[tokio::test]
+    async fn test_streaming_tool_calls_finish_without_call() {
+        let server = MockServer::start(vec![MockResponse::sse(vec![
+            stream_chunk(serde_json::json!({"content": "Nothing to call"}), None),
+            stream_chunk(serde_json::json!({}), Some("tool_calls")),
+        ])]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct FunctionCall {
-    pub name: Option<String>,
-    pub arguments: String,
-}
+        let mut stream = server.client().chat_completion_stream(&messages, None).await.unwrap();
+        let mut last = stream.recv().await.unwrap();
+        while !last.finished {
+            last = stream.recv().await.unwrap();
+        }
 
-pub fn simple_message(message: String, role: Role) -> Message {
-    Message {
-        role: Some(role),
-        content: Some(message),
-        tool_calls: None,
-        tool_call_id: None,
+        assert_eq!(last.final_content.as_deref(), Some("Nothing to call"));
+        assert!(stream.recv().await.is_none());
     }
-}
 
-pub fn tool_call_result(id: String, result: String) -> Message {
-    Message {
-        role: Some(Role::Tool),
-        content: Some(result),
-        tool_calls: None,
-        tool_call_id: Some(id),
+    #[tokio::test]
+    async fn test_streaming_cut_off_at_byte_limit() {
+        // A model that never stops talking
+        let deltas: Vec<serde_json::Value> = (0..500)
+            .map(|_| stream_chunk(serde_json::json!({"content": "again "}), None))
+            .collect();
+        let server = MockServer::start(vec![MockResponse::sse(deltas)]).await;
+        let messages = vec![simple_message("Count forever".to_string(), Role::User)];
+
+        let mut stream = server.client().with_max_stream_bytes(2000).chat_completion_stream(&messages, None).await.unwrap();
+        let mut streamed = String::new();
+        let last = loop {
+            let chunk = stream.recv().await.unwrap();
+            if chunk.finished {
+                break chunk;
+            }
+            assert!(!chunk.truncated);
+            streamed.push_str(chunk.choices[0].delta.as_ref().unwrap().content.as_deref().unwrap());
+        };
+
+        assert!(last.truncated);
+        assert_eq!(last.final_content.unwrap(), streamed);
+        assert!(streamed.len() < 2000);
+        assert!(stream.recv().await.is_none());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::env;
-    use super::*;
+    #[tokio::test]
+    async fn test_streaming_multibyte_split_across_chunks() {
+        let event = format!(
+            "data: {}\n\n",
+            stream_chunk(serde_json::json!({"role": "assistant", "content": "Привіт, café"}), None)
+        );
+        let bytes = event.into_bytes();
+        // Split in the middle of the first Cyrillic letter
+        let split = bytes.iter().position(|&b| b >= 0x80).unwrap() + 1;
+        let stop = format!("data: {}\n\ndata: [DONE]\n\n", stream_chunk(serde_json::json!({}), Some("stop")));
 
-    fn setup_client() -> OpenAIClient {
-        let base_url = env::var("CODR_BASE_URL").expect("CODR_BASE_URL must be set");
-        let api_key = env::var("CODR_API_KEY").expect("CODR_API_KEY must be set");
-        let model = env::var("CODR_MODEL").expect("CODR_MODEL must be set");
+        let server = MockServer::start(vec![MockResponse::byte_stream(vec![
+            bytes[..split].to_vec(),
+            bytes[split..].to_vec(),
+            stop.into_bytes(),
+        ])]).await;
+        let messages = vec![simple_message("Hello".to_string(), Role::User)];
 
-        OpenAIClient::new(base_url, api_key, model)
+        let mut stream = server.client().chat_completion_stream(&messages, None).await.unwrap();
+        let mut final_content = None;
+        while let Some(chunk) = stream.recv().await {
+            if chunk.finished {
+                final_content = chunk.final_content;
+                break;
+            }
+        }
+
+        assert_eq!(final_content.as_deref(), Some("Привіт, café"));
     }
 
     #[tokio::test]
@@ -349,7 +2189,7 @@ mod tests {
         match setup_client().chat_completion(&messages, Some(Box::new(vec![weather_tool]))).await {
             Ok(response) => {
                 assert!(!response.choices.is_empty());
-                assert!(!response.choices[0].message.clone().unwrap().tool_calls.is_none());
+                assert!(response.choices[0].message.clone().unwrap().tool_calls.is_some());
                 for choice in response.choices {
                     if let Some(tool_calls) = choice.message.unwrap().tool_calls {
                         for tool_call in tool_calls {
@@ -372,7 +2212,7 @@ mod tests {
         ];
 
         let client = setup_client();
-        let mut stream = client.chat_completion_stream(&messages, None).await;
+        let mut stream = client.chat_completion_stream(&messages, None).await.unwrap();
         let mut all_content = String::new();
         while let Some(chunk) = stream.recv().await {
             if chunk.finished {
@@ -416,7 +2256,7 @@ mod tests {
         ];
 
         let client = setup_client();
-        let mut stream = client.chat_completion_stream(&messages, Some(Box::new(vec![weather_tool]))).await;
+        let mut stream = client.chat_completion_stream(&messages, Some(Box::new(vec![weather_tool]))).await.unwrap();
 
         while let Some(chunk) = stream.recv().await {
             if chunk.finished {
@@ -424,13 +2264,12 @@ mod tests {
             } else {
                 for choice in chunk.choices {
                     println!("Received chunk: {:?}", choice.delta);
-                    if let Some(delta) = choice.delta {
-                        if delta.tool_calls.is_some() {
-                            for tool_call in delta.tool_calls.unwrap() {
-                                println!("Tool call: {:?}", tool_call);
-                                assert_eq!(tool_call.function.name.unwrap(), "get_weather".to_string());
-                                assert_eq!(tool_call.function.arguments.replace(" ", "").to_lowercase(), "{\"location\":\"london\"}");
-                            }
+                    if let Some(delta) = choice.delta
+                        && let Some(tool_calls) = delta.tool_calls {
+                        for tool_call in tool_calls {
+                            println!("Tool call: {:?}", tool_call);
+                            assert_eq!(tool_call.function.name.unwrap(), "get_weather".to_string());
+                            assert_eq!(tool_call.function.arguments.replace(" ", "").to_lowercase(), "{\"location\":\"london\"}");
                         }
                     }
                 }
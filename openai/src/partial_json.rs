@@ -0,0 +1,134 @@
+use serde_json::Value;
+
+/// Accumulates streamed text holding a JSON document and parses it as it
+/// grows, so structured output can be shown before the model is done.
+#[derive(Debug, Default)]
+pub struct PartialJson {
+    buffer: String,
+    latest: Option<Value>,
+}
+
+impl PartialJson {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a chunk and returns the partial value if it changed since the last call.
+    pub fn push(&mut self, chunk: &str) -> Option<Value> {
+        self.buffer.push_str(chunk);
+
+        let value = parse_partial_json(&self.buffer)?;
+        if self.latest.as_ref() == Some(&value) {
+            return None;
+        }
+
+        self.latest = Some(value.clone());
+        Some(value)
+    }
+
+    /// The most complete value parsed so far.
+    pub fn latest(&self) -> Option<&Value> {
+        self.latest.as_ref()
+    }
+}
+
+/// Parses the longest usable prefix of an incomplete JSON object or array:
+/// open strings and containers are closed, and a trailing member that can't be
+/// completed (a key without a value, a half-written number or literal) is dropped.
+pub fn parse_partial_json(text: &str) -> Option<Value> {
+    let start = text.find(['{', '['])?;
+    let text = &text[start..];
+
+    let mut stack = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    // Prefix lengths after which the document is complete but for closing brackets
+    let mut safe_points = Vec::new();
+
+    for (i, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => {
+                stack.push(c);
+                safe_points.push((i + 1, stack.clone()));
+            }
+            '}' | ']' => {
+                stack.pop();
+                if stack.is_empty() {
+                    return serde_json::from_str(&text[..=i]).ok();
+                }
+                safe_points.push((i + 1, stack.clone()));
+            }
+            ',' => safe_points.push((i, stack.clone())),
+            _ => {}
+        }
+    }
+
+    let mut whole = text.to_string();
+    if in_string {
+        if escaped {
+            whole.pop();
+        }
+        whole.push('"');
+    }
+    if let Ok(value) = serde_json::from_str(&close(&whole, &stack)) {
+        return Some(value);
+    }
+
+    safe_points.iter().rev().find_map(|(end, stack)| serde_json::from_str(&close(&text[..*end], stack)).ok())
+}
+
+fn close(prefix: &str, stack: &[char]) -> String {
+    let mut closed = prefix.to_string();
+    for open in stack.iter().rev() {
+        closed.push(if *open == '{' { '}' } else { ']' });
+    }
+    closed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progressive_partials() {
+        let fragments = [
+            r#"{"title": "Fix"#,
+            r#" the bug", "ste"#,
+            r#"ps": ["Read main"#,
+            r#".rs", "Edit it"], "done": tr"#,
+            r#"ue}"#,
+        ];
+        let mut parser = PartialJson::new();
+
+        let partials: Vec<_> = fragments.iter().map(|fragment| parser.push(fragment)).collect();
+
+        assert_eq!(partials, vec![
+            Some(serde_json::json!({"title": "Fix"})),
+            Some(serde_json::json!({"title": "Fix the bug"})),
+            Some(serde_json::json!({"title": "Fix the bug", "steps": ["Read main"]})),
+            Some(serde_json::json!({"title": "Fix the bug", "steps": ["Read main.rs", "Edit it"]})),
+            Some(serde_json::json!({"title": "Fix the bug", "steps": ["Read main.rs", "Edit it"], "done": true})),
+        ]);
+    }
+
+    #[test]
+    fn test_incomplete_members_are_dropped() {
+        assert_eq!(parse_partial_json(r#"{"a": 1, "b"#), Some(serde_json::json!({"a": 1})));
+        assert_eq!(parse_partial_json(r#"{"a": "#), Some(serde_json::json!({})));
+        assert_eq!(parse_partial_json(r#"{"a": 1.5, "b": 12."#), Some(serde_json::json!({"a": 1.5})));
+        assert_eq!(parse_partial_json(r#"{"a": "line\"#), Some(serde_json::json!({"a": "line"})));
+        assert_eq!(parse_partial_json("```json\n[1, [2, 3"), Some(serde_json::json!([1, [2, 3]])));
+        assert_eq!(parse_partial_json("no json here"), None);
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// One request and the response it got, as stored by a [`Recorder`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    pub request: serde_json::Value,
+    pub status: u16,
+    /// Body of a non-streaming response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Decoded chunks of a streaming response, in the order they arrived.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<Vec<String>>,
+}
+
+/// Answers requests without touching the network, e.g. a [`Replay`].
+pub trait ResponseSource: Send + Sync + std::fmt::Debug {
+    /// The response to `request`, the body about to be sent.
+    fn respond(&self, request: &serde_json::Value) -> io::Result<Exchange>;
+}
+
+/// Appends every exchange of a client to a JSONL file, one exchange per line.
+#[derive(Debug)]
+pub struct Recorder {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Recorder {
+            writer: Mutex::new(BufWriter::new(File::create(path)?)),
+        })
+    }
+
+    /// Writes the exchange and flushes, so the recording survives a crash mid-session.
+    pub fn record(&self, exchange: &Exchange) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        serde_json::to_writer(&mut *writer, exchange)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+}
+
+/// Serves the responses of a recording, in order, instead of calling the API.
+#[derive(Debug)]
+pub struct Replay {
+    exchanges: Mutex<VecDeque<Exchange>>,
+}
+
+impl Replay {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let exchanges = fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<VecDeque<Exchange>, _>>()?;
+
+        Ok(Replay { exchanges: Mutex::new(exchanges) })
+    }
+
+    pub fn next(&self) -> io::Result<Exchange> {
+        self.exchanges.lock().unwrap()
+            .pop_front()
+            .ok_or_else(|| io::Error::other("Replay exhausted: no more recorded responses"))
+    }
+}
+
+impl ResponseSource for Replay {
+    fn respond(&self, _request: &serde_json::Value) -> io::Result<Exchange> {
+        self.next()
+    }
+}
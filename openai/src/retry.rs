@@ -0,0 +1,111 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::{Duration, SystemTime},
+};
+
+/// How failed requests are retried against the same provider. Only network
+/// errors, rate limiting and server errors are retried.
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// Retries after the first attempt. `0` disables retrying.
+    pub max_retries: u32,
+    /// Upper bound of the first backoff, doubled on every further retry.
+    pub base_delay: Duration,
+    /// Cap on any single wait, including one requested through `Retry-After`.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_retries: 0,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// "Full jitter" backoff: a random wait between zero and the exponential
+    /// bound, so many clients failing together don't retry in lockstep.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let bound = self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        bound.mul_f64(random_fraction())
+    }
+
+    /// Wait before retry number `attempt + 1`, preferring the server's `Retry-After` hint.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        match retry_after {
+            Some(retry_after) => retry_after.min(self.max_delay),
+            None => self.backoff(attempt),
+        }
+    }
+}
+
+/// Parses a `Retry-After` value, given either as seconds or as an HTTP-date.
+/// A date in the past means retrying right away.
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+/// A uniformly distributed value in `[0, 1)`.
+fn random_fraction() -> f64 {
+    // Every RandomState is seeded differently, which is all the randomness jitter needs
+    let hasher = RandomState::new().build_hasher();
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_after_seconds() {
+        assert_eq!(parse_retry_after("120", SystemTime::now()), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 ", SystemTime::now()), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon", SystemTime::now()), None);
+    }
+
+    #[test]
+    fn test_retry_after_http_date() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now), Some(Duration::from_secs(30)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_backoff_jitter_within_bounds() {
+        let config = RetryConfig {
+            max_retries: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+
+        for attempt in 0..8 {
+            let bound = Duration::from_millis(100 * 2u64.pow(attempt)).min(Duration::from_secs(1));
+            let delays: Vec<_> = (0..200).map(|_| config.backoff(attempt)).collect();
+
+            assert!(delays.iter().all(|delay| *delay <= bound), "attempt {} exceeded {:?}", attempt, bound);
+            // Jittered, not a fixed delay
+            assert!(delays.iter().any(|delay| *delay != delays[0]));
+        }
+    }
+
+    #[test]
+    fn test_retry_after_capped() {
+        let config = RetryConfig::default();
+
+        assert_eq!(config.delay(0, Some(Duration::from_secs(3600))), config.max_delay);
+        assert_eq!(config.delay(0, Some(Duration::from_secs(2))), Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,80 @@
+/// Keeps `stop` sequences out of streamed text. Some providers let a stop
+/// sequence, or the start of one, leak into the deltas before they stop,
+/// whereas a non-streaming response never contains it. Text that might be the
+/// start of a stop sequence is held back until the next chunk decides it.
+#[derive(Debug, Default)]
+pub struct StopSequences {
+    stops: Vec<String>,
+    held: String,
+    stopped: bool,
+}
+
+impl StopSequences {
+    pub fn new(stops: Vec<String>) -> Self {
+        StopSequences {
+            stops: stops.into_iter().filter(|stop| !stop.is_empty()).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Appends a chunk and returns the part of the text that is safe to show.
+    /// Nothing is returned once a stop sequence was seen.
+    pub fn push(&mut self, chunk: &str) -> String {
+        if self.stopped {
+            return String::new();
+        }
+        if self.stops.is_empty() {
+            return chunk.to_string();
+        }
+        self.held.push_str(chunk);
+
+        let earliest_stop = self.stops.iter().filter_map(|stop| self.held.find(stop.as_str())).min();
+        if let Some(position) = earliest_stop {
+            self.stopped = true;
+            let visible = self.held[..position].to_string();
+            self.held.clear();
+            return visible;
+        }
+
+        // Hold back the longest tail that could still grow into a stop sequence
+        let keep_from = self.held.char_indices()
+            .map(|(i, _)| i)
+            .find(|&i| self.stops.iter().any(|stop| stop.starts_with(&self.held[i..])))
+            .unwrap_or(self.held.len());
+        self.held.drain(..keep_from).collect()
+    }
+
+    /// Returns the text held back, which turned out not to be a stop sequence.
+    pub fn finish(&mut self) -> String {
+        std::mem::take(&mut self.held)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(stops: &[&str], chunks: &[&str]) -> Vec<String> {
+        let mut sequences = StopSequences::new(stops.iter().map(|stop| stop.to_string()).collect());
+        let mut visible: Vec<String> = chunks.iter().map(|chunk| sequences.push(chunk)).collect();
+        visible.push(sequences.finish());
+        visible
+    }
+
+    #[test]
+    fn test_stop_sequence_split_across_chunks() {
+        assert_eq!(stream(&["\nEND"], &["Done.", "\nE", "ND and more", " text"]), vec!["Done.", "", "", "", ""]);
+    }
+
+    #[test]
+    fn test_partial_match_released() {
+        // "\nE" looked like the start of "\nEND" but wasn't
+        assert_eq!(stream(&["\nEND"], &["One\nE", "xtra"]), vec!["One", "\nExtra", ""]);
+        assert_eq!(stream(&["###"], &["Title #"]), vec!["Title ", "#"]);
+    }
+
+    #[test]
+    fn test_without_stops() {
+        assert_eq!(stream(&[], &["a", "b"]), vec!["a", "b", ""]);
+    }
+}
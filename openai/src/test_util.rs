@@ -0,0 +1,299 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// A canned HTTP response served by [`MockServer`].
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    chunks: Vec<Vec<u8>>,
+    chunked: bool,
+    chunk_delay: Duration,
+}
+
+impl MockResponse {
+    pub fn json(body: serde_json::Value) -> Self {
+        Self::status(200, &body.to_string())
+            .with_header("Content-Type", "application/json")
+    }
+
+    pub fn status(status: u16, body: &str) -> Self {
+        MockResponse {
+            status,
+            headers: Vec::new(),
+            chunks: vec![body.as_bytes().to_vec()],
+            chunked: false,
+            chunk_delay: Duration::ZERO,
+        }
+    }
+
+    /// Streams every event as its own `data: ...` line, followed by `[DONE]`.
+    pub fn sse(events: Vec<serde_json::Value>) -> Self {
+        let mut chunks: Vec<String> = events.iter()
+            .map(|event| format!("data: {}\n\n", event))
+            .collect();
+        chunks.push("data: [DONE]\n\n".to_string());
+        Self::raw_stream(chunks)
+    }
+
+    /// Streams the given strings verbatim, one HTTP chunk each.
+    pub fn raw_stream(chunks: Vec<String>) -> Self {
+        Self::byte_stream(chunks.into_iter().map(String::into_bytes).collect())
+    }
+
+    /// Streams the given byte slices verbatim, one HTTP chunk each.
+    pub fn byte_stream(chunks: Vec<Vec<u8>>) -> Self {
+        MockResponse {
+            status: 200,
+            headers: vec![("Content-Type".to_string(), "text/event-stream".to_string())],
+            chunks,
+            chunked: true,
+            chunk_delay: Duration::from_millis(10),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_chunk_delay(mut self, delay: Duration) -> Self {
+        self.chunk_delay = delay;
+        self
+    }
+}
+
+/// A request received by [`MockServer`].
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap_or(serde_json::Value::Null)
+    }
+}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    responses: Vec<MockResponse>,
+    requests: Vec<RecordedRequest>,
+    connections: usize,
+}
+
+/// A minimal local HTTP server replaying canned responses in order, used to
+/// exercise the client without reaching a real API.
+#[derive(Debug, Clone)]
+pub struct MockServer {
+    address: String,
+    state: Arc<Mutex<ServerState>>,
+}
+
+impl MockServer {
+    pub async fn start(responses: Vec<MockResponse>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(ServerState {
+            responses: responses.into_iter().rev().collect(),
+            ..Default::default()
+        }));
+
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                server_state.lock().unwrap().connections += 1;
+                tokio::spawn(handle_connection(socket, server_state.clone()));
+            }
+        });
+
+        MockServer { address, state }
+    }
+
+    /// Base URL to hand to `OpenAIClient::new`.
+    pub fn url(&self) -> String {
+        format!("{}/v1", self.address)
+    }
+
+    pub fn client(&self) -> crate::OpenAIClient {
+        crate::OpenAIClient::new(self.url(), "test-key".to_string(), "test-model".to_string())
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Number of TCP connections accepted so far.
+    pub fn connections(&self) -> usize {
+        self.state.lock().unwrap().connections
+    }
+}
+
+async fn handle_connection(mut socket: TcpStream, state: Arc<Mutex<ServerState>>) {
+    let mut buffer = Vec::new();
+    loop {
+        let request = match read_request(&mut socket, &mut buffer).await {
+            Some(request) => request,
+            None => return,
+        };
+
+        let response = {
+            let mut state = state.lock().unwrap();
+            state.requests.push(request);
+            state.responses.pop()
+        }.unwrap_or_else(|| MockResponse::status(500, "no more mock responses"));
+
+        if write_response(&mut socket, response).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn read_request(socket: &mut TcpStream, buffer: &mut Vec<u8>) -> Option<RecordedRequest> {
+    let header_end = loop {
+        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let mut chunk = [0u8; 4096];
+        let read = socket.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    let content_length = headers.iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    while buffer.len() < header_end + content_length {
+        let mut chunk = [0u8; 4096];
+        let read = socket.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+
+    let body = String::from_utf8_lossy(&buffer[header_end..header_end + content_length]).to_string();
+    buffer.drain(..header_end + content_length);
+
+    Some(RecordedRequest { method, path, headers, body })
+}
+
+async fn write_response(socket: &mut TcpStream, response: MockResponse) -> std::io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} Mock\r\n", response.status);
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    if !response.chunked {
+        let body = response.chunks.concat();
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+        socket.write_all(head.as_bytes()).await?;
+        socket.write_all(&body).await?;
+        return socket.flush().await;
+    }
+
+    head.push_str("Transfer-Encoding: chunked\r\n\r\n");
+    socket.write_all(head.as_bytes()).await?;
+    for chunk in response.chunks {
+        socket.write_all(format!("{:x}\r\n", chunk.len()).as_bytes()).await?;
+        socket.write_all(&chunk).await?;
+        socket.write_all(b"\r\n").await?;
+        socket.flush().await?;
+        tokio::time::sleep(response.chunk_delay).await;
+    }
+    socket.write_all(b"0\r\n\r\n").await?;
+    socket.flush().await
+}
+
+/// A non-streaming completion whose single choice is a plain assistant reply.
+pub fn text_completion(content: &str) -> MockResponse {
+    MockResponse::json(serde_json::json!({
+        "choices": [{
+            "message": {"role": "assistant", "content": content},
+            "finish_reason": "stop"
+        }]
+    }))
+}
+
+/// A plain assistant reply that reports the given token usage.
+pub fn text_completion_with_usage(content: &str, prompt_tokens: u64, completion_tokens: u64) -> MockResponse {
+    MockResponse::json(serde_json::json!({
+        "choices": [{
+            "message": {"role": "assistant", "content": content},
+            "finish_reason": "stop"
+        }],
+        "usage": {
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens
+        }
+    }))
+}
+
+/// A non-streaming completion whose single choice asks for one tool call.
+pub fn tool_call_completion(id: &str, name: &str, arguments: serde_json::Value) -> MockResponse {
+    MockResponse::json(serde_json::json!({
+        "choices": [{
+            "message": {
+                "role": "assistant",
+                "tool_calls": [{
+                    "id": id,
+                    "type": "function",
+                    "function": {"name": name, "arguments": arguments.to_string()}
+                }]
+            },
+            "finish_reason": "tool_calls"
+        }]
+    }))
+}
+
+/// One `chat.completion.chunk` event for [`MockResponse::sse`].
+pub fn stream_chunk(delta: serde_json::Value, finish_reason: Option<&str>) -> serde_json::Value {
+    serde_json::json!({
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "test-model",
+        "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]
+    })
+}
+
+/// The usage event that ends a stream requested with `include_usage`, for [`MockResponse::sse`].
+pub fn stream_usage_chunk(prompt_tokens: u64, completion_tokens: u64) -> serde_json::Value {
+    serde_json::json!({
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "test-model",
+        "choices": [],
+        "usage": {"prompt_tokens": prompt_tokens, "completion_tokens": completion_tokens, "total_tokens": prompt_tokens + completion_tokens}
+    })
+}
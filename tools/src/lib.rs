@@ -1,2 +1,3 @@
 pub mod tool_box;
 
+pub use tool_box::{ToolBox, composite::{CompositeStep, CompositeTool}, context::ToolContext, tools::{ToolFuture, ToolHandler}};
@@ -1,15 +1,51 @@
+pub mod composite;
+pub mod context;
+pub mod file_watcher;
+pub mod ignore;
+pub mod middleware;
+pub mod pending_edits;
+pub mod snapshots;
 pub mod tools;
 
+use std::{collections::HashMap, sync::Arc};
+
 use openai::Tool as OpenAITool;
 
-use crate::tool_box::tools::{Tool, file_tools::{
-    new_append_to_file_tool, new_create_folder_tool, new_get_folder_files_tool,
-    new_read_file_tool, new_replace_file_tool, new_write_file_tool,
-}};
+use crate::tool_box::composite::{CompositeHandler, CompositeTool};
+use crate::tool_box::context::ToolContext;
+use crate::tool_box::middleware::ToolMiddleware;
+use crate::tool_box::tools::{Tool, ToolHandler, ToolResult, handler_to_openai_tool, validate_parameters, command_tools::new_run_file_tool, file_tools::{
+    new_append_to_file_tool, new_apply_pending_edit_tool, new_conditional_write_tool, new_create_folder_tool, new_delete_folder_tool, new_get_folder_files_tool,
+    new_diff_files_tool, new_head_tail_tool, new_json_patch_tool, new_read_file_tool, new_replace_file_tool, new_write_file_tool, new_write_files_tool,
+}, project_tools::{new_code_stats_tool, new_find_definition_tool, new_project_overview_tool, new_project_replace_tool, new_summarize_file_tool, new_workspace_info_tool}, scratchpad_tools::new_scratchpad_tool, snapshot_tools::{new_restore_snapshot_tool, new_snapshot_tool}, user_tools::new_ask_user_tool, watch_tools::new_get_file_changes_tool};
 
 #[derive(Debug, Clone)]
 pub struct ToolBox {
-    tools: Vec<Box<Tool>>,
+    tools: Vec<RegisteredTool>,
+    context: ToolContext,
+    allowed_tools: Option<Vec<String>>,
+    max_result_tokens: Option<u64>,
+    middleware: ToolMiddleware,
+}
+
+/// A tool in a [`ToolBox`], built-in or not.
+#[derive(Debug, Clone)]
+struct RegisteredTool {
+    handler: Arc<dyn ToolHandler>,
+    /// Replaces the handler's own description, see [`ToolBox::set_tool_descriptions`].
+    description: Option<String>,
+    /// Tools a composite runs, which must be allowed for it to be.
+    steps: Vec<String>,
+}
+
+impl RegisteredTool {
+    fn new(handler: Arc<dyn ToolHandler>) -> Self {
+        RegisteredTool { handler, description: None, steps: Vec::new() }
+    }
+
+    fn name(&self) -> &str {
+        self.handler.name()
+    }
 }
 
 pub fn status_success() -> Result<serde_json::Value, Box<dyn std::error::Error>> {
@@ -20,34 +56,399 @@ pub fn err(message: &str) -> Result<serde_json::Value, Box<dyn std::error::Error
     Ok(serde_json::json!({"status": "error", "message": message}))
 }
 
+impl Default for ToolBox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ToolBox {
     pub fn new() -> Self {
+        Self::with_context(ToolContext::default())
+    }
+
+    pub fn with_context(context: ToolContext) -> Self {
+        let builtins = [
+            new_write_file_tool(),
+            new_write_files_tool(),
+            new_replace_file_tool(),
+            new_conditional_write_tool(),
+            new_apply_pending_edit_tool(),
+            new_json_patch_tool(),
+            new_read_file_tool(),
+            new_head_tail_tool(),
+            new_diff_files_tool(),
+            new_append_to_file_tool(),
+            new_create_folder_tool(),
+            new_delete_folder_tool(),
+            new_get_folder_files_tool(),
+            new_project_overview_tool(),
+            new_workspace_info_tool(),
+            new_find_definition_tool(),
+            new_summarize_file_tool(),
+            new_project_replace_tool(),
+            new_code_stats_tool(),
+            new_snapshot_tool(),
+            new_restore_snapshot_tool(),
+            new_scratchpad_tool(),
+            new_ask_user_tool(),
+            new_get_file_changes_tool(),
+            new_run_file_tool(),
+        ];
         ToolBox {
-            tools: vec![
-                Box::new(new_write_file_tool()),
-                Box::new(new_replace_file_tool()),
-                Box::new(new_read_file_tool()),
-                Box::new(new_append_to_file_tool()),
-                Box::new(new_create_folder_tool()),
-                Box::new(new_get_folder_files_tool()),
-            ],
-        }
-    }
-
-    pub fn run_tool(&self, name: &str, args: serde_json::Value) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
-        let tool = self.tools.iter().find(|tool| tool.name() == name).map(|tool| tool.as_ref());
-        match tool {
-            Some(tool) => {
-                tool.run(args).map_err(|e| {
-                    eprintln!("Error running tool {}: {}", name, e);
-                    e
-                })
-            }
-            None => Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, "Tool not found"))),
+            tools: builtins.into_iter().map(|tool| RegisteredTool::new(Arc::new(tool))).collect(),
+            context,
+            allowed_tools: None,
+            max_result_tokens: None,
+            middleware: ToolMiddleware::default(),
+        }
+    }
+
+    /// Adds a tool after validating its parameters schema. Fails on an invalid
+    /// schema or a name that is already taken.
+    pub fn register(&mut self, tool: Tool) -> Result<(), Box<dyn std::error::Error>> {
+        self.register_handler(tool)
+    }
+
+    /// Adds a tool implemented by your own type, offered and run just like the
+    /// built-in ones. Fails on an invalid schema or a name that is already taken.
+    pub fn register_handler(&mut self, handler: impl ToolHandler + 'static) -> Result<(), Box<dyn std::error::Error>> {
+        validate_parameters(handler.name(), &handler.parameters())?;
+        self.check_name_free(handler.name())?;
+        self.tools.push(RegisteredTool::new(Arc::new(handler)));
+        Ok(())
+    }
+
+    /// Adds a tool running other tools in order, see [`CompositeTool`]. Hooks
+    /// see the composite call only, not each of its steps. Fails on an invalid
+    /// schema, a name that is already taken or a step naming a tool that isn't
+    /// registered yet.
+    pub fn register_composite(&mut self, composite: CompositeTool) -> Result<(), Box<dyn std::error::Error>> {
+        validate_parameters(&composite.name, &composite.parameters)?;
+        self.check_name_free(&composite.name)?;
+        let steps = composite.steps.iter()
+            .map(|step| self.find(&step.tool)
+                .map(|tool| tool.handler.clone())
+                .ok_or_else(|| format!("Composite tool {} uses unknown tool {}", composite.name, step.tool)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let step_names = composite.steps.iter().map(|step| step.tool.clone()).collect();
+        self.tools.push(RegisteredTool {
+            handler: Arc::new(CompositeHandler::new(composite, steps)),
+            description: None,
+            steps: step_names,
+        });
+        Ok(())
+    }
+
+    /// The registered tool called `name`, whether allowed or not.
+    fn find(&self, name: &str) -> Option<&RegisteredTool> {
+        self.tools.iter().find(|tool| tool.name() == name)
+    }
+
+    fn check_name_free(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if self.find(name).is_some() {
+            return Err(format!("A tool named {} is already registered", name).into());
         }
+        Ok(())
+    }
+
+    /// Adds a hook that sees, and may rewrite, the arguments of every tool call
+    /// before it runs. Hooks run in the order they were added.
+    pub fn add_before_hook(&mut self, hook: impl Fn(&str, &mut serde_json::Value) + Send + Sync + 'static) {
+        self.middleware.add_before(Arc::new(hook));
+    }
+
+    /// Adds a hook that sees, and may rewrite, the result of every tool call
+    /// that succeeded. Hooks run in the order they were added.
+    pub fn add_after_hook(&mut self, hook: impl Fn(&str, &mut serde_json::Value) + Send + Sync + 'static) {
+        self.middleware.add_after(Arc::new(hook));
+    }
+
+    /// Restricts which tools are offered to and runnable by the model. `None`
+    /// allows all of them. A composite tool is only allowed along with every
+    /// tool it runs.
+    pub fn set_allowed_tools(&mut self, allowed_tools: Option<Vec<String>>) {
+        self.allowed_tools = allowed_tools;
+    }
+
+    /// Caps how many tokens of a single tool result reach the model. `None` sends results whole.
+    pub fn set_max_result_tokens(&mut self, max_result_tokens: Option<u64>) {
+        self.max_result_tokens = max_result_tokens;
+    }
+
+    pub fn max_result_tokens(&self) -> Option<u64> {
+        self.max_result_tokens
+    }
+
+    /// Overrides tool descriptions, keyed by tool name, of built-in, custom and
+    /// composite tools alike. Fails on a name that isn't a registered tool, so
+    /// register your own tools first.
+    pub fn set_tool_descriptions(&mut self, descriptions: HashMap<String, String>) -> Result<(), Box<dyn std::error::Error>> {
+        for (name, description) in descriptions {
+            let tool = self.tools.iter_mut()
+                .find(|tool| tool.name() == name)
+                .ok_or_else(|| format!("Unknown tool: {}", name))?;
+            tool.description = Some(description);
+        }
+        Ok(())
+    }
+
+    fn is_allowed(&self, name: &str) -> bool {
+        self.allowed_tools.as_ref()
+            .map(|allowed| allowed.iter().any(|allowed| allowed == name))
+            .unwrap_or(true)
+    }
+
+    fn available_tools(&self) -> impl Iterator<Item = &RegisteredTool> {
+        self.tools.iter()
+            .filter(|tool| self.is_allowed(tool.name()) && tool.steps.iter().all(|step| self.is_allowed(step)))
+    }
+
+    /// Runs a tool call, with the before and after hooks around it.
+    pub async fn run_tool(&self, name: &str, mut args: serde_json::Value) -> ToolResult {
+        let Some(tool) = self.available_tools().find(|tool| tool.name() == name) else {
+            // Name the real tools, so a model that made one up can correct itself
+            let available: Vec<&str> = self.available_tools().map(RegisteredTool::name).collect();
+            let message = format!("Tool not found: {}. Available tools: {}", name, available.join(", "));
+            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::NotFound, message)));
+        };
+
+        self.middleware.run_before(name, &mut args);
+        let mut result = tool.handler.run(&self.context, args).await?;
+        self.middleware.run_after(name, &mut result);
+        Ok(result)
+    }
+
+    pub fn context(&self) -> &ToolContext {
+        &self.context
+    }
+
+    pub fn context_mut(&mut self) -> &mut ToolContext {
+        &mut self.context
     }
 
     pub fn get_tools(&self) -> Vec<OpenAITool> {
-        self.tools.iter().map(|tool| tool.to_openai_tool()).collect()
+        self.available_tools()
+            .map(|tool| {
+                let mut definition = handler_to_openai_tool(tool.handler.as_ref());
+                if let Some(description) = &tool.description {
+                    definition.function.description = description.clone();
+                }
+                definition
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_tools_have_valid_schemas() {
+        for tool in &ToolBox::new().tools {
+            if let Err(e) = validate_parameters(tool.name(), &tool.handler.parameters()) {
+                panic!("{}", e);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hooks_run_around_tool_calls() {
+        let mut toolbox = ToolBox::new();
+        let echo = |_: &ToolContext, args: serde_json::Value| Ok(serde_json::json!({"path": args["path"]}));
+        toolbox.register(Tool::new("echo", "Echoes the path", serde_json::json!({
+            "type": "object",
+            "properties": {"path": {"type": "string"}}
+        }), echo).unwrap()).unwrap();
+
+        toolbox.add_before_hook(|_, args| {
+            if let Some(path) = args["path"].as_str() {
+                args["path"] = path.replace("/old/", "/new/").into();
+            }
+        });
+        toolbox.add_after_hook(|name, result| result["tool"] = name.into());
+        toolbox.add_after_hook(|_, result| result["checked"] = result["tool"].is_string().into());
+
+        let result = toolbox.run_tool("echo", serde_json::json!({"path": "/old/main.rs"})).await.unwrap();
+
+        assert_eq!(result, serde_json::json!({"path": "/new/main.rs", "tool": "echo", "checked": true}));
+    }
+
+    #[test]
+    fn test_register_validates() {
+        let mut toolbox = ToolBox::new();
+        let runner = |_: &ToolContext, _: serde_json::Value| status_success();
+
+        let broken = Tool::new("broken", "", serde_json::json!({"type": "object"}), runner);
+        assert!(broken.is_err());
+
+        let tool = Tool::new("ping", "Replies", serde_json::json!({"type": "object", "properties": {}}), runner).unwrap();
+        toolbox.register(tool.clone()).unwrap();
+        assert!(toolbox.get_tools().iter().any(|tool| tool.function.name == "ping"));
+        assert!(toolbox.register(tool).unwrap_err().to_string().contains("already registered"));
+    }
+
+    /// Counts calls, which a plain `fn` runner has nowhere to keep.
+    struct Counter {
+        calls: std::sync::atomic::AtomicU64,
+    }
+
+    impl ToolHandler for Counter {
+        fn name(&self) -> &str {
+            "count"
+        }
+
+        fn description(&self) -> &str {
+            "Counts how often it was called"
+        }
+
+        fn parameters(&self) -> serde_json::Value {
+            serde_json::json!({
+                "type": "object",
+                "properties": {"step": {"type": "integer"}}
+            })
+        }
+
+        fn run<'a>(&'a self, _ctx: &'a ToolContext, args: serde_json::Value) -> tools::ToolFuture<'a> {
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                let step = args["step"].as_u64().unwrap_or(1);
+                let calls = self.calls.fetch_add(step, std::sync::atomic::Ordering::SeqCst) + step;
+                Ok(serde_json::json!({"calls": calls}))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_handler() {
+        let mut toolbox = ToolBox::new();
+        toolbox.register_handler(Counter { calls: Default::default() }).unwrap();
+        toolbox.add_after_hook(|name, result| result["tool"] = name.into());
+
+        let tool = toolbox.get_tools().into_iter().find(|tool| tool.function.name == "count").unwrap();
+        assert_eq!(tool.function.description, "Counts how often it was called");
+
+        toolbox.run_tool("count", serde_json::json!({})).await.unwrap();
+        let result = toolbox.run_tool("count", serde_json::json!({"step": 2})).await.unwrap();
+        assert_eq!(result, serde_json::json!({"calls": 3, "tool": "count"}));
+
+        assert!(toolbox.register_handler(Counter { calls: Default::default() }).unwrap_err().to_string().contains("already registered"));
+        toolbox.set_allowed_tools(Some(vec!["read_file".to_string()]));
+        assert!(toolbox.run_tool("count", serde_json::json!({})).await.is_err());
+    }
+
+    #[test]
+    fn test_tool_descriptions_cover_every_tool() {
+        let mut toolbox = ToolBox::new();
+        toolbox.register_handler(Counter { calls: Default::default() }).unwrap();
+        toolbox.register_composite(serde_json::from_value(serde_json::json!({
+            "name": "read_twice",
+            "description": "Reads a file twice",
+            "parameters": {"type": "object", "properties": {"file_path": {"type": "string"}}},
+            "steps": [
+                {"tool": "read_file", "args": {"file_path": "$args.file_path"}},
+                {"tool": "read_file", "args": {"file_path": "$args.file_path"}}
+            ]
+        })).unwrap()).unwrap();
+
+        toolbox.set_tool_descriptions(HashMap::from([
+            ("read_file".to_string(), "Reads".to_string()),
+            ("count".to_string(), "Counts".to_string()),
+            ("read_twice".to_string(), "Reads again".to_string()),
+        ])).unwrap();
+
+        let description = |name: &str| toolbox.get_tools().into_iter()
+            .find(|tool| tool.function.name == name)
+            .unwrap()
+            .function.description;
+        assert_eq!(description("read_file"), "Reads");
+        assert_eq!(description("count"), "Counts");
+        assert_eq!(description("read_twice"), "Reads again");
+        assert!(toolbox.set_tool_descriptions(HashMap::from([("teleport".to_string(), "".to_string())])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_composite_tool() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("notes.txt");
+        std::fs::write(&source, "shout this").unwrap();
+        let mut toolbox = ToolBox::with_context(ToolContext::new(dir.path()));
+        let uppercase = |_: &ToolContext, args: serde_json::Value| {
+            Ok(serde_json::json!({"text": args["text"].as_str().unwrap_or_default().to_uppercase()}))
+        };
+        toolbox.register(Tool::new("uppercase", "Uppercases text", serde_json::json!({
+            "type": "object",
+            "properties": {"text": {"type": "string"}}
+        }), uppercase).unwrap()).unwrap();
+
+        let composite: CompositeTool = serde_json::from_value(serde_json::json!({
+            "name": "shout_file",
+            "description": "Uppercases a file into another",
+            "parameters": {
+                "type": "object",
+                "properties": {"source": {"type": "string"}, "target": {"type": "string"}},
+                "required": ["source", "target"]
+            },
+            "steps": [
+                {"tool": "read_file", "args": {"file_path": "$args.source"}},
+                {"tool": "uppercase", "args": {"text": "$steps.0.content"}},
+                {"tool": "write_file", "args": {"file_path": "$args.target", "content": "$steps.1.text"}}
+            ]
+        })).unwrap();
+        toolbox.register_composite(composite).unwrap();
+        assert!(toolbox.get_tools().iter().any(|tool| tool.function.name == "shout_file"));
+
+        let result = toolbox.run_tool("shout_file", serde_json::json!({"source": source, "target": "loud.txt"})).await.unwrap();
+
+        assert_eq!(result["status"], "success");
+        let steps = result["steps"].as_array().unwrap();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[1], serde_json::json!({"tool": "uppercase", "result": {"text": "SHOUT THIS"}}));
+        assert_eq!(std::fs::read_to_string(dir.path().join("loud.txt")).unwrap(), "SHOUT THIS");
+
+        toolbox.set_allowed_tools(Some(vec!["shout_file".to_string(), "read_file".to_string()]));
+        let offered: Vec<String> = toolbox.get_tools().into_iter().map(|tool| tool.function.name).collect();
+        assert_eq!(offered, vec!["read_file"]);
+    }
+
+    #[tokio::test]
+    async fn test_hooks_skip_composite_steps() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+        let mut toolbox = ToolBox::with_context(ToolContext::new(dir.path()));
+        toolbox.register_composite(serde_json::from_value(serde_json::json!({
+            "name": "read_twice",
+            "description": "Reads a file twice",
+            "parameters": {"type": "object", "properties": {"file_path": {"type": "string"}}},
+            "steps": [
+                {"tool": "read_file", "args": {"file_path": "$args.file_path"}},
+                {"tool": "read_file", "args": {"file_path": "$args.file_path"}}
+            ]
+        })).unwrap()).unwrap();
+
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let before = seen.clone();
+        toolbox.add_before_hook(move |name, _| before.lock().unwrap().push(format!("before {}", name)));
+        let after = seen.clone();
+        toolbox.add_after_hook(move |name, _| after.lock().unwrap().push(format!("after {}", name)));
+
+        let result = toolbox.run_tool("read_twice", serde_json::json!({"file_path": "notes.txt"})).await.unwrap();
+
+        assert_eq!(result["status"], "success");
+        assert_eq!(*seen.lock().unwrap(), vec!["before read_twice", "after read_twice"]);
+    }
+
+    #[test]
+    fn test_composite_tool_needs_known_steps() {
+        let composite: CompositeTool = serde_json::from_value(serde_json::json!({
+            "name": "broken",
+            "description": "Uses a tool that doesn't exist",
+            "parameters": {"type": "object", "properties": {}},
+            "steps": [{"tool": "teleport"}]
+        })).unwrap();
+
+        let error = ToolBox::new().register_composite(composite).unwrap_err();
+        assert_eq!(error.to_string(), "Composite tool broken uses unknown tool teleport");
     }
 }
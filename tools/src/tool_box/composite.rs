@@ -0,0 +1,150 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::tool_box::context::ToolContext;
+use crate::tool_box::tools::{ToolFuture, ToolHandler};
+
+/// A tool that runs other tools one after another and answers with a single
+/// combined result, so one logical step doesn't spread over several results.
+///
+/// Step arguments may refer to the composite's own arguments and to earlier
+/// results with strings of the form `"$args.source"` or `"$steps.0.content"`,
+/// which are replaced by the value they point to.
+///
+/// Tool hooks run around the composite call as a whole; its steps run without
+/// them, so a hook sees one call however many steps there are.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompositeTool {
+    pub name: String,
+    pub description: String,
+    /// JSON Schema of the composite's own arguments.
+    pub parameters: serde_json::Value,
+    pub steps: Vec<CompositeStep>,
+}
+
+/// One sub-tool call of a [`CompositeTool`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompositeStep {
+    pub tool: String,
+    #[serde(default = "empty_object")]
+    pub args: serde_json::Value,
+}
+
+fn empty_object() -> serde_json::Value {
+    serde_json::json!({})
+}
+
+/// A registered [`CompositeTool`], with each step resolved to the tool it runs.
+pub(crate) struct CompositeHandler {
+    composite: CompositeTool,
+    steps: Vec<Arc<dyn ToolHandler>>,
+}
+
+impl CompositeHandler {
+    /// `steps` holds the tool of each step of `composite`, in order.
+    pub(crate) fn new(composite: CompositeTool, steps: Vec<Arc<dyn ToolHandler>>) -> Self {
+        CompositeHandler { composite, steps }
+    }
+}
+
+impl ToolHandler for CompositeHandler {
+    fn name(&self) -> &str {
+        &self.composite.name
+    }
+
+    fn description(&self) -> &str {
+        &self.composite.description
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        self.composite.parameters.clone()
+    }
+
+    /// Runs the steps in order, stopping at the first one that fails, and
+    /// combines what they returned into one result.
+    fn run<'a>(&'a self, ctx: &'a ToolContext, args: serde_json::Value) -> ToolFuture<'a> {
+        Box::pin(async move {
+            let name = &self.composite.name;
+            let mut results = Vec::new();
+            let mut steps = Vec::new();
+            for (index, (step, handler)) in self.composite.steps.iter().zip(&self.steps).enumerate() {
+                let step_args = resolve_args(&step.args, &args, &results);
+                let result = handler.run(ctx, step_args).await
+                    .map_err(|e| format!("Step {} ({}) of {} failed: {}", index, step.tool, name, e))?;
+                let failed = result["status"] == "error";
+                steps.push(serde_json::json!({"tool": step.tool, "result": result}));
+                if failed {
+                    let message = format!("Step {} ({}) of {} failed: {}", index, step.tool, name, result["message"].as_str().unwrap_or("unknown error"));
+                    return Ok(serde_json::json!({"status": "error", "message": message, "steps": steps}));
+                }
+                results.push(result);
+            }
+            Ok(serde_json::json!({"status": "success", "steps": steps}))
+        })
+    }
+}
+
+/// `template` with every reference replaced by what it points to in `args` or
+/// `results`. A reference to something missing becomes `null`.
+pub fn resolve_args(template: &serde_json::Value, args: &serde_json::Value, results: &[serde_json::Value]) -> serde_json::Value {
+    match template {
+        serde_json::Value::String(text) => {
+            let (root, path) = if let Some(path) = text.strip_prefix("$args.") {
+                (args, path)
+            } else if let Some(rest) = text.strip_prefix("$steps.") {
+                let (index, path) = rest.split_once('.').unwrap_or((rest, ""));
+                match index.parse::<usize>().ok().and_then(|index| results.get(index)) {
+                    Some(result) => (result, path),
+                    None => return serde_json::Value::Null,
+                }
+            } else {
+                return template.clone();
+            };
+            lookup(root, path).cloned().unwrap_or_default()
+        }
+        serde_json::Value::Array(items) => items.iter()
+            .map(|item| resolve_args(item, args, results))
+            .collect(),
+        serde_json::Value::Object(fields) => fields.iter()
+            .map(|(key, value)| (key.clone(), resolve_args(value, args, results)))
+            .collect(),
+        other => other.clone(),
+    }
+}
+
+/// The value at a dotted `path` of object keys and array indices; an empty path is `value` itself.
+fn lookup<'a>(value: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.')
+        .filter(|segment| !segment.is_empty())
+        .try_fold(value, |value, segment| match value {
+            serde_json::Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+            _ => value.get(segment),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_args() {
+        let template = serde_json::json!({
+            "file_path": "$args.target",
+            "content": "$steps.1.lines.0",
+            "whole": "$steps.0",
+            "literal": "kept as is",
+            "missing": "$steps.5.content"
+        });
+        let args = serde_json::json!({"target": "out.txt"});
+        let results = vec![serde_json::json!({"status": "success"}), serde_json::json!({"lines": ["first", "second"]})];
+
+        assert_eq!(resolve_args(&template, &args, &results), serde_json::json!({
+            "file_path": "out.txt",
+            "content": "first",
+            "whole": {"status": "success"},
+            "literal": "kept as is",
+            "missing": null
+        }));
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    collections::HashMap,
+    env, fmt,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::tool_box::{file_watcher::FileChanges, ignore::IgnoreRules, pending_edits::PendingEdits};
+
+/// Directory, relative to the workspace root, holding per-session scratchpads.
+pub const SCRATCHPAD_DIR: &str = ".codr_scratchpad";
+
+/// Directory, relative to the workspace root, reserved for backups of edited files.
+pub const BACKUP_DIR: &str = ".codr_backup";
+
+/// Directory, relative to the workspace root, holding workspace snapshots.
+pub const SNAPSHOT_DIR: &str = ".codr_snapshots";
+
+/// Directories holding Codr's own state, which file tools must never modify.
+const RESERVED_DIRS: &[&str] = &[BACKUP_DIR, SCRATCHPAD_DIR, SNAPSHOT_DIR];
+
+/// Refuses paths inside Codr's backup, scratchpad or snapshot directories, so the model
+/// can't be talked into corrupting them through the regular file tools.
+pub fn ensure_not_reserved(path: impl AsRef<Path>) -> Result<(), String> {
+    let reserved = path.as_ref().components().find_map(|component| {
+        RESERVED_DIRS.iter().find(|dir| component.as_os_str() == **dir)
+    });
+
+    match reserved {
+        Some(dir) => Err(format!("{} is inside {}, which is reserved for Codr's own state", path.as_ref().display(), dir)),
+        None => Ok(()),
+    }
+}
+
+/// Interpreters `run_file` uses out of the box, keyed by file extension.
+pub fn default_interpreters() -> HashMap<String, String> {
+    [("py", "python3"), ("js", "node"), ("sh", "sh")].into_iter()
+        .map(|(extension, command)| (extension.to_string(), command.to_string()))
+        .collect()
+}
+
+/// Asks the user a question on the model's behalf and returns their answer.
+pub type UserInputHandler = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Asks the user to approve a destructive action, described in plain words.
+pub type ConfirmHandler = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// State shared by every tool in a `ToolBox`.
+#[derive(Clone)]
+pub struct ToolContext {
+    /// Workspace the tools operate in.
+    pub root: PathBuf,
+    /// Identifies the current session, e.g. to scope its scratchpad.
+    pub session_id: String,
+    /// Answers `ask_user` questions. Without one, the model is told no user is available.
+    pub user_input: Option<UserInputHandler>,
+    /// Approves destructive actions such as deleting folders. Without one they proceed.
+    pub confirm: Option<ConfirmHandler>,
+    /// Makes edit tools propose a diff instead of writing; `apply_pending_edit` writes it.
+    pub preview_edits: bool,
+    pub pending_edits: PendingEdits,
+    /// Files changed during the session, reported by `get_file_changes` once watched.
+    pub file_changes: FileChanges,
+    /// Commands `run_file` runs scripts with, keyed by file extension. May include arguments.
+    pub interpreters: HashMap<String, String>,
+}
+
+impl fmt::Debug for ToolContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ToolContext")
+            .field("root", &self.root)
+            .field("session_id", &self.session_id)
+            .field("user_input", &self.user_input.is_some())
+            .field("confirm", &self.confirm.is_some())
+            .field("preview_edits", &self.preview_edits)
+            .field("pending_edits", &self.pending_edits)
+            .field("file_changes", &self.file_changes)
+            .field("interpreters", &self.interpreters)
+            .finish()
+    }
+}
+
+impl Default for ToolContext {
+    fn default() -> Self {
+        Self::new(env::current_dir().unwrap_or_else(|_| PathBuf::from(".")))
+    }
+}
+
+impl ToolContext {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+
+        ToolContext {
+            root: root.into(),
+            session_id: format!("{}-{}", started, std::process::id()),
+            user_input: None,
+            confirm: None,
+            preview_edits: false,
+            pending_edits: PendingEdits::default(),
+            file_changes: FileChanges::default(),
+            interpreters: default_interpreters(),
+        }
+    }
+
+    /// Whether the user approves `action`. Always true when no handler is set.
+    pub fn confirm(&self, action: &str) -> bool {
+        self.confirm.as_ref().is_none_or(|confirm| confirm(action))
+    }
+
+    pub fn scratchpad_path(&self) -> PathBuf {
+        self.root.join(SCRATCHPAD_DIR).join(format!("{}.md", self.session_id))
+    }
+
+    /// Resolves a path the model asked to read against the workspace root,
+    /// rejecting paths that escape the root.
+    pub fn resolve_read_path(&self, path: &str) -> Result<PathBuf, String> {
+        let root = self.absolute_root();
+        let resolved = normalize(&root.join(path));
+
+        match resolved.starts_with(&root) {
+            true => Ok(resolved),
+            false => Err(format!("{} is outside the workspace", path)),
+        }
+    }
+
+    /// Like [`Self::resolve_read_path`], but also rejects paths in Codr's own
+    /// directories or under the ignore rules.
+    pub fn resolve_write_path(&self, path: &str) -> Result<PathBuf, String> {
+        let root = self.absolute_root();
+        let resolved = self.resolve_read_path(path)?;
+
+        let relative = resolved.strip_prefix(&root).unwrap_or(&resolved);
+        ensure_not_reserved(relative)?;
+        if IgnoreRules::load(&root).is_ignored(relative) {
+            return Err(format!("{} is ignored", path));
+        }
+
+        Ok(resolved)
+    }
+
+    /// The root as an absolute path, so `..` can't walk past a relative one.
+    fn absolute_root(&self) -> PathBuf {
+        normalize(&std::path::absolute(&self.root).unwrap_or_else(|_| self.root.clone()))
+    }
+}
+
+/// Lexically resolves `.` and `..` so escapes are caught before the path exists.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_write_path() {
+        let ctx = ToolContext::new("/work/project");
+
+        assert_eq!(ctx.resolve_write_path("src/main.rs").unwrap(), PathBuf::from("/work/project/src/main.rs"));
+        assert_eq!(ctx.resolve_write_path("./a/../b.txt").unwrap(), PathBuf::from("/work/project/b.txt"));
+        assert_eq!(ctx.resolve_write_path("/work/project/c.txt").unwrap(), PathBuf::from("/work/project/c.txt"));
+        assert!(ctx.resolve_write_path("../other/file.txt").is_err());
+        assert!(ctx.resolve_write_path("/etc/passwd").is_err());
+        assert!(ctx.resolve_write_path(".git/config").is_err());
+    }
+
+    #[test]
+    fn test_resolve_read_path() {
+        let ctx = ToolContext::new("/work/project");
+
+        assert_eq!(ctx.resolve_read_path("src/main.rs").unwrap(), PathBuf::from("/work/project/src/main.rs"));
+        assert_eq!(ctx.resolve_read_path(".git/config").unwrap(), PathBuf::from("/work/project/.git/config"));
+        assert!(ctx.resolve_read_path("src/../../secret.txt").is_err());
+        assert!(ctx.resolve_read_path("/etc/passwd").is_err());
+
+        // A relative root can't be escaped either
+        let relative = ToolContext::new(".");
+        assert!(relative.resolve_read_path("../outside.txt").is_err());
+        assert!(relative.resolve_read_path("inside.txt").is_ok());
+    }
+
+    #[test]
+    fn test_reserved_dirs() {
+        assert!(ensure_not_reserved("src/main.rs").is_ok());
+        assert!(ensure_not_reserved(".codr_backup/src/main.rs").is_err());
+        assert!(ensure_not_reserved("/work/project/.codr_scratchpad/notes.md").is_err());
+
+        let error = ToolContext::new("/work/project").resolve_write_path("src/../.codr_backup/x.rs").unwrap_err();
+        assert!(error.contains("reserved"));
+    }
+}
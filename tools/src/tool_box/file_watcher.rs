@@ -0,0 +1,106 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::tool_box::ignore::IgnoreRules;
+
+/// Workspace files changed since they were last reported, collected in the
+/// background once [`FileChanges::watch`] is called. Clones share the same state.
+#[derive(Clone, Default)]
+pub struct FileChanges {
+    changed: Arc<Mutex<BTreeSet<PathBuf>>>,
+    watcher: Arc<Mutex<Option<RecommendedWatcher>>>,
+}
+
+impl fmt::Debug for FileChanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileChanges")
+            .field("watching", &self.is_watching())
+            .field("changed", &self.changed.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl FileChanges {
+    /// Starts recording changes to the non-ignored files under `root`.
+    pub fn watch(&self, root: &Path) -> notify::Result<()> {
+        let root = root.canonicalize()?;
+        let ignore = IgnoreRules::load(&root);
+        let changed = self.changed.clone();
+        let watched_root = root.clone();
+
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            if event.kind.is_access() {
+                return;
+            }
+
+            let mut changed = changed.lock().unwrap();
+            for path in event.paths {
+                if let Ok(relative) = path.strip_prefix(&watched_root)
+                    && !ignore.is_ignored(relative) {
+                    changed.insert(relative.to_path_buf());
+                }
+            }
+        })?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+
+        *self.watcher.lock().unwrap() = Some(watcher);
+        Ok(())
+    }
+
+    pub fn is_watching(&self) -> bool {
+        self.watcher.lock().unwrap().is_some()
+    }
+
+    /// Returns the changed paths, relative to the root, and starts over.
+    pub fn take(&self) -> Vec<PathBuf> {
+        std::mem::take(&mut *self.changed.lock().unwrap()).into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, thread, time::{Duration, Instant}};
+
+    /// Waits for the watcher to report `path`, collecting everything reported meanwhile.
+    fn wait_for(changes: &FileChanges, path: &str) -> Vec<PathBuf> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut seen = Vec::new();
+        while Instant::now() < deadline {
+            seen.extend(changes.take());
+            if seen.iter().any(|seen| seen == Path::new(path)) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        seen
+    }
+
+    #[test]
+    fn test_reports_modified_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+
+        let changes = FileChanges::default();
+        assert!(!changes.is_watching());
+        changes.watch(dir.path()).unwrap();
+
+        fs::create_dir_all(dir.path().join("target")).unwrap();
+        fs::write(dir.path().join("target/build.log"), "ignored").unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() { run(); }").unwrap();
+
+        let seen = wait_for(&changes, "src/main.rs");
+        assert!(seen.contains(&PathBuf::from("src/main.rs")));
+        assert!(!seen.iter().any(|path| path.starts_with("target")));
+    }
+}
@@ -0,0 +1,96 @@
+use std::{fs, path::Path};
+
+use glob::Pattern;
+
+use crate::tool_box::context::{BACKUP_DIR, SCRATCHPAD_DIR, SNAPSHOT_DIR};
+
+/// Name of the per-workspace file listing extra paths tools should skip.
+pub const IGNORE_FILE: &str = ".codrignore";
+
+/// Paths skipped even without an ignore file.
+const DEFAULT_IGNORES: &[&str] = &[".git", "target", "node_modules", BACKUP_DIR, SCRATCHPAD_DIR, SNAPSHOT_DIR];
+
+/// A small gitignore-like rule set. A pattern without a `/` matches any path
+/// component (`*.log`, `build`); a pattern with one matches the path relative
+/// to the workspace root (`docs/generated/*`). Blank lines and `#` comments
+/// are skipped.
+#[derive(Debug, Clone)]
+pub struct IgnoreRules {
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreRules {
+    /// Default rules plus those listed in `.codrignore` under `root`, if any.
+    pub fn load(root: &Path) -> Self {
+        Self::with_defaults(&fs::read_to_string(root.join(IGNORE_FILE)).unwrap_or_default())
+    }
+
+    /// Default rules plus those in `content`, the text of an ignore file.
+    pub fn with_defaults(content: &str) -> Self {
+        let mut rules = Self::new(DEFAULT_IGNORES);
+        rules.extend(content.lines());
+        rules
+    }
+
+    pub fn new<S: AsRef<str>>(patterns: impl IntoIterator<Item = S>) -> Self {
+        let mut rules = IgnoreRules { patterns: Vec::new() };
+        rules.extend(patterns);
+        rules
+    }
+
+    pub fn extend<S: AsRef<str>>(&mut self, patterns: impl IntoIterator<Item = S>) {
+        for pattern in patterns {
+            let pattern = pattern.as_ref().trim().trim_end_matches('/');
+            if pattern.is_empty() || pattern.starts_with('#') {
+                continue;
+            }
+            if let Ok(pattern) = Pattern::new(pattern.trim_start_matches('/')) {
+                self.patterns.push(pattern);
+            }
+        }
+    }
+
+    /// Whether `relative` (a path relative to the workspace root) is ignored.
+    pub fn is_ignored(&self, relative: &Path) -> bool {
+        let relative_str = relative.to_string_lossy().replace('\\', "/");
+
+        self.patterns.iter().any(|pattern| {
+            if pattern.as_str().contains('/') {
+                pattern.matches(&relative_str)
+            } else {
+                relative.components().any(|component| {
+                    pattern.matches(&component.as_os_str().to_string_lossy())
+                })
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_component_and_path_patterns() {
+        let rules = IgnoreRules::new(["*.log", "docs/generated", "# comment", ""]);
+
+        assert!(rules.is_ignored(Path::new("server.log")));
+        assert!(rules.is_ignored(Path::new("logs/today.log")));
+        assert!(rules.is_ignored(Path::new("docs/generated")));
+        assert!(!rules.is_ignored(Path::new("docs/guide.md")));
+        assert!(!rules.is_ignored(Path::new("src/main.rs")));
+    }
+
+    #[test]
+    fn test_load_defaults_and_ignore_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(IGNORE_FILE), "secrets/\n").unwrap();
+
+        let rules = IgnoreRules::load(dir.path());
+
+        assert!(rules.is_ignored(Path::new("target/debug")));
+        assert!(rules.is_ignored(Path::new(".git")));
+        assert!(rules.is_ignored(Path::new("secrets")));
+        assert!(!rules.is_ignored(Path::new("src")));
+    }
+}
@@ -0,0 +1,44 @@
+use std::{fmt, sync::Arc};
+
+/// Sees a tool's name together with its arguments before it runs, or with
+/// its result after, and may change them.
+pub type ToolHook = Arc<dyn Fn(&str, &mut serde_json::Value) + Send + Sync>;
+
+/// Hooks run around every tool call, e.g. to rewrite paths, redact results
+/// or collect metrics. Each kind runs in the order it was added.
+#[derive(Clone, Default)]
+pub struct ToolMiddleware {
+    before: Vec<ToolHook>,
+    after: Vec<ToolHook>,
+}
+
+impl fmt::Debug for ToolMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ToolMiddleware")
+            .field("before", &self.before.len())
+            .field("after", &self.after.len())
+            .finish()
+    }
+}
+
+impl ToolMiddleware {
+    pub fn add_before(&mut self, hook: ToolHook) {
+        self.before.push(hook);
+    }
+
+    pub fn add_after(&mut self, hook: ToolHook) {
+        self.after.push(hook);
+    }
+
+    pub fn run_before(&self, name: &str, args: &mut serde_json::Value) {
+        for hook in &self.before {
+            hook(name, args);
+        }
+    }
+
+    pub fn run_after(&self, name: &str, result: &mut serde_json::Value) {
+        for hook in &self.after {
+            hook(name, result);
+        }
+    }
+}
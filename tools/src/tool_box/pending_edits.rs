@@ -0,0 +1,72 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use similar::TextDiff;
+
+/// A file write proposed while edit previews are on, held until it is applied.
+#[derive(Debug, Clone)]
+pub struct PendingEdit {
+    pub path: PathBuf,
+    /// Content of the file when the edit was proposed, `None` if it didn't exist.
+    pub original: Option<String>,
+    pub content: String,
+    /// Unified diff from `original` to `content`.
+    pub diff: String,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: usize,
+    edits: BTreeMap<usize, PendingEdit>,
+}
+
+/// Edits waiting for `apply_pending_edit`. Clones share the same edits.
+#[derive(Debug, Clone, Default)]
+pub struct PendingEdits {
+    state: Arc<Mutex<State>>,
+}
+
+impl PendingEdits {
+    /// Stores a write of `content` to `path` without touching the file, and
+    /// returns the id to apply it with together with the diff it would make.
+    pub fn propose(&self, path: &Path, content: &str) -> io::Result<(String, String)> {
+        let original = match fs::read_to_string(path) {
+            Ok(original) => Some(original),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+
+        let name = path.display().to_string();
+        let diff = TextDiff::from_lines(original.as_deref().unwrap_or_default(), content)
+            .unified_diff()
+            .header(&name, &name)
+            .to_string();
+
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let number = state.next_id;
+        state.edits.insert(number, PendingEdit {
+            path: path.to_path_buf(),
+            original,
+            content: content.to_string(),
+            diff: diff.clone(),
+        });
+
+        Ok((format!("edit_{}", number), diff))
+    }
+
+    /// Removes and returns the edit with `id`.
+    pub fn take(&self, id: &str) -> Option<PendingEdit> {
+        let number = id.strip_prefix("edit_")?.parse().ok()?;
+        self.state.lock().unwrap().edits.remove(&number)
+    }
+
+    /// Ids of the edits not applied yet, oldest first.
+    pub fn ids(&self) -> Vec<String> {
+        self.state.lock().unwrap().edits.keys().map(|number| format!("edit_{}", number)).collect()
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::tool_box::{context::SNAPSHOT_DIR, ignore::{IGNORE_FILE, IgnoreRules}};
+
+/// Snapshots kept per workspace. Taking another removes the oldest.
+pub const MAX_SNAPSHOTS: usize = 5;
+
+/// Copies every non-ignored file under `root` into a new snapshot directory
+/// named after the current time, and returns its id with the number of files
+/// copied. The ignore rules in effect are kept alongside, see [`restore`].
+/// Only the newest [`MAX_SNAPSHOTS`] snapshots are kept.
+pub fn create(root: &Path) -> io::Result<(String, usize)> {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let dir = root.join(SNAPSHOT_DIR);
+    // Ids sort by age, so a new one must come after the newest, even within the same millisecond
+    let newest = list(root)?.last().and_then(|id| id.strip_prefix("snapshot_")?.parse::<u128>().ok());
+    let id = snapshot_id(newest.map_or(millis, |newest| millis.max(newest + 1)));
+
+    let ignore_file = fs::read_to_string(root.join(IGNORE_FILE)).unwrap_or_default();
+    let files = relative_files(root, root, &IgnoreRules::with_defaults(&ignore_file))?;
+    fs::create_dir_all(dir.join(&id))?;
+    fs::write(ignore_record(root, &id), ignore_file)?;
+    for file in &files {
+        let target = dir.join(&id).join(file);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(root.join(file), target)?;
+    }
+
+    let snapshots = list(root)?;
+    for old in &snapshots[..snapshots.len().saturating_sub(MAX_SNAPSHOTS)] {
+        fs::remove_dir_all(dir.join(old))?;
+        if let Err(e) = fs::remove_file(ignore_record(root, old)) && e.kind() != io::ErrorKind::NotFound {
+            return Err(e);
+        }
+    }
+
+    Ok((id, files.len()))
+}
+
+/// Puts the workspace back the way it was when snapshot `id` was taken: files
+/// are restored and files created since are removed. Files ignored either now
+/// or when the snapshot was taken are left alone, and so are all files when
+/// the snapshot has no record of its ignore rules. Returns the number of files
+/// restored and removed.
+pub fn restore(root: &Path, id: &str) -> io::Result<(usize, usize)> {
+    let snapshot = root.join(SNAPSHOT_DIR).join(id);
+    if !list(root)?.iter().any(|existing| existing == id) {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("No snapshot {}", id)));
+    }
+
+    let saved = relative_files(&snapshot, &snapshot, &IgnoreRules::new::<&str>([]))?;
+    let mut removed = 0;
+    let eligible = match fs::read_to_string(ignore_record(root, id)) {
+        Ok(ignore_file) => relative_files(root, root, &IgnoreRules::with_defaults(&ignore_file))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let ignore = IgnoreRules::load(root);
+    for file in eligible {
+        if !saved.contains(&file) && !ignore.is_ignored(&file) {
+            fs::remove_file(root.join(file))?;
+            removed += 1;
+        }
+    }
+    for file in &saved {
+        let target = root.join(file);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(snapshot.join(file), target)?;
+    }
+
+    Ok((saved.len(), removed))
+}
+
+/// Ids of the snapshots of `root`, oldest first.
+pub fn list(root: &Path) -> io::Result<Vec<String>> {
+    let mut ids: Vec<String> = match fs::read_dir(root.join(SNAPSHOT_DIR)) {
+        Ok(entries) => entries.flatten()
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| name.starts_with("snapshot_"))
+            .collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    ids.sort();
+    Ok(ids)
+}
+
+/// Zero-padded so that ids sort in the order they were taken.
+fn snapshot_id(number: u128) -> String {
+    format!("snapshot_{:016}", number)
+}
+
+/// Where the ignore file in effect when snapshot `id` was taken is kept, next
+/// to the snapshot so it can't clash with a saved file.
+fn ignore_record(root: &Path, id: &str) -> PathBuf {
+    root.join(SNAPSHOT_DIR).join(format!("{}{}", id, IGNORE_FILE))
+}
+
+/// Paths, relative to `root`, of the files under `dir` not covered by `ignore`.
+fn relative_files(root: &Path, dir: &Path, ignore: &IgnoreRules) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        if ignore.is_ignored(&relative) {
+            continue;
+        }
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            files.extend(relative_files(root, &path, ignore)?);
+        } else if file_type.is_file() {
+            files.push(relative);
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retention_bounded() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("main.rs"), "fn main() {}").unwrap();
+
+        let ids: Vec<String> = (0..MAX_SNAPSHOTS + 2).map(|_| create(dir.path()).unwrap().0).collect();
+
+        assert_eq!(list(dir.path()).unwrap(), ids[2..]);
+        assert!(!ignore_record(dir.path(), &ids[0]).exists());
+    }
+
+    #[test]
+    fn test_restore_removes_new_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("main.rs"), "fn main() {}").unwrap();
+        let (id, _) = create(dir.path()).unwrap();
+
+        fs::write(dir.path().join("main.rs"), "changed").unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/new.rs"), "new").unwrap();
+
+        assert_eq!(restore(dir.path(), &id).unwrap(), (1, 1));
+        assert_eq!(fs::read_to_string(dir.path().join("main.rs")).unwrap(), "fn main() {}");
+        assert!(!dir.path().join("src/new.rs").exists());
+    }
+
+    #[test]
+    fn test_restore_keeps_files_ignored_at_snapshot_time() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(IGNORE_FILE), "*.log\n").unwrap();
+        fs::write(dir.path().join("server.log"), "precious").unwrap();
+        let (id, files) = create(dir.path()).unwrap();
+        assert_eq!(files, 1);
+
+        fs::remove_file(dir.path().join(IGNORE_FILE)).unwrap();
+        fs::write(dir.path().join("notes.txt"), "new").unwrap();
+
+        assert_eq!(restore(dir.path(), &id).unwrap(), (1, 1));
+        assert_eq!(fs::read_to_string(dir.path().join("server.log")).unwrap(), "precious");
+        assert!(!dir.path().join("notes.txt").exists());
+        assert!(dir.path().join(IGNORE_FILE).exists());
+    }
+}
@@ -1,16 +1,88 @@
+pub mod command_tools;
 pub mod file_tools;
+pub mod project_tools;
+pub mod scratchpad_tools;
+pub mod snapshot_tools;
+pub mod user_tools;
+pub mod watch_tools;
+
+use std::{fmt, future::Future, pin::Pin};
 
 use openai::Tool as OpenAITool;
 
+use crate::tool_box::context::ToolContext;
+
+pub type ToolResult = Result<serde_json::Value, Box<dyn std::error::Error>>;
+
+/// What [`ToolHandler::run`] returns; implementations typically wrap an
+/// `async move` block in `Box::pin`.
+pub type ToolFuture<'a> = Pin<Box<dyn Future<Output = ToolResult> + Send + 'a>>;
+
+/// A tool implemented by your own type, for tools that keep state or need to
+/// await something, which a [`Tool`] with a plain function can't. Register it
+/// with [`crate::ToolBox::register_handler`].
+pub trait ToolHandler: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn description(&self) -> &str;
+
+    /// JSON Schema of the arguments, checked like [`Tool::validate`] on registration.
+    fn parameters(&self) -> serde_json::Value;
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext, args: serde_json::Value) -> ToolFuture<'a>;
+}
+
+impl fmt::Debug for dyn ToolHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ToolHandler").field("name", &self.name()).finish()
+    }
+}
+
+/// The definition of `handler` as offered to the model.
+pub fn handler_to_openai_tool(handler: &dyn ToolHandler) -> OpenAITool {
+    OpenAITool {
+        tool_type: "function".to_string(),
+        function: openai::Function {
+            name: handler.name().to_string(),
+            description: handler.description().to_string(),
+            parameters: handler.parameters(),
+        },
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Tool {
     name: String,
     description: String,
     parameters: serde_json::Value,
-    runner: fn(serde_json::Value) -> Result<serde_json::Value, Box<dyn std::error::Error>>,
+    runner: fn(&ToolContext, serde_json::Value) -> ToolResult,
 }
 
 impl Tool {
+    /// Builds a tool, failing if its parameters schema is broken, see [`Tool::validate`].
+    pub fn new(
+        name: &str,
+        description: &str,
+        parameters: serde_json::Value,
+        runner: fn(&ToolContext, serde_json::Value) -> ToolResult,
+    ) -> Result<Tool, Box<dyn std::error::Error>> {
+        let tool = Tool {
+            name: name.to_string(),
+            description: description.to_string(),
+            parameters,
+            runner,
+        };
+        tool.validate()?;
+        Ok(tool)
+    }
+
+    /// Checks that `parameters` is a JSON Schema object with `"type": "object"`
+    /// and a `properties` map, the shape the API expects. Catches broken
+    /// definitions before they turn into a rejected request.
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        validate_parameters(&self.name, &self.parameters)
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -19,12 +91,17 @@ impl Tool {
         &self.description
     }
 
+    /// Replaces the description offered to the model, e.g. with wording tuned for a particular model.
+    pub fn set_description(&mut self, description: String) {
+        self.description = description;
+    }
+
     pub fn parameters(&self) -> &serde_json::Value {
         &self.parameters
     }
 
-    pub fn run(&self, args: serde_json::Value) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
-        (self.runner)(args)
+    pub fn run(&self, ctx: &ToolContext, args: serde_json::Value) -> ToolResult {
+        (self.runner)(ctx, args)
     }
 
     pub fn to_openai_tool(&self) -> OpenAITool {
@@ -38,3 +115,102 @@ impl Tool {
         }
     }
 }
+
+/// Built-in tools do blocking file and process I/O, so they run on a blocking
+/// thread rather than on the runtime awaiting them.
+impl ToolHandler for Tool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        self.parameters.clone()
+    }
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext, args: serde_json::Value) -> ToolFuture<'a> {
+        let (tool, ctx) = (self.clone(), ctx.clone());
+        Box::pin(async move {
+            let result = tokio::task::spawn_blocking(move || Tool::run(&tool, &ctx, args).map_err(|e| e.to_string())).await?;
+            Ok(result?)
+        })
+    }
+}
+
+/// Checks the parameters schema of tool `name`, see [`Tool::validate`].
+pub fn validate_parameters(name: &str, parameters: &serde_json::Value) -> Result<(), Box<dyn std::error::Error>> {
+    let invalid = |problem: &str| format!("Invalid parameters for tool {}: {}", name, problem);
+
+    let schema = parameters.as_object()
+        .ok_or_else(|| invalid("the schema must be a JSON object"))?;
+    match schema.get("type") {
+        Some(serde_json::Value::String(schema_type)) if schema_type == "object" => {}
+        Some(other) => return Err(invalid(&format!("`type` must be \"object\", got {}", other)).into()),
+        None => return Err(invalid("missing `type`").into()),
+    }
+
+    let properties = schema.get("properties")
+        .ok_or_else(|| invalid("missing `properties`"))?
+        .as_object()
+        .ok_or_else(|| invalid("`properties` must be an object"))?;
+    for (name, property) in properties {
+        if !property.is_object() {
+            return Err(invalid(&format!("property `{}` must be a schema object", name)).into());
+        }
+    }
+
+    if let Some(required) = schema.get("required") {
+        let required = required.as_array().ok_or_else(|| invalid("`required` must be an array"))?;
+        for name in required {
+            let name = name.as_str().ok_or_else(|| invalid("`required` must list property names"))?;
+            if !properties.contains_key(name) {
+                return Err(invalid(&format!("required property `{}` is not in `properties`", name)).into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noop(_ctx: &ToolContext, _args: serde_json::Value) -> ToolResult {
+        Ok(serde_json::Value::Null)
+    }
+
+    #[test]
+    fn test_valid_schema() {
+        let tool = Tool::new("greet", "Greets someone", serde_json::json!({
+            "type": "object",
+            "properties": {"name": {"type": "string"}},
+            "required": ["name"]
+        }), noop);
+
+        assert!(tool.is_ok());
+    }
+
+    #[test]
+    fn test_schema_missing_type() {
+        let error = Tool::new("greet", "Greets someone", serde_json::json!({
+            "properties": {"name": {"type": "string"}}
+        }), noop).unwrap_err();
+
+        assert_eq!(error.to_string(), "Invalid parameters for tool greet: missing `type`");
+    }
+
+    #[test]
+    fn test_schema_required_unknown_property() {
+        let error = Tool::new("greet", "Greets someone", serde_json::json!({
+            "type": "object",
+            "properties": {},
+            "required": ["name"]
+        }), noop).unwrap_err();
+
+        assert!(error.to_string().contains("required property `name`"));
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    io::{self, Read},
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::tool_box::{tools::Tool, err};
+
+const DEFAULT_RUN_TIMEOUT_SECS: u64 = 30;
+
+/// Most output kept per stream, so a chatty script can't flood the context.
+const MAX_OUTPUT_BYTES: usize = 64 * 1024;
+
+/// How long output is still collected once the script has ended. Background
+/// processes it started may hold the pipes open for much longer.
+const OUTPUT_GRACE: Duration = Duration::from_millis(500);
+
+pub fn new_run_file_tool() -> Tool {
+    Tool {
+        name: "run_file".to_string(),
+        description: "Runs a script from the project with the interpreter for its extension (e.g. .py with python3, .js with node, .sh with sh) and returns its exit code, stdout and stderr. Use it to test scripts you wrote. The user may be asked to approve the run".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "file_path": {
+                    "type": "string",
+                    "description": "Path to the script to run"
+                },
+                "args": {
+                    "type": "array",
+                    "items": {"type": "string"},
+                    "description": "Arguments passed to the script"
+                },
+                "timeout_seconds": {
+                    "type": "integer",
+                    "description": "Seconds after which the script is stopped (default: 30)",
+                    "default": DEFAULT_RUN_TIMEOUT_SECS
+                }
+            },
+            "required": ["file_path"]
+        }),
+        runner: |ctx, args| {
+            let file_path = args["file_path"].as_str().ok_or("file_path is required")?;
+            let script_args: Vec<&str> = args["args"].as_array()
+                .map(|args| args.iter().filter_map(|arg| arg.as_str()).collect())
+                .unwrap_or_default();
+            let timeout = Duration::from_secs(args["timeout_seconds"].as_u64().unwrap_or(DEFAULT_RUN_TIMEOUT_SECS));
+
+            let path = match ctx.resolve_write_path(file_path) {
+                Ok(path) => path,
+                Err(message) => return err(&message),
+            };
+            if !path.is_file() {
+                return err(&format!("{} is not a file", file_path));
+            }
+
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+            let Some(interpreter) = ctx.interpreters.get(extension) else {
+                let mut known: Vec<&str> = ctx.interpreters.keys().map(String::as_str).collect();
+                known.sort();
+                return err(&format!("No interpreter configured for .{} files, known: {}", extension, known.join(", ")));
+            };
+            let mut interpreter = interpreter.split_whitespace();
+            let program = interpreter.next().ok_or("Interpreter command is empty")?;
+
+            if !ctx.confirm(&format!("Run {} with {}?", file_path, program)) {
+                return err("The user declined running the file");
+            }
+
+            let mut command = Command::new(program);
+            command
+                .args(interpreter)
+                .arg(&path)
+                .args(&script_args)
+                .current_dir(&ctx.root)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+            // In a group of its own, so a timeout stops whatever the script started too
+            #[cfg(unix)]
+            std::os::unix::process::CommandExt::process_group(&mut command, 0);
+            let mut child = command.spawn()?;
+
+            let stdout = read_in_background(child.stdout.take());
+            let stderr = read_in_background(child.stderr.take());
+
+            let started = Instant::now();
+            let status = loop {
+                if let Some(status) = child.try_wait()? {
+                    break Some(status);
+                }
+                if started.elapsed() >= timeout {
+                    kill(&mut child)?;
+                    child.wait()?;
+                    break None;
+                }
+                thread::sleep(Duration::from_millis(10));
+            };
+
+            let deadline = Instant::now() + OUTPUT_GRACE;
+            let stdout = stdout.collect(deadline);
+            let stderr = stderr.collect(deadline);
+            Ok(serde_json::json!({
+                "status": if status.is_some_and(|status| status.success()) { "success" } else { "error" },
+                "exit_code": status.and_then(|status| status.code()),
+                "timed_out": status.is_none(),
+                "stdout": stdout,
+                "stderr": stderr
+            }))
+        },
+    }
+}
+
+/// Kills the child along with every process in its group.
+#[cfg(unix)]
+fn kill(child: &mut Child) -> io::Result<()> {
+    // The child leads its group, so the group id is its pid
+    if unsafe { libc::killpg(child.id() as libc::pid_t, libc::SIGKILL) } == 0 {
+        return Ok(());
+    }
+    child.kill()
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) -> io::Result<()> {
+    child.kill()
+}
+
+/// Output of a pipe, collected on its own thread.
+struct PipeOutput {
+    output: Arc<Mutex<Vec<u8>>>,
+    done: mpsc::Receiver<()>,
+}
+
+impl PipeOutput {
+    /// What was collected once the pipe closed, or by `deadline` if it is still open.
+    fn collect(self, deadline: Instant) -> String {
+        let _ = self.done.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+        String::from_utf8_lossy(&self.output.lock().unwrap()).to_string()
+    }
+}
+
+/// Collects a pipe on its own thread, so a full pipe can't block the child.
+/// Output past [`MAX_OUTPUT_BYTES`] is read and dropped rather than left
+/// unread, which would kill the child with a broken pipe.
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> PipeOutput {
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let (finished, done) = mpsc::channel();
+
+    let collected = output.clone();
+    thread::spawn(move || {
+        if let Some(mut pipe) = pipe {
+            let mut buffer = [0; 8192];
+            loop {
+                let read = match pipe.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => read,
+                };
+                let mut output = collected.lock().unwrap();
+                let keep = read.min(MAX_OUTPUT_BYTES - output.len());
+                output.extend_from_slice(&buffer[..keep]);
+            }
+        }
+        let _ = finished.send(());
+    });
+
+    PipeOutput { output, done }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::tool_box::context::ToolContext;
+
+    #[test]
+    fn test_run_file_captures_output() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("hello.sh"), "echo \"hello $1\"\necho oops >&2\nexit 3\n").unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_run_file_tool().run(&ctx, serde_json::json!({
+            "file_path": "hello.sh",
+            "args": ["codr"]
+        })).unwrap();
+
+        assert_eq!(result["stdout"], "hello codr\n");
+        assert_eq!(result["stderr"], "oops\n");
+        assert_eq!(result["exit_code"], 3);
+        assert_eq!(result["status"], "error");
+    }
+
+    #[test]
+    fn test_run_file_drains_long_output() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("chatty.sh"), "head -c 1000000 /dev/zero | tr '\\0' a || exit 1\necho done >&2\n").unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_run_file_tool().run(&ctx, serde_json::json!({"file_path": "chatty.sh"})).unwrap();
+
+        assert_eq!(result["status"], "success");
+        assert_eq!(result["stdout"].as_str().unwrap().len(), MAX_OUTPUT_BYTES);
+        assert_eq!(result["stderr"], "done\n");
+    }
+
+    #[test]
+    fn test_run_file_returns_despite_background_process() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("server.sh"), "sleep 5 &\necho started\n").unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let started = Instant::now();
+        let result = new_run_file_tool().run(&ctx, serde_json::json!({"file_path": "server.sh"})).unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(result["status"], "success");
+        assert_eq!(result["stdout"], "started\n");
+    }
+
+    #[test]
+    fn test_run_file_guards() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("slow.sh"), "sleep 5\n").unwrap();
+        fs::write(dir.path().join("data.bin"), "").unwrap();
+        let mut ctx = ToolContext::new(dir.path());
+
+        let started = Instant::now();
+        let result = new_run_file_tool().run(&ctx, serde_json::json!({"file_path": "slow.sh", "timeout_seconds": 0})).unwrap();
+        assert_eq!(result["timed_out"], true);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let result = new_run_file_tool().run(&ctx, serde_json::json!({"file_path": "data.bin"})).unwrap();
+        assert!(result["message"].as_str().unwrap().contains("No interpreter"));
+
+        ctx.confirm = Some(std::sync::Arc::new(|_: &str| false));
+        let result = new_run_file_tool().run(&ctx, serde_json::json!({"file_path": "slow.sh"})).unwrap();
+        assert_eq!(result["status"], "error");
+        assert!(result["message"].as_str().unwrap().contains("declined"));
+    }
+}
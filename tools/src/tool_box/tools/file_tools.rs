@@ -1,10 +1,12 @@
-use std::{fs, io::Write};
-use crate::tool_box::{tools::Tool, status_success};
+use std::{fs, io::{self, BufRead, Read, Seek, SeekFrom, Write}, path::Path};
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+use crate::tool_box::{context::ToolContext, tools::{Tool, ToolResult}, status_success, err};
 
 pub fn new_write_file_tool() -> Tool {
     Tool {
         name: "write_file".to_string(),
-        description: "Writes content to a file. If trying to write a file and the folder does not exist, use create_folder tool to create a folder first".to_string(),
+        description: "Writes content to a file. Missing parent folders are created unless create_dirs is false".to_string(),
         parameters: serde_json::json!({
             "type": "object",
             "properties": {
@@ -15,15 +17,154 @@ pub fn new_write_file_tool() -> Tool {
                 "content": {
                     "type": "string",
                     "description": "Content to write to the file"
+                },
+                "create_dirs": {
+                    "type": "boolean",
+                    "description": "Create missing parent folders first. Defaults to true; with false, writing into a missing folder fails"
                 }
             },
             "required": ["file_path", "content"]
         }),
-        runner: |args| {
+        runner: |ctx, args| {
             let file_path = args["file_path"].as_str().ok_or("file_path is required")?;
             let content = args["content"].as_str().ok_or("content is required")?;
+            let create_dirs = args["create_dirs"].as_bool().unwrap_or(true);
+
+            let path = match ctx.resolve_write_path(file_path) {
+                Ok(path) => path,
+                Err(message) => return err(&message),
+            };
+            // Previews create folders once the edit is applied
+            if create_dirs && !ctx.preview_edits
+                && let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            write_or_propose(ctx, &path, content)
+        },
+    }
+}
+
+pub fn new_write_files_tool() -> Tool {
+    Tool {
+        name: "write_files".to_string(),
+        description: "Writes several files in one call, creating parent folders as needed. Use this to scaffold a project structure. Reports success or failure for each file".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "files": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "file_path": {
+                                "type": "string",
+                                "description": "Path to the file to write"
+                            },
+                            "content": {
+                                "type": "string",
+                                "description": "Content to write to the file"
+                            }
+                        },
+                        "required": ["file_path", "content"]
+                    },
+                    "description": "Files to write"
+                }
+            },
+            "required": ["files"]
+        }),
+        runner: |ctx, args| {
+            let files = args["files"].as_array().ok_or("files is required")?;
 
-            fs::write(file_path, content)?;
+            let results: Vec<_> = files.iter().map(|file| {
+                let file_path = file["file_path"].as_str().unwrap_or_default();
+                match write_one(ctx, file) {
+                    Ok(mut result) => {
+                        result["file_path"] = file_path.into();
+                        result
+                    }
+                    Err(message) => serde_json::json!({"file_path": file_path, "status": "error", "message": message}),
+                }
+            }).collect();
+
+            let failed = results.iter().filter(|result| result["status"] == "error").count();
+            let done = if ctx.preview_edits { "proposed" } else { "written" };
+            Ok(serde_json::json!({
+                "status": if failed == 0 { "success" } else { "error" },
+                "message": format!("{} of {} files {}", files.len() - failed, files.len(), done),
+                "files": results
+            }))
+        },
+    }
+}
+
+fn write_one(ctx: &ToolContext, file: &serde_json::Value) -> Result<serde_json::Value, String> {
+    let file_path = file["file_path"].as_str().ok_or("file_path is required")?;
+    let content = file["content"].as_str().ok_or("content is required")?;
+
+    let path = ctx.resolve_write_path(file_path)?;
+    if ctx.preview_edits {
+        return propose_edit(ctx, &path, content).map_err(|e| e.to_string());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    fs::write(&path, content).map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({"status": "success"}))
+}
+
+/// Writes `content` to `path`, or only proposes the write when edit previews are on.
+fn write_or_propose(ctx: &ToolContext, path: &Path, content: &str) -> ToolResult {
+    if ctx.preview_edits {
+        return Ok(propose_edit(ctx, path, content)?);
+    }
+    fs::write(path, content)?;
+    status_success()
+}
+
+fn propose_edit(ctx: &ToolContext, path: &Path, content: &str) -> Result<serde_json::Value, std::io::Error> {
+    let (edit_id, diff) = ctx.pending_edits.propose(path, content)?;
+    Ok(serde_json::json!({
+        "status": "pending",
+        "edit_id": edit_id,
+        "diff": diff,
+        "message": "Nothing was written yet; call apply_pending_edit with this edit_id to write it"
+    }))
+}
+
+pub fn new_apply_pending_edit_tool() -> Tool {
+    Tool {
+        name: "apply_pending_edit".to_string(),
+        description: "Writes an edit that an edit tool proposed as a diff instead of writing. The user may be asked to approve the diff first".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "edit_id": {
+                    "type": "string",
+                    "description": "edit_id returned with the proposed diff"
+                }
+            },
+            "required": ["edit_id"]
+        }),
+        runner: |ctx, args| {
+            let edit_id = args["edit_id"].as_str().ok_or("edit_id is required")?;
+
+            let Some(edit) = ctx.pending_edits.take(edit_id) else {
+                return err(&format!("No pending edit with id {}", edit_id));
+            };
+
+            let current = fs::read_to_string(&edit.path).ok();
+            if current != edit.original {
+                return err(&format!("Conflict: {} changed since the edit was proposed; propose it again", edit.path.display()));
+            }
+
+            if !ctx.confirm(&format!("Apply this edit to {}?\n{}", edit.path.display(), edit.diff)) {
+                return err("The user rejected the edit");
+            }
+
+            if let Some(parent) = edit.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&edit.path, &edit.content)?;
             status_success()
         },
     }
@@ -47,12 +188,119 @@ pub fn new_replace_file_tool() -> Tool {
             },
             "required": ["file_path", "content"]
         }),
-        runner: |args| {
+        runner: |ctx, args| {
             let file_path = args["file_path"].as_str().ok_or("file_path is required")?;
             let content = args["content"].as_str().ok_or("content is required")?;
 
-            fs::write(file_path, content)?;
-            status_success()
+            let path = match ctx.resolve_write_path(file_path) {
+                Ok(path) => path,
+                Err(message) => return err(&message),
+            };
+            write_or_propose(ctx, &path, content)
+        },
+    }
+}
+
+pub fn new_conditional_write_tool() -> Tool {
+    Tool {
+        name: "conditional_write_file".to_string(),
+        description: "Replaces content of a file only if its current content still matches what you expect, either verbatim or by SHA-256 hash. Use this to avoid overwriting changes made since you last read the file".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "file_path": {
+                    "type": "string",
+                    "description": "Path to the file to write"
+                },
+                "expected_current_content": {
+                    "type": "string",
+                    "description": "Content the file is expected to have right now"
+                },
+                "expected_current_hash": {
+                    "type": "string",
+                    "description": "Hex-encoded SHA-256 hash of the content the file is expected to have right now, as an alternative to expected_current_content"
+                },
+                "new_content": {
+                    "type": "string",
+                    "description": "Content to write to the file"
+                }
+            },
+            "required": ["file_path", "new_content"]
+        }),
+        runner: |ctx, args| {
+            let file_path = args["file_path"].as_str().ok_or("file_path is required")?;
+            let new_content = args["new_content"].as_str().ok_or("new_content is required")?;
+
+            let path = match ctx.resolve_write_path(file_path) {
+                Ok(path) => path,
+                Err(message) => return err(&message),
+            };
+            let current = fs::read_to_string(&path)?;
+            let current_hash = sha256_hex(&current);
+
+            let matches = match (args["expected_current_content"].as_str(), args["expected_current_hash"].as_str()) {
+                (Some(expected), _) => expected == current,
+                (None, Some(expected_hash)) => expected_hash.eq_ignore_ascii_case(&current_hash),
+                (None, None) => return err("expected_current_content or expected_current_hash is required"),
+            };
+
+            if !matches {
+                return err(&format!(
+                    "Conflict: file content changed since it was last read (current hash {}); read it again before writing",
+                    current_hash
+                ));
+            }
+
+            write_or_propose(ctx, &path, new_content)
+        },
+    }
+}
+
+fn sha256_hex(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+pub fn new_json_patch_tool() -> Tool {
+    Tool {
+        name: "json_patch".to_string(),
+        description: "Edits a JSON file by applying a JSON Patch (RFC 6902), e.g. [{\"op\": \"replace\", \"path\": \"/version\", \"value\": \"2.0.0\"}], and writes it back pretty-printed. Safer than rewriting JSON as text. Either every operation applies or the file is left untouched".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "file_path": {
+                    "type": "string",
+                    "description": "Path to the JSON file to edit"
+                },
+                "patch": {
+                    "type": "array",
+                    "items": {"type": "object"},
+                    "description": "Operations to apply in order, each with an op (add, remove, replace, move, copy or test), a JSON Pointer path, and a value or from as the op requires"
+                }
+            },
+            "required": ["file_path", "patch"]
+        }),
+        runner: |ctx, args| {
+            let file_path = args["file_path"].as_str().ok_or("file_path is required")?;
+            let patch: json_patch::Patch = match serde_json::from_value(args["patch"].clone()) {
+                Ok(patch) => patch,
+                Err(e) => return err(&format!("Invalid JSON Patch: {}", e)),
+            };
+
+            let path = match ctx.resolve_write_path(file_path) {
+                Ok(path) => path,
+                Err(message) => return err(&message),
+            };
+            let mut document: serde_json::Value = match serde_json::from_str(&fs::read_to_string(&path)?) {
+                Ok(document) => document,
+                Err(e) => return err(&format!("{} is not valid JSON: {}", file_path, e)),
+            };
+
+            if let Err(e) = json_patch::patch(&mut document, &patch) {
+                return err(&format!("Patch not applied: {}", e));
+            }
+
+            let content = serde_json::to_string_pretty(&document)? + "\n";
+            write_or_propose(ctx, &path, &content)
         },
     }
 }
@@ -67,14 +315,175 @@ pub fn new_read_file_tool() -> Tool {
                 "file_path": {
                     "type": "string",
                     "description": "Path to the file to read"
+                },
+                "encoding": {
+                    "type": "string",
+                    "description": "Encoding of the file when it isn't UTF-8, e.g. \"latin1\", \"windows-1251\" or \"shift_jis\""
                 }
             },
             "required": ["file_path"]
         }),
-        runner: |args| {
+        runner: |ctx, args| {
+            let file_path = args["file_path"].as_str().ok_or("file_path is required")?;
+            let path = match ctx.resolve_read_path(file_path) {
+                Ok(path) => path,
+                Err(message) => return err(&message),
+            };
+            let Some(label) = args["encoding"].as_str() else {
+                let content = fs::read_to_string(&path)?;
+                return Ok(serde_json::json!({"content": content}));
+            };
+
+            let Some(encoding) = encoding_rs::Encoding::for_label(label.trim().as_bytes()) else {
+                return err(&format!("Unknown encoding: {}", label));
+            };
+            let bytes = fs::read(&path)?;
+            let (content, _, had_errors) = encoding.decode(&bytes);
+            Ok(serde_json::json!({
+                "content": content,
+                "encoding": encoding.name(),
+                "had_errors": had_errors
+            }))
+        },
+    }
+}
+
+/// Lines `head_tail` returns when no count is given.
+const DEFAULT_PEEK_LINES: u64 = 20;
+
+/// Bytes read per step while `head_tail` scans backwards for the last lines.
+const TAIL_BLOCK_SIZE: u64 = 8192;
+
+pub fn new_head_tail_tool() -> Tool {
+    Tool {
+        name: "head_tail".to_string(),
+        description: "Returns only the first (head) or last (tail) lines of a file, without reading all of it. Use it to peek at logs and other large files".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "file_path": {
+                    "type": "string",
+                    "description": "Path to the file to peek at"
+                },
+                "mode": {
+                    "type": "string",
+                    "enum": ["head", "tail"],
+                    "description": "Whether to return the first or the last lines"
+                },
+                "lines": {
+                    "type": "integer",
+                    "description": "How many lines to return. Defaults to 20"
+                }
+            },
+            "required": ["file_path", "mode"]
+        }),
+        runner: |ctx, args| {
             let file_path = args["file_path"].as_str().ok_or("file_path is required")?;
-            let content = fs::read_to_string(file_path)?;
-            Ok(serde_json::json!({"content": content}))
+            let mode = args["mode"].as_str().ok_or("mode is required")?;
+            let count = args["lines"].as_u64().unwrap_or(DEFAULT_PEEK_LINES) as usize;
+
+            let path = match ctx.resolve_read_path(file_path) {
+                Ok(path) => path,
+                Err(message) => return err(&message),
+            };
+            let lines = match mode {
+                "head" => head_lines(&path, count)?,
+                "tail" => tail_lines(&path, count)?,
+                other => return err(&format!("Unknown mode '{}', expected head or tail", other)),
+            };
+
+            Ok(serde_json::json!({
+                "mode": mode,
+                "lines": lines.len(),
+                "content": lines.join("\n")
+            }))
+        },
+    }
+}
+
+fn head_lines(path: &Path, count: usize) -> io::Result<Vec<String>> {
+    io::BufReader::new(fs::File::open(path)?)
+        .split(b'\n')
+        .take(count)
+        .map(|line| line.map(|line| String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(&line)).into_owned()))
+        .collect()
+}
+
+/// Reads blocks backwards from the end of the file until they hold `count`
+/// complete lines, so only the tail of a large file is ever read.
+fn tail_lines(path: &Path, count: usize) -> io::Result<Vec<String>> {
+    let mut file = fs::File::open(path)?;
+    let mut start = file.metadata()?.len();
+    let mut tail = Vec::new();
+
+    loop {
+        let trailing = tail.last() == Some(&b'\n');
+        let newlines = tail.iter().filter(|&&byte| byte == b'\n').count() - usize::from(trailing);
+        if newlines >= count || start == 0 {
+            break;
+        }
+
+        let block_start = start.saturating_sub(TAIL_BLOCK_SIZE);
+        let mut block = vec![0; (start - block_start) as usize];
+        file.seek(SeekFrom::Start(block_start))?;
+        file.read_exact(&mut block)?;
+        block.extend(tail);
+        tail = block;
+        start = block_start;
+    }
+
+    // The first line may have been cut off, but then there are more than `count`
+    let text = String::from_utf8_lossy(&tail);
+    let lines: Vec<&str> = text.lines().collect();
+    Ok(lines[lines.len().saturating_sub(count)..].iter().map(|line| line.to_string()).collect())
+}
+
+pub fn new_diff_files_tool() -> Tool {
+    Tool {
+        name: "diff_files".to_string(),
+        description: "Compares two text files and returns a unified diff from path_a to path_b, e.g. to see how two versions or two similar files differ".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "path_a": {
+                    "type": "string",
+                    "description": "Path to the original file"
+                },
+                "path_b": {
+                    "type": "string",
+                    "description": "Path to the file to compare it with"
+                }
+            },
+            "required": ["path_a", "path_b"]
+        }),
+        runner: |ctx, args| {
+            let path_a = args["path_a"].as_str().ok_or("path_a is required")?;
+            let path_b = args["path_b"].as_str().ok_or("path_b is required")?;
+
+            let mut contents = Vec::new();
+            for path in [path_a, path_b] {
+                let resolved = match ctx.resolve_read_path(path) {
+                    Ok(resolved) => resolved,
+                    Err(message) => return err(&message),
+                };
+                match fs::read_to_string(resolved) {
+                    Ok(content) => contents.push(content),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        return err(&format!("File not found: {}", path));
+                    }
+                    Err(e) => return err(&format!("Could not read {}: {}", path, e)),
+                }
+            }
+
+            if contents[0] == contents[1] {
+                return Ok(serde_json::json!({"identical": true, "message": "No differences"}));
+            }
+
+            let diff = TextDiff::from_lines(&contents[0], &contents[1])
+                .unified_diff()
+                .header(path_a, path_b)
+                .to_string();
+            Ok(serde_json::json!({"identical": false, "diff": diff}))
         },
     }
 }
@@ -97,15 +506,25 @@ pub fn new_append_to_file_tool() -> Tool {
             },
             "required": ["file_path", "content"]
         }),
-        runner: |args| {
+        runner: |ctx, args| {
             let file_path = args["file_path"].as_str().ok_or("file_path is required")?;
             let content = args["content"].as_str().ok_or("content is required")?;
 
+            let path = match ctx.resolve_write_path(file_path) {
+                Ok(path) => path,
+                Err(message) => return err(&message),
+            };
+
+            if ctx.preview_edits {
+                let current = fs::read_to_string(&path)?;
+                return Ok(propose_edit(ctx, &path, &(current + content))?);
+            }
+
             let mut file = fs::OpenOptions::new()
                 .append(true)
-                .open(file_path)?;
+                .open(&path)?;
                 
-            file.write(content.as_bytes())?;
+            file.write_all(content.as_bytes())?;
             status_success()
         },
     }
@@ -125,14 +544,85 @@ pub fn new_create_folder_tool() -> Tool {
             },
             "required": ["folder_path"]
         }),
-        runner: |args| {
+        runner: |ctx, args| {
             let folder_path = args["folder_path"].as_str().ok_or("folder_path is required")?;
-            fs::create_dir_all(folder_path)?;
+            let path = match ctx.resolve_write_path(folder_path) {
+                Ok(path) => path,
+                Err(message) => return err(&message),
+            };
+            fs::create_dir_all(path)?;
             status_success()
         },
     }
 }
 
+pub fn new_delete_folder_tool() -> Tool {
+    Tool {
+        name: "delete_folder".to_string(),
+        description: "Deletes a folder inside the project. A folder that is not empty is only deleted when recursive is true, which removes everything in it. The user may be asked to confirm. Returns the number of files removed".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "folder_path": {
+                    "type": "string",
+                    "description": "Path to the folder to delete"
+                },
+                "recursive": {
+                    "type": "boolean",
+                    "description": "Whether to delete the folder's contents too. Must be true for a folder that is not empty"
+                }
+            },
+            "required": ["folder_path", "recursive"]
+        }),
+        runner: |ctx, args| {
+            let folder_path = args["folder_path"].as_str().ok_or("folder_path is required")?;
+            let recursive = args["recursive"].as_bool().ok_or("recursive is required")?;
+
+            let path = match ctx.resolve_write_path(folder_path) {
+                Ok(path) => path,
+                Err(message) => return err(&message),
+            };
+            if Ok(&path) == ctx.resolve_write_path(".").as_ref() {
+                return err("Refusing to delete the project root");
+            }
+            if !path.is_dir() {
+                return err(&format!("{} is not a folder", folder_path));
+            }
+
+            let is_empty = fs::read_dir(&path)?.next().is_none();
+            if !is_empty && !recursive {
+                return err(&format!("{} is not empty; set recursive to true to delete it with its contents", folder_path));
+            }
+
+            let files = count_files(&path)?;
+            if !ctx.confirm(&format!("Delete folder {} and the {} files in it?", folder_path, files)) {
+                return err("The user declined deleting the folder");
+            }
+
+            if is_empty {
+                fs::remove_dir(&path)?;
+            } else {
+                fs::remove_dir_all(&path)?;
+            }
+
+            Ok(serde_json::json!({"status": "success", "files_removed": files}))
+        },
+    }
+}
+
+fn count_files(dir: &Path) -> Result<usize, std::io::Error> {
+    let mut count = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            count += count_files(&entry.path())?;
+        } else {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
 pub fn new_get_folder_files_tool() -> Tool {
     Tool {
         name: "get_folder_files".to_string(),
@@ -152,46 +642,30 @@ pub fn new_get_folder_files_tool() -> Tool {
             },
             "required": ["folder_path"]
         }),
-        runner: |args| {
+        runner: |ctx, args| {
             let folder_path = args["folder_path"].as_str().ok_or("folder_path is required")?;
+            let path = match ctx.resolve_read_path(folder_path) {
+                Ok(path) => path,
+                Err(message) => return err(&message),
+            };
             let recursive = args.get("recursive").and_then(|v| v.as_bool()).unwrap_or(true);
             
-            fn scan_directory(path: &str, recursive: bool) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+            fn scan_directory(path: &Path, recursive: bool) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
                 let mut files = Vec::new();
                 let mut folders = Vec::new();
                 
-                for entry in fs::read_dir(path)? {
-                    if let Ok(entry) = entry {
-                        let path_buf = entry.path();
-                        let file_name = path_buf.file_name()
-                            .and_then(|n| n.to_str())
-                            .map(String::from)
-                            .ok_or("Invalid filename")?;
-                        
-                        let file_type = entry.file_type()?;
-                        
-                        if file_type.is_dir() {
-                            if recursive {
-                                let subfolder_path = path_buf.to_str()
-                                    .ok_or("Invalid path")?;
-                                let subfolder_contents = scan_directory(subfolder_path, recursive)?;
-                                folders.push(serde_json::json!({
-                                    "name": file_name,
-                                    "path": subfolder_path,
-                                    "contents": subfolder_contents
-                                }));
-                            } else {
-                                folders.push(serde_json::json!({
-                                    "name": file_name,
-                                    "path": path_buf.to_str().ok_or("Invalid path")?
-                                }));
-                            }
-                        } else if file_type.is_file() {
-                            files.push(serde_json::json!({
-                                "name": file_name,
-                                "path": path_buf.to_str().ok_or("Invalid path")?
-                            }));
+                for entry in fs::read_dir(path)?.flatten() {
+                    let path_buf = entry.path();
+                    let file_type = entry.file_type()?;
+                    let mut listed = listed_entry(&path_buf);
+                    
+                    if file_type.is_dir() {
+                        if recursive {
+                            listed["contents"] = scan_directory(&path_buf, recursive)?;
                         }
+                        folders.push(listed);
+                    } else if file_type.is_file() {
+                        files.push(listed);
                     }
                 }
                 
@@ -201,8 +675,529 @@ pub fn new_get_folder_files_tool() -> Tool {
                 }))
             }
             
-            let result = scan_directory(folder_path, recursive)?;
+            let result = scan_directory(&path, recursive)?;
             Ok(result)
         },
     }
 }
+
+/// Name and path of a directory entry for JSON output. Names that aren't valid
+/// UTF-8 are converted lossily and marked with `"lossy": true`, since the
+/// converted path no longer refers to the file.
+fn listed_entry(path: &Path) -> serde_json::Value {
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    let mut entry = serde_json::json!({
+        "name": name.to_string_lossy(),
+        "path": path.to_string_lossy()
+    });
+    if path.to_str().is_none() {
+        entry["lossy"] = true.into();
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peek(ctx: &ToolContext, mode: &str, lines: u64) -> serde_json::Value {
+        new_head_tail_tool().run(ctx, serde_json::json!({"file_path": "app.log", "mode": mode, "lines": lines})).unwrap()
+    }
+
+    #[test]
+    fn test_head_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        // Long enough for tail to read several blocks
+        let log: String = (1..=5000).map(|i| format!("line {}\n", i)).collect();
+        fs::write(dir.path().join("app.log"), log).unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let head = peek(&ctx, "head", 3);
+        assert_eq!(head["content"], "line 1\nline 2\nline 3");
+        assert_eq!(head["lines"], 3);
+
+        let tail = peek(&ctx, "tail", 3);
+        assert_eq!(tail["content"], "line 4998\nline 4999\nline 5000");
+
+        let tail = peek(&ctx, "tail", 2000);
+        assert_eq!(tail["lines"], 2000);
+        assert!(tail["content"].as_str().unwrap().starts_with("line 3001\n"));
+    }
+
+    #[test]
+    fn test_head_tail_short_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("app.log"), "first\r\nsecond").unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        for mode in ["head", "tail"] {
+            let result = peek(&ctx, mode, 10);
+            assert_eq!(result["content"], "first\nsecond", "mode {}", mode);
+            assert_eq!(result["lines"], 2);
+        }
+    }
+
+    #[test]
+    fn test_head_tail_outside_workspace() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        let ctx = ToolContext::new(dir.path().join("project"));
+
+        for file_path in ["../secret.txt", dir.path().join("secret.txt").to_str().unwrap()] {
+            let result = new_head_tail_tool().run(&ctx, serde_json::json!({"file_path": file_path, "mode": "head"})).unwrap();
+            assert_eq!(result["status"], "error");
+            assert!(result["message"].as_str().unwrap().contains("outside the workspace"));
+        }
+    }
+
+    fn diff(ctx: &ToolContext, path_a: &str, path_b: &str) -> serde_json::Value {
+        new_diff_files_tool().run(ctx, serde_json::json!({"path_a": path_a, "path_b": path_b})).unwrap()
+    }
+
+    #[test]
+    fn test_diff_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("old.rs"), "fn main() {\n    run();\n}\n").unwrap();
+        fs::write(dir.path().join("new.rs"), "fn main() {\n    run_all();\n}\n").unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = diff(&ctx, "old.rs", "new.rs");
+
+        assert_eq!(result["identical"], false);
+        let diff = result["diff"].as_str().unwrap();
+        assert!(diff.starts_with("--- old.rs\n+++ new.rs\n"));
+        assert!(diff.contains("\n-    run();\n+    run_all();\n"));
+    }
+
+    #[test]
+    fn test_diff_identical_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "same\n").unwrap();
+        fs::write(dir.path().join("b.txt"), "same\n").unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = diff(&ctx, "a.txt", "b.txt");
+
+        assert_eq!(result, serde_json::json!({"identical": true, "message": "No differences"}));
+    }
+
+    #[test]
+    fn test_diff_outside_workspace() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("secret.txt"), "secret\n").unwrap();
+        fs::create_dir_all(dir.path().join("project")).unwrap();
+        fs::write(dir.path().join("project/a.txt"), "text\n").unwrap();
+        let ctx = ToolContext::new(dir.path().join("project"));
+
+        let result = diff(&ctx, "a.txt", "../secret.txt");
+
+        assert_eq!(result["status"], "error");
+        assert_eq!(result["message"], "../secret.txt is outside the workspace");
+        assert!(result.get("diff").is_none());
+    }
+
+    #[test]
+    fn test_diff_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "text\n").unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = diff(&ctx, "a.txt", "gone.txt");
+
+        assert_eq!(result["status"], "error");
+        assert_eq!(result["message"], "File not found: gone.txt");
+    }
+
+    #[test]
+    fn test_file_tools_stay_in_workspace() {
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ToolContext::new(dir.path().join("project"));
+        let secret = outside.path().join("secret.txt");
+        let secret = secret.to_str().unwrap();
+
+        let result = new_read_file_tool().run(&ctx, serde_json::json!({"file_path": secret})).unwrap();
+        assert_eq!(result["status"], "error");
+        let result = new_get_folder_files_tool().run(&ctx, serde_json::json!({"folder_path": ".."})).unwrap();
+        assert_eq!(result["status"], "error");
+        let result = new_replace_file_tool().run(&ctx, serde_json::json!({"file_path": secret, "content": "x"})).unwrap();
+        assert_eq!(result["status"], "error");
+        let result = new_append_to_file_tool().run(&ctx, serde_json::json!({"file_path": secret, "content": "x"})).unwrap();
+        assert_eq!(result["status"], "error");
+        let result = new_create_folder_tool().run(&ctx, serde_json::json!({"folder_path": "../escaped"})).unwrap();
+        assert_eq!(result["status"], "error");
+
+        assert_eq!(fs::read_to_string(outside.path().join("secret.txt")).unwrap(), "secret");
+        assert!(!dir.path().join("escaped").exists());
+    }
+
+    #[test]
+    fn test_read_file_with_encoding() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("legacy.log");
+        // "café naïve — 10€" in latin-1 / windows-1252
+        fs::write(&path, b"caf\xe9 na\xefve \x97 10\x80\n").unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_read_file_tool().run(&ctx, serde_json::json!({
+            "file_path": path.to_str().unwrap(),
+            "encoding": "latin1"
+        })).unwrap();
+        assert_eq!(result["content"], "café naïve — 10€\n");
+        assert_eq!(result["encoding"], "windows-1252");
+        assert_eq!(result["had_errors"], false);
+
+        // Without an encoding the file must be UTF-8
+        assert!(new_read_file_tool().run(&ctx, serde_json::json!({"file_path": path.to_str().unwrap()})).is_err());
+
+        let result = new_read_file_tool().run(&ctx, serde_json::json!({
+            "file_path": path.to_str().unwrap(),
+            "encoding": "klingon"
+        })).unwrap();
+        assert_eq!(result["status"], "error");
+    }
+
+    #[test]
+    fn test_conditional_write_matching_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "old").unwrap();
+
+        let result = new_conditional_write_tool().run(&ToolContext::new(dir.path()), serde_json::json!({
+            "file_path": path.to_str().unwrap(),
+            "expected_current_content": "old",
+            "new_content": "new"
+        })).unwrap();
+
+        assert_eq!(result["status"], "success");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_conditional_write_matching_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "old").unwrap();
+
+        let result = new_conditional_write_tool().run(&ToolContext::new(dir.path()), serde_json::json!({
+            "file_path": path.to_str().unwrap(),
+            "expected_current_hash": sha256_hex("old"),
+            "new_content": "new"
+        })).unwrap();
+
+        assert_eq!(result["status"], "success");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_conditional_write_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "changed by someone else").unwrap();
+
+        let result = new_conditional_write_tool().run(&ToolContext::new(dir.path()), serde_json::json!({
+            "file_path": path.to_str().unwrap(),
+            "expected_current_content": "old",
+            "new_content": "new"
+        })).unwrap();
+
+        assert_eq!(result["status"], "error");
+        assert!(result["message"].as_str().unwrap().starts_with("Conflict"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "changed by someone else");
+    }
+
+    #[test]
+    fn test_conditional_write_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("file.txt"), "old").unwrap();
+        fs::write(dir.path().join("outside.txt"), "old").unwrap();
+        let ctx = ToolContext::new(dir.path().join("project"));
+        fs::create_dir_all(&ctx.root).unwrap();
+        fs::write(ctx.root.join("file.txt"), "old").unwrap();
+
+        let result = new_conditional_write_tool().run(&ctx, serde_json::json!({
+            "file_path": "../outside.txt",
+            "expected_current_content": "old",
+            "new_content": "new"
+        })).unwrap();
+        assert_eq!(result["status"], "error");
+        assert_eq!(fs::read_to_string(dir.path().join("outside.txt")).unwrap(), "old");
+
+        let result = new_conditional_write_tool().run(&ctx, serde_json::json!({
+            "file_path": "file.txt",
+            "new_content": "new"
+        })).unwrap();
+        assert_eq!(result["status"], "error");
+        assert!(result["message"].as_str().unwrap().contains("expected_current_content"));
+        assert_eq!(fs::read_to_string(ctx.root.join("file.txt")).unwrap(), "old");
+    }
+
+    #[test]
+    fn test_json_patch() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ToolContext::new(dir.path());
+        fs::write(dir.path().join("package.json"), r#"{"name": "app", "version": "1.0.0", "private": true, "scripts": {}}"#).unwrap();
+
+        let result = new_json_patch_tool().run(&ctx, serde_json::json!({
+            "file_path": "package.json",
+            "patch": [
+                {"op": "replace", "path": "/version", "value": "1.1.0"},
+                {"op": "remove", "path": "/private"},
+                {"op": "add", "path": "/scripts/test", "value": "jest"}
+            ]
+        })).unwrap();
+
+        assert_eq!(result["status"], "success");
+        let content = fs::read_to_string(dir.path().join("package.json")).unwrap();
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&content).unwrap(), serde_json::json!({
+            "name": "app",
+            "version": "1.1.0",
+            "scripts": {"test": "jest"}
+        }));
+        assert!(content.contains("\n  \"name\": \"app\""));
+    }
+
+    #[test]
+    fn test_json_patch_failing_operation_leaves_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ToolContext::new(dir.path());
+        let original = r#"{"version": "1.0.0"}"#;
+        fs::write(dir.path().join("config.json"), original).unwrap();
+
+        let result = new_json_patch_tool().run(&ctx, serde_json::json!({
+            "file_path": "config.json",
+            "patch": [
+                {"op": "replace", "path": "/version", "value": "2.0.0"},
+                {"op": "remove", "path": "/missing"}
+            ]
+        })).unwrap();
+
+        assert_eq!(result["status"], "error");
+        assert_eq!(fs::read_to_string(dir.path().join("config.json")).unwrap(), original);
+    }
+
+    #[test]
+    fn test_json_patch_rejects_invalid_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ToolContext::new(dir.path());
+        fs::write(dir.path().join("broken.json"), "{\"version\": ").unwrap();
+
+        let result = new_json_patch_tool().run(&ctx, serde_json::json!({
+            "file_path": "broken.json",
+            "patch": [{"op": "replace", "path": "/version", "value": "2.0.0"}]
+        })).unwrap();
+
+        assert_eq!(result["status"], "error");
+        assert!(result["message"].as_str().unwrap().contains("not valid JSON"));
+        assert_eq!(fs::read_to_string(dir.path().join("broken.json")).unwrap(), "{\"version\": ");
+    }
+
+    #[test]
+    fn test_write_file_creates_parent_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_write_file_tool().run(&ctx, serde_json::json!({
+            "file_path": "src/nested/lib.rs",
+            "content": "pub fn lib() {}"
+        })).unwrap();
+        assert_eq!(result["status"], "success");
+        assert_eq!(fs::read_to_string(dir.path().join("src/nested/lib.rs")).unwrap(), "pub fn lib() {}");
+
+        let result = new_write_file_tool().run(&ctx, serde_json::json!({
+            "file_path": "missing/lib.rs",
+            "content": "pub fn lib() {}",
+            "create_dirs": false
+        }));
+        assert!(result.is_err());
+        assert!(!dir.path().join("missing").exists());
+    }
+
+    #[test]
+    fn test_write_files_batch_with_invalid_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_write_files_tool().run(&ctx, serde_json::json!({
+            "files": [
+                {"file_path": "Cargo.toml", "content": "[package]"},
+                {"file_path": "../escape.txt", "content": "nope"},
+                {"file_path": "src/bin/main.rs", "content": "fn main() {}"}
+            ]
+        })).unwrap();
+
+        assert_eq!(result["status"], "error");
+        assert_eq!(result["message"], "2 of 3 files written");
+        assert_eq!(result["files"][0]["status"], "success");
+        assert_eq!(result["files"][1]["status"], "error");
+        assert_eq!(result["files"][2]["status"], "success");
+        assert_eq!(fs::read_to_string(dir.path().join("Cargo.toml")).unwrap(), "[package]");
+        assert_eq!(fs::read_to_string(dir.path().join("src/bin/main.rs")).unwrap(), "fn main() {}");
+        assert!(!dir.path().parent().unwrap().join("escape.txt").exists());
+    }
+
+    #[test]
+    fn test_writes_into_backup_dir_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ToolContext::new(dir.path());
+        let backup = dir.path().join(".codr_backup");
+        fs::create_dir_all(&backup).unwrap();
+        fs::write(backup.join("main.rs"), "backup").unwrap();
+
+        let result = new_write_file_tool().run(&ctx, serde_json::json!({
+            "file_path": ".codr_backup/main.rs",
+            "content": "corrupted"
+        })).unwrap();
+        assert_eq!(result["status"], "error");
+        assert!(result["message"].as_str().unwrap().contains("reserved"));
+
+        let result = new_append_to_file_tool().run(&ctx, serde_json::json!({
+            "file_path": backup.join("main.rs").to_str().unwrap(),
+            "content": "corrupted"
+        })).unwrap();
+        assert_eq!(result["status"], "error");
+
+        assert_eq!(fs::read_to_string(backup.join("main.rs")).unwrap(), "backup");
+    }
+
+    #[tokio::test]
+    async fn test_preview_edits_until_applied() {
+        let dir = tempfile::tempdir().unwrap();
+        let existing = dir.path().join("main.rs");
+        fs::write(&existing, "fn main() {}\n").unwrap();
+
+        let mut toolbox = crate::ToolBox::with_context(ToolContext::new(dir.path()));
+        toolbox.context_mut().preview_edits = true;
+
+        let created = toolbox.run_tool("write_file", serde_json::json!({
+            "file_path": "lib.rs",
+            "content": "pub fn lib() {}\n"
+        })).await.unwrap();
+        let replaced = toolbox.run_tool("replace_file_content", serde_json::json!({
+            "file_path": existing.to_str().unwrap(),
+            "content": "fn main() {\n    println!(\"hi\");\n}\n"
+        })).await.unwrap();
+
+        assert_eq!(created["status"], "pending");
+        assert_eq!(replaced["status"], "pending");
+        assert!(replaced["diff"].as_str().unwrap().contains("-fn main() {}"));
+        assert!(!dir.path().join("lib.rs").exists());
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "fn main() {}\n");
+
+        for result in [&created, &replaced] {
+            let applied = toolbox.run_tool("apply_pending_edit", serde_json::json!({"edit_id": result["edit_id"]})).await.unwrap();
+            assert_eq!(applied["status"], "success");
+        }
+
+        assert_eq!(fs::read_to_string(dir.path().join("lib.rs")).unwrap(), "pub fn lib() {}\n");
+        assert!(fs::read_to_string(&existing).unwrap().contains("println!"));
+        assert!(toolbox.context().pending_edits.ids().is_empty());
+    }
+
+    #[test]
+    fn test_apply_pending_edit_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "old").unwrap();
+
+        let mut ctx = ToolContext::new(dir.path());
+        ctx.preview_edits = true;
+        let proposed = new_write_file_tool().run(&ctx, serde_json::json!({"file_path": "file.txt", "content": "new"})).unwrap();
+        fs::write(&path, "changed meanwhile").unwrap();
+
+        let result = new_apply_pending_edit_tool().run(&ctx, serde_json::json!({"edit_id": proposed["edit_id"]})).unwrap();
+
+        assert_eq!(result["status"], "error");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "changed meanwhile");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_get_folder_files_non_utf8_name() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(OsStr::from_bytes(b"caf\xe9.txt")), "").unwrap();
+        fs::write(dir.path().join("plain.txt"), "").unwrap();
+
+        let result = new_get_folder_files_tool().run(&ToolContext::new(dir.path()), serde_json::json!({
+            "folder_path": "."
+        })).unwrap();
+
+        let mut files = result["files"].as_array().unwrap().clone();
+        files.sort_by_key(|file| file["name"].as_str().unwrap().to_string());
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0]["name"], "caf\u{FFFD}.txt");
+        assert_eq!(files[0]["lossy"], true);
+        assert_eq!(files[1]["name"], "plain.txt");
+        assert!(files[1].get("lossy").is_none());
+    }
+
+    fn folder_fixture() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("empty")).unwrap();
+        fs::create_dir_all(dir.path().join("build/nested")).unwrap();
+        fs::write(dir.path().join("build/a.o"), "").unwrap();
+        fs::write(dir.path().join("build/nested/b.o"), "").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_delete_empty_folder() {
+        let dir = folder_fixture();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_delete_folder_tool()
+            .run(&ctx, serde_json::json!({"folder_path": "empty", "recursive": false}))
+            .unwrap();
+
+        assert_eq!(result["status"], "success");
+        assert_eq!(result["files_removed"], 0);
+        assert!(!dir.path().join("empty").exists());
+    }
+
+    #[test]
+    fn test_delete_non_empty_folder_requires_recursive() {
+        let dir = folder_fixture();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_delete_folder_tool()
+            .run(&ctx, serde_json::json!({"folder_path": "build", "recursive": false}))
+            .unwrap();
+
+        assert_eq!(result["status"], "error");
+        assert!(dir.path().join("build/nested/b.o").exists());
+    }
+
+    #[test]
+    fn test_delete_folder_recursive() {
+        let dir = folder_fixture();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_delete_folder_tool()
+            .run(&ctx, serde_json::json!({"folder_path": "build", "recursive": true}))
+            .unwrap();
+
+        assert_eq!(result["status"], "success");
+        assert_eq!(result["files_removed"], 2);
+        assert!(!dir.path().join("build").exists());
+    }
+
+    #[test]
+    fn test_delete_folder_guards() {
+        let dir = folder_fixture();
+        let mut ctx = ToolContext::new(dir.path().join("build"));
+        ctx.confirm = Some(std::sync::Arc::new(|_: &str| false));
+
+        for folder_path in [".", "../empty", "nested"] {
+            let result = new_delete_folder_tool()
+                .run(&ctx, serde_json::json!({"folder_path": folder_path, "recursive": true}))
+                .unwrap();
+            assert_eq!(result["status"], "error", "{} should not be deleted", folder_path);
+        }
+
+        assert!(dir.path().join("empty").exists());
+        assert!(dir.path().join("build/nested/b.o").exists());
+    }
+}
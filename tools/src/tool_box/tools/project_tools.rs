@@ -0,0 +1,767 @@
+use std::{collections::BTreeMap, fs, path::Path};
+use regex::Regex;
+use crate::tool_box::{context::ensure_not_reserved, err, ignore::IgnoreRules, tools::Tool};
+
+const DEFAULT_OVERVIEW_DEPTH: u64 = 3;
+
+const DEFAULT_STATS_DEPTH: u64 = 20;
+
+/// Most top-level entries `workspace_info` lists.
+const MAX_TOP_LEVEL_ENTRIES: usize = 50;
+
+/// Most definitions `find_definition` reports, so a common name can't flood the context.
+const MAX_DEFINITIONS: usize = 50;
+
+/// Most landmarks `summarize_file` reports.
+const MAX_LANDMARKS: usize = 200;
+
+/// Characters of a landmark line kept, long signatures are cut.
+const MAX_LANDMARK_CHARS: usize = 160;
+
+/// Definition patterns for the files with the given extensions. `{name}` is
+/// replaced by the (escaped) symbol being looked up. Supporting another
+/// language is a matter of adding an entry here.
+struct LanguagePatterns {
+    extensions: &'static [&'static str],
+    patterns: &'static [&'static str],
+}
+
+const LANGUAGES: &[LanguagePatterns] = &[
+    LanguagePatterns {
+        extensions: &["rs"],
+        patterns: &[
+            r"\bfn\s+{name}\b",
+            r"\b(struct|enum|union|trait|type|mod)\s+{name}\b",
+            r"\b(const|static)\s+(mut\s+)?{name}\s*:",
+            r"\bmacro_rules!\s*{name}\b",
+        ],
+    },
+    LanguagePatterns {
+        extensions: &["py"],
+        patterns: &[r"^\s*(async\s+)?def\s+{name}\b", r"^\s*class\s+{name}\b"],
+    },
+    LanguagePatterns {
+        extensions: &["js", "jsx", "ts", "tsx", "mjs", "cjs"],
+        patterns: &[
+            r"\bfunction\*?\s+{name}\b",
+            r"\b(class|interface|enum)\s+{name}\b",
+            r"\btype\s+{name}\s*=",
+            r"\b(const|let|var)\s+{name}\s*=",
+        ],
+    },
+    LanguagePatterns {
+        extensions: &["go"],
+        patterns: &[r"\bfunc\s+(\([^)]*\)\s*)?{name}\b", r"\btype\s+{name}\b"],
+    },
+    LanguagePatterns {
+        extensions: &["java", "kt", "cs", "scala", "swift"],
+        patterns: &[r"\b(class|interface|enum|record|struct|object|protocol)\s+{name}\b", r"\b(fun|func|def)\s+{name}\b"],
+    },
+    LanguagePatterns {
+        extensions: &["c", "h", "cc", "cpp", "hpp"],
+        patterns: &[r"\b(struct|class|enum|union)\s+{name}\b", r"#define\s+{name}\b", r"^\w[\w\s\*&:<>]*\b{name}\s*\([^;]*$"],
+    },
+    LanguagePatterns {
+        extensions: &["rb"],
+        patterns: &[r"\bdef\s+(self\.)?{name}\b", r"\b(class|module)\s+{name}\b"],
+    },
+];
+
+pub fn new_project_overview_tool() -> Tool {
+    Tool {
+        name: "project_overview".to_string(),
+        description: "Returns a compact tree of the project's files and folders, skipping ignored paths (.git, target, node_modules and anything in .codrignore). Folders end with '/'. A good first call to get oriented in a project".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Folder to describe, relative to the project root (default: the project root)"
+                },
+                "max_depth": {
+                    "type": "integer",
+                    "description": "How many folder levels to descend (default: 3)",
+                    "default": DEFAULT_OVERVIEW_DEPTH
+                },
+                "ignore": {
+                    "type": "array",
+                    "items": {"type": "string"},
+                    "description": "Extra glob patterns to skip, e.g. [\"*.lock\", \"docs/generated\"]"
+                }
+            }
+        }),
+        runner: |ctx, args| {
+            let path = args["path"].as_str().unwrap_or(".");
+            let max_depth = args["max_depth"].as_u64().unwrap_or(DEFAULT_OVERVIEW_DEPTH);
+
+            let mut ignore = IgnoreRules::load(&ctx.root);
+            if let Some(extra) = args["ignore"].as_array() {
+                ignore.extend(extra.iter().filter_map(|pattern| pattern.as_str()));
+            }
+
+            let start = match ctx.resolve_read_path(path) {
+                Ok(start) => start,
+                Err(message) => return err(&message),
+            };
+            let mut tree = format!("{}/\n", path.trim_end_matches('/'));
+            render_tree(&ctx.root, &start, &ignore, "", 1, max_depth, &mut tree)?;
+
+            Ok(serde_json::json!({
+                "root": ctx.root.to_string_lossy(),
+                "tree": tree
+            }))
+        },
+    }
+}
+
+pub fn new_workspace_info_tool() -> Tool {
+    Tool {
+        name: "workspace_info".to_string(),
+        description: "Describes where you are working: the workspace root, the current git branch (if any), the operating system and the top-level files and folders. Call it at the start of a session to get oriented".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {}
+        }),
+        runner: |ctx, _args| {
+            let ignore = IgnoreRules::load(&ctx.root);
+            let mut entries: Vec<String> = fs::read_dir(&ctx.root)?
+                .filter_map(Result::ok)
+                .filter(|entry| !ignore.is_ignored(Path::new(&entry.file_name())))
+                .map(|entry| {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    if entry.path().is_dir() { format!("{}/", name) } else { name }
+                })
+                .collect();
+            entries.sort();
+            let truncated = entries.len() > MAX_TOP_LEVEL_ENTRIES;
+            entries.truncate(MAX_TOP_LEVEL_ENTRIES);
+
+            Ok(serde_json::json!({
+                "root": ctx.root.to_string_lossy(),
+                "git_branch": git_branch(&ctx.root),
+                "os": std::env::consts::OS,
+                "arch": std::env::consts::ARCH,
+                "top_level": entries,
+                "truncated": truncated
+            }))
+        },
+    }
+}
+
+/// Branch checked out in the repository at `root`, read from `.git/HEAD`
+/// without running git. A detached HEAD is reported by its short commit hash.
+fn git_branch(root: &Path) -> Option<String> {
+    let head = fs::read_to_string(root.join(".git/HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(reference) => Some(reference.strip_prefix("refs/heads/").unwrap_or(reference).to_string()),
+        None => Some(format!("detached at {}", &head[..head.len().min(7)])),
+    }
+}
+
+pub fn new_summarize_file_tool() -> Tool {
+    Tool {
+        name: "summarize_file".to_string(),
+        description: "Lists the landmarks of a file instead of its content: function, type and class signatures for code, headings for Markdown, each with its line number. Use it to find your way around a large file, then read only the part you need".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "file_path": {
+                    "type": "string",
+                    "description": "Path to the file, relative to the project root"
+                }
+            },
+            "required": ["file_path"]
+        }),
+        runner: |ctx, args| {
+            let file_path = args["file_path"].as_str().ok_or("file_path is required")?;
+            let path = match ctx.resolve_read_path(file_path) {
+                Ok(path) => path,
+                Err(message) => return err(&message),
+            };
+            let content = fs::read_to_string(&path)?;
+
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+            let patterns: Vec<Regex> = if matches!(extension, "md" | "markdown") {
+                vec![Regex::new(r"^#{1,6}\s")?]
+            } else {
+                // The definition patterns, matching any name
+                let Some(language) = LANGUAGES.iter().find(|language| language.extensions.contains(&extension)) else {
+                    return err(&format!("No landmarks known for .{} files, use read_file instead", extension));
+                };
+                language.patterns.iter()
+                    .map(|pattern| Regex::new(&pattern.replace("{name}", r"\w+")))
+                    .collect::<Result<_, _>>()?
+            };
+
+            let mut landmarks = Vec::new();
+            let mut truncated = false;
+            let mut in_fence = false;
+            for (number, line) in content.lines().enumerate() {
+                let trimmed = line.trim();
+                // Skip comments, and code blocks in Markdown
+                if trimmed.starts_with("```") {
+                    in_fence = !in_fence;
+                }
+                if in_fence || ["//", "/*", "*"].iter().any(|comment| trimmed.starts_with(comment)) {
+                    continue;
+                }
+                if !patterns.iter().any(|re| re.is_match(line)) {
+                    continue;
+                }
+
+                if landmarks.len() == MAX_LANDMARKS {
+                    truncated = true;
+                    break;
+                }
+                let text = trimmed.trim_end_matches('{').trim_end();
+                landmarks.push(serde_json::json!({
+                    "line": number + 1,
+                    "text": text.chars().take(MAX_LANDMARK_CHARS).collect::<String>()
+                }));
+            }
+
+            Ok(serde_json::json!({
+                "file": file_path,
+                "lines": content.lines().count(),
+                "landmarks": landmarks,
+                "truncated": truncated
+            }))
+        },
+    }
+}
+
+pub fn new_find_definition_tool() -> Tool {
+    Tool {
+        name: "find_definition".to_string(),
+        description: "Finds where a symbol (function, struct, class, type...) is defined in the project, using per-language definition patterns. Returns the file and line of each match. Skips ignored paths".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "symbol": {
+                    "type": "string",
+                    "description": "Name of the symbol to look up, e.g. \"ToolBox\" or \"run_tool\""
+                },
+                "patterns": {
+                    "type": "array",
+                    "items": {"type": "string"},
+                    "description": "Extra regex patterns to try on every file, with {name} standing for the symbol, e.g. [\"^\\s*proc\\s+{name}\\b\"]"
+                }
+            },
+            "required": ["symbol"]
+        }),
+        runner: |ctx, args| {
+            let symbol = args["symbol"].as_str().ok_or("symbol is required")?;
+            let name = regex::escape(symbol);
+            let compile = |pattern: &str| Regex::new(&pattern.replace("{name}", &name));
+
+            let languages = LANGUAGES.iter()
+                .map(|language| Ok((language.extensions, language.patterns.iter().map(|p| compile(p)).collect::<Result<Vec<_>, _>>()?)))
+                .collect::<Result<Vec<_>, regex::Error>>()?;
+            let extra = args["patterns"].as_array()
+                .map(|patterns| patterns.iter().filter_map(|p| p.as_str()).map(compile).collect::<Result<Vec<_>, _>>())
+                .transpose()?
+                .unwrap_or_default();
+
+            let mut definitions = Vec::new();
+            let ignore = IgnoreRules::load(&ctx.root);
+            visit_text_files(&ctx.root, &ctx.root, &ignore, &mut |path, content| {
+                let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+                let language = languages.iter()
+                    .find(|(extensions, _)| extensions.contains(&extension))
+                    .map(|(_, patterns)| patterns.as_slice())
+                    .unwrap_or_default();
+                if language.is_empty() && extra.is_empty() {
+                    return;
+                }
+
+                for (number, line) in content.lines().enumerate() {
+                    if definitions.len() < MAX_DEFINITIONS && language.iter().chain(&extra).any(|re| re.is_match(line)) {
+                        definitions.push(serde_json::json!({
+                            "file": path.strip_prefix(&ctx.root).unwrap_or(path).to_string_lossy(),
+                            "line": number + 1,
+                            "text": line.trim()
+                        }));
+                    }
+                }
+            })?;
+
+            Ok(serde_json::json!({
+                "symbol": symbol,
+                "definitions": definitions
+            }))
+        },
+    }
+}
+
+pub fn new_project_replace_tool() -> Tool {
+    Tool {
+        name: "project_replace".to_string(),
+        description: "Replaces text in every project file, or every file matching a glob, skipping ignored paths. Use it for renames and other project-wide edits. The user may be asked to confirm. Returns the number of replacements per file and in total".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "pattern": {
+                    "type": "string",
+                    "description": "Text to replace, or a regex when regex is true"
+                },
+                "replacement": {
+                    "type": "string",
+                    "description": "Text to put in its place. With regex, $1 or ${name} insert capture groups"
+                },
+                "glob": {
+                    "type": "string",
+                    "description": "Only edit files whose path relative to the project root matches, e.g. \"*.rs\" or \"src/**/*.ts\""
+                },
+                "regex": {
+                    "type": "boolean",
+                    "description": "Treat pattern as a regular expression. Defaults to false"
+                }
+            },
+            "required": ["pattern", "replacement"]
+        }),
+        runner: |ctx, args| {
+            let pattern = args["pattern"].as_str().ok_or("pattern is required")?;
+            let replacement = args["replacement"].as_str().ok_or("replacement is required")?;
+            if pattern.is_empty() {
+                return err("pattern must not be empty");
+            }
+            let regex = match args["regex"].as_bool().unwrap_or(false) {
+                true => Some(Regex::new(pattern)?),
+                false => None,
+            };
+            let glob = args["glob"].as_str().map(glob::Pattern::new).transpose()?;
+
+            let mut edits = Vec::new();
+            let ignore = IgnoreRules::load(&ctx.root);
+            visit_text_files(&ctx.root, &ctx.root, &ignore, &mut |path, content| {
+                let relative = path.strip_prefix(&ctx.root).unwrap_or(path);
+                if glob.as_ref().is_some_and(|glob| !glob.matches_path(relative)) || ensure_not_reserved(relative).is_err() {
+                    return;
+                }
+
+                let (count, edited) = match &regex {
+                    Some(regex) => (regex.find_iter(content).count(), regex.replace_all(content, replacement).into_owned()),
+                    None => (content.matches(pattern).count(), content.replace(pattern, replacement)),
+                };
+                if count > 0 {
+                    edits.push((path.to_path_buf(), relative.to_string_lossy().to_string(), count, edited));
+                }
+            })?;
+
+            let total: usize = edits.iter().map(|(_, _, count, _)| count).sum();
+            // Previewed edits are confirmed one by one when applied
+            if total > 0 && !ctx.preview_edits && !ctx.confirm(&format!("Replace {} occurrences of {:?} with {:?} in {} files?", total, pattern, replacement, edits.len())) {
+                return err("The user declined the replacement");
+            }
+
+            let mut files = Vec::new();
+            for (path, relative, count, edited) in edits {
+                let mut file = serde_json::json!({"file": relative, "replacements": count});
+                // With previews on, each file becomes an edit to apply separately
+                if ctx.preview_edits {
+                    file["edit_id"] = ctx.pending_edits.propose(&path, &edited)?.0.into();
+                } else {
+                    fs::write(&path, edited)?;
+                }
+                files.push(file);
+            }
+
+            Ok(serde_json::json!({
+                "status": "success",
+                "files": files,
+                "total": total
+            }))
+        },
+    }
+}
+
+pub fn new_code_stats_tool() -> Tool {
+    Tool {
+        name: "code_stats".to_string(),
+        description: "Counts the project's files and lines, in total and per file extension, skipping ignored paths. Use it to gauge the size and languages of a project before diving in".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "path": {
+                    "type": "string",
+                    "description": "Folder to count, relative to the project root (default: the project root)"
+                },
+                "max_depth": {
+                    "type": "integer",
+                    "description": "How many folder levels to descend (default: 20)",
+                    "default": DEFAULT_STATS_DEPTH
+                }
+            }
+        }),
+        runner: |ctx, args| {
+            let path = args["path"].as_str().unwrap_or(".");
+            let max_depth = args["max_depth"].as_u64().unwrap_or(DEFAULT_STATS_DEPTH);
+
+            let start = match ctx.resolve_read_path(path) {
+                Ok(start) => start,
+                Err(message) => return err(&message),
+            };
+            let ignore = IgnoreRules::load(&ctx.root);
+            let mut by_extension = BTreeMap::new();
+            count_code(&ctx.root, &start, &ignore, 1, max_depth, &mut by_extension)?;
+
+            let total_files: u64 = by_extension.values().map(|stats: &ExtensionStats| stats.files).sum();
+            let total_lines: u64 = by_extension.values().map(|stats| stats.lines).sum();
+            let by_extension: serde_json::Map<_, _> = by_extension.into_iter()
+                .map(|(extension, stats)| (extension, serde_json::json!({"files": stats.files, "lines": stats.lines})))
+                .collect();
+
+            Ok(serde_json::json!({
+                "total_files": total_files,
+                "total_lines": total_lines,
+                "by_extension": by_extension
+            }))
+        },
+    }
+}
+
+#[derive(Debug, Default)]
+struct ExtensionStats {
+    files: u64,
+    lines: u64,
+}
+
+/// Tallies the files under `dir` by extension, `(none)` for files without one.
+fn count_code(
+    root: &Path,
+    dir: &Path,
+    ignore: &IgnoreRules,
+    depth: u64,
+    max_depth: u64,
+    by_extension: &mut BTreeMap<String, ExtensionStats>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for entry in fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        if ignore.is_ignored(path.strip_prefix(root).unwrap_or(&path)) {
+            continue;
+        }
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if depth < max_depth {
+                count_code(root, &path, ignore, depth + 1, max_depth, by_extension)?;
+            }
+        } else if file_type.is_file() {
+            let extension = path.extension()
+                .map(|extension| extension.to_string_lossy().to_string())
+                .unwrap_or_else(|| "(none)".to_string());
+            let stats = by_extension.entry(extension).or_default();
+            stats.files += 1;
+            stats.lines += count_lines(&fs::read(&path)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Number of lines, counting a last line without a trailing newline.
+fn count_lines(content: &[u8]) -> u64 {
+    let newlines = content.iter().filter(|byte| **byte == b'\n').count() as u64;
+    newlines + u64::from(!content.is_empty() && !content.ends_with(b"\n"))
+}
+
+/// Calls `visit` with the path and content of every non-ignored UTF-8 file under `dir`.
+fn visit_text_files(
+    root: &Path,
+    dir: &Path,
+    ignore: &IgnoreRules,
+    visit: &mut impl FnMut(&Path, &str),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.flatten().collect();
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        if ignore.is_ignored(path.strip_prefix(root).unwrap_or(&path)) {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            visit_text_files(root, &path, ignore, visit)?;
+        } else if let Ok(content) = fs::read_to_string(&path) {
+            visit(&path, &content);
+        }
+    }
+
+    Ok(())
+}
+
+fn render_tree(
+    root: &Path,
+    dir: &Path,
+    ignore: &IgnoreRules,
+    prefix: &str,
+    depth: u64,
+    max_depth: u64,
+    tree: &mut String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?
+        .flatten()
+        .filter(|entry| {
+            let path = entry.path();
+            !ignore.is_ignored(path.strip_prefix(root).unwrap_or(&path))
+        })
+        .collect();
+
+    // Folders first, then files, each alphabetically
+    entries.sort_by_key(|entry| (!entry.path().is_dir(), entry.file_name()));
+
+    for (i, entry) in entries.iter().enumerate() {
+        let last = i == entries.len() - 1;
+        let branch = if last { "└── " } else { "├── " };
+        let name = entry.file_name().to_string_lossy().to_string();
+        let is_dir = entry.file_type()?.is_dir();
+
+        tree.push_str(&format!("{}{}{}{}\n", prefix, branch, name, if is_dir { "/" } else { "" }));
+
+        if is_dir && depth < max_depth {
+            let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            render_tree(root, &entry.path(), ignore, &child_prefix, depth + 1, max_depth, tree)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tool_box::context::ToolContext;
+
+    fn fixture() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/nested/deeper")).unwrap();
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::create_dir_all(root.join("logs")).unwrap();
+        fs::write(root.join("Cargo.toml"), "").unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+        fs::write(root.join("src/nested/mod.rs"), "").unwrap();
+        fs::write(root.join("src/nested/deeper/hidden.rs"), "").unwrap();
+        fs::write(root.join("target/debug/app"), "").unwrap();
+        fs::write(root.join("logs/today.log"), "").unwrap();
+        fs::write(root.join(".codrignore"), "logs\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_project_replace() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() { old_name(); old_name(); }").unwrap();
+        fs::write(root.join("src/nested/lib.rs"), "pub fn old_name() {}").unwrap();
+        fs::write(root.join("src/other.rs"), "fn unrelated() {}").unwrap();
+        fs::write(root.join("notes.txt"), "call old_name").unwrap();
+        fs::write(root.join("target/gen.rs"), "old_name").unwrap();
+        let ctx = ToolContext::new(root);
+
+        let result = new_project_replace_tool().run(&ctx, serde_json::json!({
+            "pattern": "old_name",
+            "replacement": "new_name",
+            "glob": "*.rs"
+        })).unwrap();
+
+        assert_eq!(result["total"], 3);
+        assert_eq!(result["files"], serde_json::json!([
+            {"file": "src/main.rs", "replacements": 2},
+            {"file": "src/nested/lib.rs", "replacements": 1}
+        ]));
+        assert_eq!(fs::read_to_string(root.join("src/main.rs")).unwrap(), "fn main() { new_name(); new_name(); }");
+        assert_eq!(fs::read_to_string(root.join("src/nested/lib.rs")).unwrap(), "pub fn new_name() {}");
+        assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "call old_name");
+        assert_eq!(fs::read_to_string(root.join("target/gen.rs")).unwrap(), "old_name");
+
+        let result = new_project_replace_tool().run(&ctx, serde_json::json!({
+            "pattern": r"fn (\w+)\(\)",
+            "replacement": "fn ${1}_v2()",
+            "regex": true
+        })).unwrap();
+        assert_eq!(result["total"], 3);
+        assert_eq!(fs::read_to_string(root.join("src/other.rs")).unwrap(), "fn unrelated_v2() {}");
+    }
+
+    #[test]
+    fn test_project_replace_declined() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.rs"), "old").unwrap();
+        let mut ctx = ToolContext::new(dir.path());
+        ctx.confirm = Some(std::sync::Arc::new(|_: &str| false));
+
+        let result = new_project_replace_tool()
+            .run(&ctx, serde_json::json!({"pattern": "old", "replacement": "new"}))
+            .unwrap();
+
+        assert_eq!(result["status"], "error");
+        assert_eq!(fs::read_to_string(dir.path().join("a.rs")).unwrap(), "old");
+    }
+
+    #[test]
+    fn test_overview_respects_ignores_and_depth() {
+        let dir = fixture();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_project_overview_tool()
+            .run(&ctx, serde_json::json!({"max_depth": 2}))
+            .unwrap();
+        let tree = result["tree"].as_str().unwrap();
+
+        assert!(tree.contains("├── src/"));
+        assert!(tree.contains("main.rs"));
+        assert!(tree.contains("nested/"));
+        assert!(tree.contains("Cargo.toml"));
+        assert!(!tree.contains("target"));
+        assert!(!tree.contains("logs"));
+        // depth 2 lists the contents of src/, but not of src/nested/
+        assert!(!tree.contains("mod.rs"));
+        assert!(!tree.contains("hidden.rs"));
+    }
+
+    #[test]
+    fn test_overview_extra_ignores() {
+        let dir = fixture();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_project_overview_tool()
+            .run(&ctx, serde_json::json!({"ignore": ["*.toml"]}))
+            .unwrap();
+        let tree = result["tree"].as_str().unwrap();
+
+        assert!(!tree.contains("Cargo.toml"));
+        assert!(tree.contains("mod.rs"));
+    }
+
+    #[test]
+    fn test_workspace_info() {
+        let dir = fixture();
+        fs::create_dir_all(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join(".git/HEAD"), "ref: refs/heads/feature/login\n").unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_workspace_info_tool().run(&ctx, serde_json::json!({})).unwrap();
+
+        assert_eq!(result["root"], ctx.root.to_string_lossy().as_ref());
+        assert_eq!(result["git_branch"], "feature/login");
+        assert_eq!(result["os"], std::env::consts::OS);
+        // .git, target/ and logs/ are ignored
+        assert_eq!(result["top_level"], serde_json::json!([".codrignore", "Cargo.toml", "src/"]));
+        assert_eq!(result["truncated"], false);
+
+        fs::write(dir.path().join(".git/HEAD"), "3f786850e387550fdab836ed7e6dc881de23001b\n").unwrap();
+        let result = new_workspace_info_tool().run(&ctx, serde_json::json!({})).unwrap();
+        assert_eq!(result["git_branch"], "detached at 3f78685");
+    }
+
+    #[test]
+    fn test_summarize_rust_file() {
+        let dir = fixture();
+        fs::write(dir.path().join("src/parser.rs"), r#"use std::fmt;
+
+/// Parses input. Callers use fn parse_all for batches.
+pub struct Parser {
+    input: String,
+}
+
+pub enum Token {
+    Word(String),
+}
+
+impl Parser {
+    pub fn new(input: &str) -> Self {
+        Parser { input: input.to_string() }
+    }
+
+    pub(crate) async fn next_token(&mut self) -> Option<Token> {
+        None
+    }
+}
+
+const MAX_TOKENS: usize = 10;
+
+fn helper() {}
+"#).unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_summarize_file_tool().run(&ctx, serde_json::json!({"file_path": "src/parser.rs"})).unwrap();
+
+        let landmarks: Vec<(u64, &str)> = result["landmarks"].as_array().unwrap().iter()
+            .map(|landmark| (landmark["line"].as_u64().unwrap(), landmark["text"].as_str().unwrap()))
+            .collect();
+        assert_eq!(landmarks, vec![
+            (4, "pub struct Parser"),
+            (8, "pub enum Token"),
+            (13, "pub fn new(input: &str) -> Self"),
+            (17, "pub(crate) async fn next_token(&mut self) -> Option<Token>"),
+            (22, "const MAX_TOKENS: usize = 10;"),
+            (24, "fn helper() {}"),
+        ]);
+        assert_eq!(result["lines"], 24);
+    }
+
+    #[test]
+    fn test_summarize_markdown_file() {
+        let dir = fixture();
+        fs::write(dir.path().join("README.md"), "# Title\n\nText\n\n```bash\n# not a heading\n```\n\n## Usage\n").unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_summarize_file_tool().run(&ctx, serde_json::json!({"file_path": "README.md"})).unwrap();
+        assert_eq!(result["landmarks"], serde_json::json!([{"line": 1, "text": "# Title"}, {"line": 9, "text": "## Usage"}]));
+
+        let result = new_summarize_file_tool().run(&ctx, serde_json::json!({"file_path": "Cargo.toml"})).unwrap();
+        assert_eq!(result["status"], "error");
+    }
+
+    #[test]
+    fn test_code_stats() {
+        let dir = fixture();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {\n    run();\n}\n").unwrap();
+        fs::write(dir.path().join("src/nested/mod.rs"), "pub fn run() {}").unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"app\"\n").unwrap();
+        fs::write(dir.path().join("README"), "").unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_code_stats_tool().run(&ctx, serde_json::json!({})).unwrap();
+
+        // target/ and logs/ are ignored, README and .codrignore have no extension
+        assert_eq!(result["by_extension"], serde_json::json!({
+            "(none)": {"files": 2, "lines": 1},
+            "rs": {"files": 3, "lines": 4},
+            "toml": {"files": 1, "lines": 2}
+        }));
+        assert_eq!(result["total_files"], 6);
+        assert_eq!(result["total_lines"], 7);
+
+        let result = new_code_stats_tool().run(&ctx, serde_json::json!({"path": "src", "max_depth": 1})).unwrap();
+        assert_eq!(result["by_extension"], serde_json::json!({"rs": {"files": 1, "lines": 3}}));
+    }
+
+    #[test]
+    fn test_find_definition() {
+        let dir = fixture();
+        fs::write(dir.path().join("src/main.rs"), "use crate::nested::Parser;\n\nfn main() {\n    Parser::new();\n}\n").unwrap();
+        fs::write(dir.path().join("src/nested/mod.rs"), "pub struct Parser {\n    input: String,\n}\n\nimpl Parser {\n    pub fn new() -> Self { todo!() }\n}\n").unwrap();
+        fs::write(dir.path().join("target/debug/main.rs"), "fn main() {}\n").unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_find_definition_tool()
+            .run(&ctx, serde_json::json!({"symbol": "main"}))
+            .unwrap();
+        assert_eq!(result["definitions"], serde_json::json!([
+            {"file": "src/main.rs", "line": 3, "text": "fn main() {"}
+        ]));
+
+        let result = new_find_definition_tool()
+            .run(&ctx, serde_json::json!({"symbol": "Parser"}))
+            .unwrap();
+        assert_eq!(result["definitions"], serde_json::json!([
+            {"file": "src/nested/mod.rs", "line": 1, "text": "pub struct Parser {"}
+        ]));
+    }
+}
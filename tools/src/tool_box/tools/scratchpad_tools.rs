@@ -0,0 +1,128 @@
+use std::{fs, io::Write};
+use crate::tool_box::{err, status_success, tools::Tool};
+
+pub fn new_scratchpad_tool() -> Tool {
+    Tool {
+        name: "scratchpad".to_string(),
+        description: "A private notes file for this session. Use it to write down a plan, findings or TODOs and read them back in later turns. Actions: append (add a note), read (get all notes), clear (remove all notes)".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "action": {
+                    "type": "string",
+                    "enum": ["append", "read", "clear"],
+                    "description": "What to do with the scratchpad"
+                },
+                "content": {
+                    "type": "string",
+                    "description": "Note to append (required for append)"
+                }
+            },
+            "required": ["action"]
+        }),
+        runner: |ctx, args| {
+            let action = args["action"].as_str().ok_or("action is required")?;
+            let path = ctx.scratchpad_path();
+
+            match action {
+                "append" => {
+                    let content = args["content"].as_str().ok_or("content is required")?;
+
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+
+                    let mut file = fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)?;
+
+                    writeln!(file, "{}", content)?;
+                    status_success()
+                }
+                "read" => {
+                    let notes = match fs::read_to_string(&path) {
+                        Ok(notes) => notes,
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                        Err(e) => return Err(e.into()),
+                    };
+                    Ok(serde_json::json!({"content": notes}))
+                }
+                "clear" => {
+                    if path.exists() {
+                        fs::remove_file(&path)?;
+                    }
+                    status_success()
+                }
+                other => err(&format!("Unknown action '{}', expected append, read or clear", other)),
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tool_box::context::ToolContext;
+
+    fn run(ctx: &ToolContext, args: serde_json::Value) -> serde_json::Value {
+        new_scratchpad_tool().run(ctx, args).unwrap()
+    }
+
+    #[test]
+    fn test_append() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = run(&ctx, serde_json::json!({"action": "append", "content": "1. read main.rs"}));
+
+        assert_eq!(result["status"], "success");
+        assert!(ctx.scratchpad_path().starts_with(dir.path().join(".codr_scratchpad")));
+        assert_eq!(fs::read_to_string(ctx.scratchpad_path()).unwrap(), "1. read main.rs\n");
+    }
+
+    #[test]
+    fn test_read_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = run(&ctx, serde_json::json!({"action": "read"}));
+
+        assert_eq!(result["content"], "");
+    }
+
+    #[test]
+    fn test_read_after_append() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        run(&ctx, serde_json::json!({"action": "append", "content": "plan: refactor"}));
+        run(&ctx, serde_json::json!({"action": "append", "content": "done: step 1"}));
+        let result = run(&ctx, serde_json::json!({"action": "read"}));
+
+        assert_eq!(result["content"], "plan: refactor\ndone: step 1\n");
+    }
+
+    #[test]
+    fn test_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        run(&ctx, serde_json::json!({"action": "append", "content": "stale note"}));
+        let result = run(&ctx, serde_json::json!({"action": "clear"}));
+
+        assert_eq!(result["status"], "success");
+        assert_eq!(run(&ctx, serde_json::json!({"action": "read"}))["content"], "");
+    }
+
+    #[test]
+    fn test_sessions_are_isolated() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = ToolContext { session_id: "first".to_string(), ..ToolContext::new(dir.path()) };
+        let second = ToolContext { session_id: "second".to_string(), ..ToolContext::new(dir.path()) };
+
+        run(&first, serde_json::json!({"action": "append", "content": "only in first"}));
+
+        assert_eq!(run(&second, serde_json::json!({"action": "read"}))["content"], "");
+    }
+}
@@ -0,0 +1,97 @@
+use crate::tool_box::{err, snapshots::{self, MAX_SNAPSHOTS}, tools::Tool};
+
+pub fn new_snapshot_tool() -> Tool {
+    Tool {
+        name: "snapshot".to_string(),
+        description: format!("Saves a copy of every project file that is not ignored, to go back to with restore_snapshot if a large change goes wrong. Take one before changing many files. Only the last {} snapshots are kept. Returns the snapshot_id", MAX_SNAPSHOTS),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {}
+        }),
+        runner: |ctx, _args| {
+            let (id, files) = snapshots::create(&ctx.root)?;
+            Ok(serde_json::json!({"status": "success", "snapshot_id": id, "files": files}))
+        },
+    }
+}
+
+pub fn new_restore_snapshot_tool() -> Tool {
+    Tool {
+        name: "restore_snapshot".to_string(),
+        description: "Puts the project back the way it was when a snapshot was taken: saved files are restored and files created since are deleted. The user may be asked to confirm".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "snapshot_id": {
+                    "type": "string",
+                    "description": "Id returned by the snapshot tool"
+                }
+            },
+            "required": ["snapshot_id"]
+        }),
+        runner: |ctx, args| {
+            let id = args["snapshot_id"].as_str().ok_or("snapshot_id is required")?;
+            if !snapshots::list(&ctx.root)?.iter().any(|existing| existing == id) {
+                return err(&format!("No snapshot {}", id));
+            }
+            if !ctx.confirm(&format!("Restore the project to {}, discarding the changes made since?", id)) {
+                return err("The user declined restoring the snapshot");
+            }
+
+            let (restored, removed) = snapshots::restore(&ctx.root, id)?;
+            Ok(serde_json::json!({"status": "success", "restored": restored, "removed": removed}))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tool_box::context::ToolContext;
+    use std::fs;
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(dir.path().join("README.md"), "# Demo").unwrap();
+        fs::create_dir_all(dir.path().join("target")).unwrap();
+        fs::write(dir.path().join("target/build.log"), "old build").unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let snapshot = new_snapshot_tool().run(&ctx, serde_json::json!({})).unwrap();
+        assert_eq!(snapshot["files"], 2);
+
+        fs::write(dir.path().join("src/main.rs"), "fn main() { broken( }").unwrap();
+        fs::remove_file(dir.path().join("README.md")).unwrap();
+        fs::write(dir.path().join("src/new.rs"), "pub fn new() {}").unwrap();
+        fs::write(dir.path().join("target/build.log"), "new build").unwrap();
+
+        let result = new_restore_snapshot_tool()
+            .run(&ctx, serde_json::json!({"snapshot_id": snapshot["snapshot_id"]}))
+            .unwrap();
+
+        assert_eq!(result["status"], "success");
+        assert_eq!(result["restored"], 2);
+        assert_eq!(result["removed"], 1);
+        assert_eq!(fs::read_to_string(dir.path().join("src/main.rs")).unwrap(), "fn main() {}");
+        assert_eq!(fs::read_to_string(dir.path().join("README.md")).unwrap(), "# Demo");
+        assert!(!dir.path().join("src/new.rs").exists());
+        // Ignored files are neither saved nor touched
+        assert_eq!(fs::read_to_string(dir.path().join("target/build.log")).unwrap(), "new build");
+    }
+
+    #[test]
+    fn test_restore_unknown_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_restore_snapshot_tool()
+            .run(&ctx, serde_json::json!({"snapshot_id": "../src"}))
+            .unwrap();
+
+        assert_eq!(result["status"], "error");
+        assert_eq!(result["message"], "No snapshot ../src");
+    }
+}
@@ -0,0 +1,53 @@
+use crate::tool_box::{tools::Tool, err};
+
+pub fn new_ask_user_tool() -> Tool {
+    Tool {
+        name: "ask_user".to_string(),
+        description: "Asks the user a question and waits for their answer. Use it when the request is ambiguous or a decision needs their input, rather than guessing".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {
+                "question": {
+                    "type": "string",
+                    "description": "The question to ask, phrased so it can be answered briefly"
+                }
+            },
+            "required": ["question"]
+        }),
+        runner: |ctx, args| {
+            let question = args["question"].as_str().ok_or("question is required")?;
+
+            match &ctx.user_input {
+                Some(ask) => Ok(serde_json::json!({"answer": ask(question)})),
+                None => err("No user is available to answer questions; proceed with your best judgement"),
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::tool_box::context::ToolContext;
+
+    #[test]
+    fn test_ask_user() {
+        let mut ctx = ToolContext::new(".");
+        ctx.user_input = Some(Arc::new(|question: &str| format!("You asked: {}", question)));
+
+        let result = new_ask_user_tool().run(&ctx, serde_json::json!({"question": "Which file?"})).unwrap();
+
+        assert_eq!(result["answer"], "You asked: Which file?");
+    }
+
+    #[test]
+    fn test_ask_user_without_handler() {
+        let result = new_ask_user_tool()
+            .run(&ToolContext::new("."), serde_json::json!({"question": "Which file?"}))
+            .unwrap();
+
+        assert_eq!(result["status"], "error");
+    }
+}
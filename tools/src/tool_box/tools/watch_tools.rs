@@ -0,0 +1,52 @@
+use crate::tool_box::{tools::Tool, err};
+
+pub fn new_get_file_changes_tool() -> Tool {
+    Tool {
+        name: "get_file_changes".to_string(),
+        description: "Lists the project files created, modified or deleted since the last call, e.g. by the user editing alongside you. Read a changed file again before editing it".to_string(),
+        parameters: serde_json::json!({
+            "type": "object",
+            "properties": {}
+        }),
+        runner: |ctx, _args| {
+            if !ctx.file_changes.is_watching() {
+                return err("File changes are not being watched in this session");
+            }
+
+            let changed: Vec<_> = ctx.file_changes.take().iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect();
+            Ok(serde_json::json!({"changed_files": changed}))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, thread, time::{Duration, Instant}};
+    use crate::tool_box::context::ToolContext;
+
+    #[test]
+    fn test_get_file_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("notes.md"), "draft").unwrap();
+        let ctx = ToolContext::new(dir.path());
+
+        let result = new_get_file_changes_tool().run(&ctx, serde_json::json!({})).unwrap();
+        assert_eq!(result["status"], "error");
+
+        ctx.file_changes.watch(dir.path()).unwrap();
+        fs::write(dir.path().join("notes.md"), "edited by the user").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut changed = Vec::new();
+        while changed.is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+            let result = new_get_file_changes_tool().run(&ctx, serde_json::json!({})).unwrap();
+            changed = result["changed_files"].as_array().unwrap().clone();
+        }
+
+        assert_eq!(changed, vec![serde_json::json!("notes.md")]);
+    }
+}